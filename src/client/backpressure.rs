@@ -0,0 +1,363 @@
+//! Backpressure handling between the client event loop and the consumer of
+//! [`HomieClientEvent`]s.
+//!
+//! With [`BackpressurePolicy::Block`] the event loop awaits room in the
+//! bounded event channel. A slow consumer then stalls polling of the MQTT
+//! connection, keep-alive pings stop and the broker eventually drops the
+//! connection. The other policies never block the event loop: events are
+//! staged in a bounded queue that a forwarding task drains into the event
+//! channel, and once the consumer falls behind, events are dropped or
+//! coalesced instead. Dropped and coalesced events are counted in
+//! [`ClientStats`].
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use homie5::Homie5Message;
+use tokio::sync::Notify;
+
+use super::hub::EventSink;
+use super::{ClientStats, HomieClientError, HomieClientEvent};
+
+/// What the client event loop does when the event consumer falls behind.
+///
+/// Dropped and coalesced events are counted in
+/// [`ClientStatsSnapshot::dropped_events`](super::ClientStatsSnapshot::dropped_events)
+/// and [`coalesced_events`](super::ClientStatsSnapshot::coalesced_events).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Wait for the consumer. No event is lost, but a slow consumer stalls
+    /// the MQTT connection until the broker drops it for missed keep-alives.
+    #[default]
+    Block,
+    /// Keep up to `channel_size` further events queued, then drop the
+    /// oldest queued message. Connection events (`Connect`, `Disconnect`,
+    /// `Error`, ...) are only dropped when the queue holds nothing else,
+    /// oldest first, so the queue never exceeds `channel_size`.
+    DropOldest,
+    /// Like [`DropOldest`](Self::DropOldest), but a queued property value
+    /// that was delivered with the retain flag (i.e. from the broker's
+    /// retained store) is replaced by newer values of the same property
+    /// while it waits in the queue, so the consumer only sees the latest
+    /// state.
+    CoalesceRetained,
+}
+
+/// Sending half of the client event channel, applying a
+/// [`BackpressurePolicy`].
+pub(crate) enum EventSender<E> {
+    Block(EventSink<E>),
+    Queued(Arc<EventQueue<E>>),
+}
+
+impl<E: std::fmt::Debug + Send + 'static> EventSender<E> {
+    pub fn new(
+        sink: EventSink<E>,
+        policy: BackpressurePolicy,
+        capacity: usize,
+        stats: ClientStats,
+    ) -> Self {
+        if policy == BackpressurePolicy::Block {
+            return Self::Block(sink);
+        }
+        let queue = Arc::new(EventQueue {
+            state: Mutex::new(QueueState::default()),
+            notify: Notify::new(),
+            sink,
+            policy,
+            capacity: capacity.max(1),
+            stats,
+        });
+        tokio::spawn(Arc::clone(&queue).forward());
+        Self::Queued(queue)
+    }
+
+    pub async fn send(&self, event: HomieClientEvent<E>) -> Result<(), HomieClientError> {
+        match self {
+            Self::Block(sink) => sink.send(event).await,
+            Self::Queued(queue) => queue.push(event, None),
+        }
+    }
+
+    /// Sends an event parsed from a message on `topic`.
+    pub async fn send_message(
+        &self,
+        event: HomieClientEvent<E>,
+        topic: &str,
+        retain: bool,
+    ) -> Result<(), HomieClientError> {
+        match self {
+            Self::Block(sink) => sink.send(event).await,
+            Self::Queued(queue) => queue.push(event, Some((topic, retain))),
+        }
+    }
+}
+
+impl<E> Drop for EventSender<E> {
+    fn drop(&mut self) {
+        if let Self::Queued(queue) = self {
+            queue.state().closed = true;
+            queue.notify.notify_one();
+        }
+    }
+}
+
+struct QueuedEvent<E> {
+    event: HomieClientEvent<E>,
+    /// Topic of a coalescable property value.
+    coalesce_topic: Option<String>,
+}
+
+struct QueueState<E> {
+    events: VecDeque<QueuedEvent<E>>,
+    /// Coalesce topics of the queued events.
+    retained_topics: HashSet<String>,
+    closed: bool,
+}
+
+impl<E> QueueState<E> {
+    fn pop_front(&mut self) -> Option<QueuedEvent<E>> {
+        let queued = self.events.pop_front()?;
+        self.forget(&queued);
+        Some(queued)
+    }
+
+    fn remove(&mut self, index: usize) {
+        if let Some(queued) = self.events.remove(index) {
+            self.forget(&queued);
+        }
+    }
+
+    fn forget(&mut self, queued: &QueuedEvent<E>) {
+        if let Some(topic) = &queued.coalesce_topic {
+            self.retained_topics.remove(topic);
+        }
+    }
+}
+
+impl<E> Default for QueueState<E> {
+    fn default() -> Self {
+        Self {
+            events: VecDeque::new(),
+            retained_topics: HashSet::new(),
+            closed: false,
+        }
+    }
+}
+
+pub(crate) struct EventQueue<E> {
+    state: Mutex<QueueState<E>>,
+    notify: Notify,
+    sink: EventSink<E>,
+    policy: BackpressurePolicy,
+    capacity: usize,
+    stats: ClientStats,
+}
+
+impl<E> EventQueue<E> {
+    fn state(&self) -> MutexGuard<'_, QueueState<E>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<E: std::fmt::Debug> EventQueue<E> {
+    fn push(
+        &self,
+        event: HomieClientEvent<E>,
+        message: Option<(&str, bool)>,
+    ) -> Result<(), HomieClientError> {
+        if self.sink.is_closed() {
+            return Err(HomieClientError::ChannelClosed);
+        }
+        let mut state = self.state();
+        let coalesce_topic = match message {
+            Some((topic, retain)) if self.policy == BackpressurePolicy::CoalesceRetained => {
+                let is_value = matches!(
+                    event,
+                    HomieClientEvent::HomieMessage {
+                        message: Homie5Message::PropertyValue { .. },
+                        ..
+                    }
+                );
+                (is_value && (retain || state.retained_topics.contains(topic)))
+                    .then(|| topic.to_string())
+            }
+            _ => None,
+        };
+
+        if let Some(topic) = &coalesce_topic {
+            if let Some(queued) = state
+                .events
+                .iter_mut()
+                .rev()
+                .find(|queued| queued.coalesce_topic.as_ref() == Some(topic))
+            {
+                queued.event = event;
+                self.stats.record_coalesced_event();
+                return Ok(());
+            }
+        }
+
+        if state.events.len() >= self.capacity {
+            self.stats.record_dropped_event();
+            let oldest_message = state
+                .events
+                .iter()
+                .position(|queued| is_message(&queued.event));
+            match oldest_message {
+                Some(index) => state.remove(index),
+                // Only connection events are queued: a message is the
+                // oldest one itself, otherwise the oldest event goes.
+                None if is_message(&event) => return Ok(()),
+                None => state.remove(0),
+            }
+        }
+        if let Some(topic) = &coalesce_topic {
+            state.retained_topics.insert(topic.clone());
+        }
+        state.events.push_back(QueuedEvent {
+            event,
+            coalesce_topic,
+        });
+        drop(state);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Forwards queued events to the event channel until the sender is
+    /// dropped and the queue is empty, or the receiver is gone.
+    async fn forward(self: Arc<Self>) {
+        loop {
+            let next = {
+                let mut state = self.state();
+                match state.pop_front() {
+                    Some(queued) => Some(queued.event),
+                    None if state.closed => return,
+                    None => None,
+                }
+            };
+            match next {
+                Some(event) => {
+                    if self.sink.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                None => self.notify.notified().await,
+            }
+        }
+    }
+}
+
+fn is_message<E>(event: &HomieClientEvent<E>) -> bool {
+    match event {
+        HomieClientEvent::HomieMessage { .. } | HomieClientEvent::Raw { .. } => true,
+        #[cfg(feature = "ext-meta")]
+        HomieClientEvent::MetaMessage(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use homie5::{HomieDomain, HomieID, PropertyRef};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::client::{EventHub, PendingPublishTracker};
+
+    fn sender(
+        policy: BackpressurePolicy,
+    ) -> (
+        EventSender<String>,
+        mpsc::Receiver<HomieClientEvent<String>>,
+        ClientStats,
+    ) {
+        let (sender, receiver) = mpsc::channel(1);
+        let stats = ClientStats::new(PendingPublishTracker::new().1);
+        let events = EventSender::new(
+            EventSink::new(sender, EventHub::new(1)),
+            policy,
+            4,
+            stats.clone(),
+        );
+        (events, receiver, stats)
+    }
+
+    fn queue<E>(events: &EventSender<E>) -> &EventQueue<E> {
+        match events {
+            EventSender::Queued(queue) => queue,
+            EventSender::Block(_) => panic!("expected a queued sender"),
+        }
+    }
+
+    fn value(value: &str, retain: bool) -> HomieClientEvent<String> {
+        HomieClientEvent::HomieMessage {
+            message: Homie5Message::PropertyValue {
+                property: PropertyRef::new(
+                    HomieDomain::Default,
+                    HomieID::new_const("dev"),
+                    HomieID::new_const("node"),
+                    HomieID::new_const("prop"),
+                ),
+                value: value.to_string(),
+            },
+            retain,
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_events_are_capped_too() {
+        let (events, mut receiver, stats) = sender(BackpressurePolicy::DropOldest);
+        for _ in 0..100 {
+            events.send(HomieClientEvent::Disconnect).await.unwrap();
+        }
+        assert!(queue(&events).state().events.len() <= 4);
+        assert!(stats.snapshot().dropped_events >= 94);
+        drop(events);
+
+        let mut received = 0;
+        while receiver.recv().await.is_some() {
+            received += 1;
+        }
+        assert_eq!(received + stats.snapshot().dropped_events, 100);
+    }
+
+    #[tokio::test]
+    async fn values_coalesce_while_the_retained_value_is_queued() {
+        const TOPIC: &str = "homie/5/dev/node/prop";
+        let (events, mut receiver, stats) = sender(BackpressurePolicy::CoalesceRetained);
+        // The forwarding task only runs once this task yields.
+        events
+            .send_message(value("0", true), TOPIC, true)
+            .await
+            .unwrap();
+        for i in 1..=3 {
+            events
+                .send_message(value(&i.to_string(), false), TOPIC, false)
+                .await
+                .unwrap();
+        }
+        assert_eq!(stats.snapshot().coalesced_events, 3);
+        assert_eq!(queue(&events).state().events.len(), 1);
+
+        assert!(matches!(
+            receiver.recv().await,
+            Some(HomieClientEvent::HomieMessage {
+                message: Homie5Message::PropertyValue { value, .. },
+                ..
+            })
+                if value == "3"
+        ));
+        // Delivered: the topic is forgotten and later values queue up.
+        assert!(queue(&events).state().retained_topics.is_empty());
+        events
+            .send_message(value("4", false), TOPIC, false)
+            .await
+            .unwrap();
+        events
+            .send_message(value("5", false), TOPIC, false)
+            .await
+            .unwrap();
+        assert_eq!(stats.snapshot().coalesced_events, 3);
+    }
+}
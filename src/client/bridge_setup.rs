@@ -1,8 +1,10 @@
 use homie5::{Homie5DeviceProtocol, HomieDomain, HomieID};
-use tokio::sync::mpsc::Receiver;
 
 use super::{
-    HomieClientError, HomieClientEvent, HomieClientHandle, HomieMQTTClient, MqttClientConfig,
+    run_homie_client_with_endpoints, BackpressurePolicy, ErrorClassifier, HomieClientError,
+    HomieClientOptions, HomieClientParts, MqttClient, MqttClientConfig, MqttConnectionError,
+    MqttEndpointOptions, OfflineBufferPolicy, ReconnectPolicy, SharedCredentialProvider,
+    TrafficRecorder,
 };
 
 /// Result of preparing a bridge MQTT setup.
@@ -11,9 +13,19 @@ use super::{
 /// ready to be launched with [`BridgeMqttSetup::run`].
 pub struct BridgeMqttSetup {
     pub homie_proto: Homie5DeviceProtocol,
-    pub mqtt_options: rumqttc::MqttOptions,
+    /// Options of every broker, in failover order, in the protocol version
+    /// selected by the config.
+    pub mqtt_options: MqttEndpointOptions,
     pub mqtt_channel_size: usize,
     pub max_disconnect: Option<std::time::Duration>,
+    pub reconnect_policy: ReconnectPolicy,
+    pub offline_buffer: Option<OfflineBufferPolicy>,
+    pub max_packet_size_outgoing: usize,
+    pub raw_topic_filters: Vec<String>,
+    pub backpressure: BackpressurePolicy,
+    pub credential_provider: Option<SharedCredentialProvider>,
+    pub error_classifier: Option<ErrorClassifier>,
+    pub recorder: Option<TrafficRecorder>,
     #[cfg(feature = "ext-meta")]
     pub meta_provider: homie5::extensions::meta::MetaProviderProtocol,
 }
@@ -32,7 +44,10 @@ impl MqttClientConfig {
         let (homie_proto, last_will) =
             Homie5DeviceProtocol::new(controller_id.clone(), domain.clone());
 
-        let mqtt_options = self.clone().last_will(Some(last_will)).to_mqtt_options()?;
+        let mqtt_options = self
+            .clone()
+            .last_will(Some(last_will))
+            .to_endpoint_options()?;
 
         #[cfg(feature = "ext-meta")]
         let meta_provider =
@@ -43,6 +58,14 @@ impl MqttClientConfig {
             mqtt_options,
             mqtt_channel_size: self.mqtt_channel_size,
             max_disconnect: self.max_disconnect,
+            reconnect_policy: self.reconnect_policy,
+            offline_buffer: self.offline_buffer,
+            max_packet_size_outgoing: self.max_packet_size_outgoing,
+            raw_topic_filters: self.raw_topic_filters,
+            backpressure: self.backpressure,
+            credential_provider: self.credential_provider,
+            error_classifier: self.error_classifier,
+            recorder: self.recorder,
             #[cfg(feature = "ext-meta")]
             meta_provider,
         })
@@ -55,18 +78,19 @@ impl BridgeMqttSetup {
     /// Returns the client handle, MQTT client wrapper, and event receiver.
     pub fn run(
         self,
-    ) -> Result<
-        (
-            HomieClientHandle,
-            HomieMQTTClient,
-            Receiver<HomieClientEvent>,
-        ),
-        HomieClientError,
-    > {
-        super::run_homie_client_with_options(
+    ) -> Result<HomieClientParts<MqttClient, MqttConnectionError>, HomieClientError> {
+        run_homie_client_with_endpoints(
             self.mqtt_options,
-            self.mqtt_channel_size,
-            self.max_disconnect,
+            HomieClientOptions::new(self.mqtt_channel_size)
+                .max_disconnect(self.max_disconnect)
+                .reconnect_policy(self.reconnect_policy)
+                .offline_buffer(self.offline_buffer)
+                .max_packet_size_outgoing(self.max_packet_size_outgoing)
+                .raw_topic_filters(self.raw_topic_filters)
+                .backpressure(self.backpressure)
+                .credential_provider(self.credential_provider)
+                .error_classifier(self.error_classifier)
+                .recorder(self.recorder),
         )
     }
 }
@@ -1,13 +1,19 @@
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
 
 use homie5::client::LastWill;
 use rand::{distr::Alphanumeric, rng, RngExt};
-use rumqttc::{ClientError, MqttOptions, Transport};
+use rumqttc::{ClientError, MqttOptions, TlsConfiguration, Transport};
 use thiserror::Error;
 use tokio::{sync::mpsc::error::SendError, task::JoinError};
 
-use super::{HomieClientEvent, HomieMQTTClient};
+use super::{
+    tls::TlsMaterial, BackpressurePolicy, BrokerEndpoint, ConnectionFailure, CredentialProvider,
+    ErrorClass, ErrorClassifier, HomieClientEvent, HomieClientOptions, HomieMQTTClient,
+    MqttEndpointOptions, MqttV5Client, OfflineBufferPolicy, ReconnectPolicy,
+    SharedCredentialProvider, TlsError, TlsSource, TrafficRecorder,
+};
 
 #[derive(Debug, Error)]
 pub enum HomieClientError {
@@ -18,18 +24,128 @@ pub enum HomieClientError {
     #[error("Hhomie client channel is closed. Error sending event via mpsc::channel.")]
     ChannelClosed,
     #[error("TLS configuration error: {0}")]
-    TlsConfig(String),
+    TlsConfig(#[from] TlsError),
+    #[error("MQTT protocol version mismatch: {0}")]
+    ProtocolVersion(String),
+    #[error("MQTT transport configuration error: {0}")]
+    TransportConfig(String),
+    #[error("Invalid broker endpoint '{0}', expected host, host:port or [ipv6]:port")]
+    InvalidEndpoint(String),
+    /// A connection error worth retrying; the client loop stopped because
+    /// the reconnect policy's `max_attempts` ran out. (Running out of
+    /// `max_disconnect` ends the loop with `Ok(())`.)
+    #[error("Recoverable connection error: {0}")]
+    RecoverableConnection(ConnectionFailure),
+    /// A connection error that retrying cannot fix; the client loop stopped.
+    #[error("Fatal connection error: {0}")]
+    FatalConnection(ConnectionFailure),
 }
-impl From<SendError<HomieClientEvent>> for HomieClientError {
-    fn from(_: SendError<HomieClientEvent>) -> Self {
+
+impl HomieClientError {
+    /// Typed error for a classified connection failure.
+    pub fn connection(failure: ConnectionFailure, class: ErrorClass) -> Self {
+        match class {
+            ErrorClass::Recoverable => Self::RecoverableConnection(failure),
+            ErrorClass::Fatal => Self::FatalConnection(failure),
+        }
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::FatalConnection(_))
+    }
+}
+impl<E> From<SendError<HomieClientEvent<E>>> for HomieClientError {
+    fn from(_: SendError<HomieClientEvent<E>>) -> Self {
         Self::ChannelClosed
     }
 }
 
+/// MQTT protocol version spoken by the client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MqttProtocolVersion {
+    /// MQTT 3.1.1, see [`MqttClientConfig::to_mqtt_options`].
+    #[default]
+    V311,
+    /// MQTT 5, see [`MqttClientConfig::to_mqtt_v5_options`].
+    ///
+    /// The session expiry is sent with CONNECT and the message expiry
+    /// attached to every non-retained publish, so stale `$target` or `set`
+    /// messages expire on the broker.
+    /// [`HomieMQTTClient::homie_publish_with_user_properties`] attaches user
+    /// properties, and CONNACK, SUBACK and DISCONNECT reason codes are
+    /// reported as [`HomieClientEvent::Reason`]. The 3.1.1-only
+    /// entry points ([`run_homie_client`](super::run_homie_client),
+    /// [`run_homie_client_with_failover`](super::run_homie_client_with_failover))
+    /// refuse such a config instead of silently downgrading.
+    V5,
+}
+
+/// Network transport used to reach the broker.
+///
+/// `use_tls` upgrades [`Tcp`](Self::Tcp) to [`Tls`](Self::Tls) and
+/// [`Ws`](Self::Ws) to [`Wss`](Self::Wss). The WebSocket transports require
+/// the `websocket` crate feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MqttTransportKind {
+    #[default]
+    Tcp,
+    Tls,
+    /// WebSocket (`ws://`).
+    Ws,
+    /// WebSocket over TLS (`wss://`).
+    Wss,
+}
+
+impl MqttTransportKind {
+    fn with_tls(self, use_tls: bool) -> Self {
+        match (self, use_tls) {
+            (Self::Tcp, true) => Self::Tls,
+            (Self::Ws, true) => Self::Wss,
+            (kind, _) => kind,
+        }
+    }
+
+    pub fn is_websocket(self) -> bool {
+        matches!(self, Self::Ws | Self::Wss)
+    }
+}
+
+impl FromStr for MqttTransportKind {
+    type Err = HomieClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "tcp" => Ok(Self::Tcp),
+            "tls" => Ok(Self::Tls),
+            "ws" => Ok(Self::Ws),
+            "wss" => Ok(Self::Wss),
+            other => Err(HomieClientError::TransportConfig(format!(
+                "unknown transport '{other}', expected tcp, tls, ws or wss"
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for MqttTransportKind {
+    type Error = HomieClientError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MqttClientConfig {
     pub hostname: String,
     pub port: u16,
+    /// Brokers to connect to, in failover order. Takes precedence over
+    /// `hostname` and `port` when not empty. Default: empty.
+    ///
+    /// A failed connection attempt moves on to the next broker, wrapping
+    /// around at the end of the list; a dropped connection is retried on the
+    /// same broker first. Requires a failover-capable entry point such as
+    /// [`run_homie_client_with_config`](super::run_homie_client_with_config).
+    pub endpoints: Vec<BrokerEndpoint>,
     pub username: String,
     pub password: String,
     pub client_id: Option<String>,
@@ -43,10 +159,63 @@ pub struct MqttClientConfig {
     pub ca_path: Option<PathBuf>,
     pub client_cert_path: Option<PathBuf>,
     pub client_key_path: Option<PathBuf>,
+    /// CA certificate(s) to trust. Takes precedence over `ca_path`.
+    /// Default: the platform certificate store.
+    pub ca: Option<TlsSource>,
+    /// Client certificate chain. Takes precedence over `client_cert_path`.
+    /// A [`TlsSource::Pkcs12`] bundle also provides the client key.
+    pub client_cert: Option<TlsSource>,
+    /// Client private key. Takes precedence over `client_key_path`.
+    pub client_key: Option<TlsSource>,
+    /// ALPN protocols offered during the TLS handshake.
+    pub alpn_protocols: Vec<Vec<u8>>,
+    /// Accept any broker certificate. For lab setups only: the connection
+    /// is encrypted but not authenticated, and a warning is logged.
+    pub tls_insecure_skip_verify: bool,
+    /// Network transport. Default: TCP (TLS when `use_tls` is set).
+    pub transport: MqttTransportKind,
+    /// WebSocket transports only: URL path of the MQTT endpoint.
+    /// Default: `/mqtt`.
+    pub ws_path: String,
+    /// WebSocket transports only: extra HTTP headers sent with the upgrade
+    /// request (e.g. for a reverse proxy).
+    pub ws_headers: Vec<(String, String)>,
     /// Maximum time the client will retry after disconnect before giving up.
-    /// When exceeded, `HomieClientEvent::Stop` is sent.
-    /// Default: `None` (retry forever).
+    /// When exceeded, `HomieClientEvent::Stop` is sent and the client task
+    /// ends with `Ok(())`. Default: `None` (retry forever).
     pub max_disconnect: Option<Duration>,
+    /// Delays between reconnect attempts after a connection error.
+    /// Default: fixed 5 seconds, retry forever.
+    pub reconnect_policy: ReconnectPolicy,
+    /// Buffer publishes while disconnected instead of queueing them in the
+    /// MQTT client. Default: `None`.
+    pub offline_buffer: Option<OfflineBufferPolicy>,
+    /// Protocol version to connect with. Default: MQTT 3.1.1.
+    pub protocol_version: MqttProtocolVersion,
+    /// MQTT 5 only: seconds the broker keeps the session after the
+    /// connection closed. Default: `None` (session ends with the connection).
+    pub session_expiry_interval: Option<u32>,
+    /// MQTT 5 only: seconds after which the broker drops undelivered
+    /// non-retained publishes (e.g. stale `$target` or `set` messages).
+    /// Default: `None` (never expire).
+    pub message_expiry_interval: Option<u32>,
+    /// Topic filters (e.g. vendor topics subscribed on the same connection)
+    /// whose non-Homie messages are passed on as `HomieClientEvent::Raw`.
+    /// Unparseable messages on other topics are logged as errors.
+    /// Default: empty.
+    pub raw_topic_filters: Vec<String>,
+    /// What the client does when the event consumer falls behind.
+    /// Default: [`BackpressurePolicy::Block`].
+    pub backpressure: BackpressurePolicy,
+    /// Asked for fresh credentials before every connection attempt,
+    /// overriding `username` and `password` (e.g. for short-lived tokens).
+    /// Default: `None`.
+    pub credential_provider: Option<SharedCredentialProvider>,
+    /// Decides which connection errors stop the client instead of being
+    /// retried. Default: `None` ([`ConnectionFailure::default_class`]).
+    pub error_classifier: Option<ErrorClassifier>,
+    /// Records all incoming publishes for later replay. Default: `None`.
+    pub recorder: Option<TrafficRecorder>,
 }
 
 impl MqttClientConfig {
@@ -57,6 +226,7 @@ impl MqttClientConfig {
         Self {
             hostname: hostname.into(),
             port: 1883,
+            endpoints: Vec::new(),
             username: String::new(),
             password: String::new(),
             client_id: None,
@@ -70,7 +240,25 @@ impl MqttClientConfig {
             ca_path: None,
             client_cert_path: None,
             client_key_path: None,
+            ca: None,
+            client_cert: None,
+            client_key: None,
+            alpn_protocols: Vec::new(),
+            tls_insecure_skip_verify: false,
+            transport: MqttTransportKind::default(),
+            ws_path: "/mqtt".to_string(),
+            ws_headers: Vec::new(),
             max_disconnect: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            offline_buffer: None,
+            protocol_version: MqttProtocolVersion::default(),
+            session_expiry_interval: None,
+            message_expiry_interval: None,
+            raw_topic_filters: Vec::new(),
+            backpressure: BackpressurePolicy::default(),
+            credential_provider: None,
+            error_classifier: None,
+            recorder: None,
         }
     }
 
@@ -84,6 +272,11 @@ impl MqttClientConfig {
         self
     }
 
+    pub fn endpoints(mut self, endpoints: Vec<BrokerEndpoint>) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
     pub fn username(mut self, username: impl Into<String>) -> Self {
         self.username = username.into();
         self
@@ -147,6 +340,48 @@ impl MqttClientConfig {
         self
     }
 
+    pub fn ca(mut self, ca: Option<TlsSource>) -> Self {
+        self.ca = ca;
+        self
+    }
+
+    pub fn client_cert(mut self, client_cert: Option<TlsSource>) -> Self {
+        self.client_cert = client_cert;
+        self
+    }
+
+    pub fn client_key(mut self, client_key: Option<TlsSource>) -> Self {
+        self.client_key = client_key;
+        self
+    }
+
+    pub fn alpn_protocols(mut self, alpn_protocols: Vec<Vec<u8>>) -> Self {
+        self.alpn_protocols = alpn_protocols;
+        self
+    }
+
+    pub fn tls_insecure_skip_verify(mut self, insecure_skip_verify: bool) -> Self {
+        self.tls_insecure_skip_verify = insecure_skip_verify;
+        self
+    }
+
+    pub fn transport(mut self, transport: MqttTransportKind) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn ws_path(mut self, ws_path: impl Into<String>) -> Self {
+        self.ws_path = ws_path.into();
+        self
+    }
+
+    /// Adds an HTTP header to the WebSocket upgrade request. Can be called
+    /// repeatedly.
+    pub fn ws_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.ws_headers.push((name.into(), value.into()));
+        self
+    }
+
     pub fn client_cert_path(mut self, client_cert_path: Option<impl Into<PathBuf>>) -> Self {
         self.client_cert_path = client_cert_path.map(|p| p.into());
         self
@@ -165,21 +400,111 @@ impl MqttClientConfig {
         self
     }
 
+    pub fn reconnect_policy(mut self, reconnect_policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = reconnect_policy;
+        self
+    }
+
+    pub fn offline_buffer(mut self, offline_buffer: Option<OfflineBufferPolicy>) -> Self {
+        self.offline_buffer = offline_buffer;
+        self
+    }
+
+    pub fn protocol_version(mut self, protocol_version: MqttProtocolVersion) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    pub fn session_expiry_interval(mut self, interval: Option<u32>) -> Self {
+        self.session_expiry_interval = interval;
+        self
+    }
+
+    pub fn message_expiry_interval(mut self, interval: Option<u32>) -> Self {
+        self.message_expiry_interval = interval;
+        self
+    }
+
+    /// Passes non-Homie messages on topics matching `filter` on as
+    /// `HomieClientEvent::Raw`. Can be called repeatedly.
+    pub fn raw_topic_filter(mut self, filter: impl Into<String>) -> Self {
+        self.raw_topic_filters.push(filter.into());
+        self
+    }
+
+    pub fn backpressure(mut self, backpressure: BackpressurePolicy) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    pub fn credential_provider(mut self, provider: impl CredentialProvider) -> Self {
+        self.credential_provider = Some(SharedCredentialProvider::new(provider));
+        self
+    }
+
+    pub fn error_classifier(
+        mut self,
+        classify: impl Fn(&ConnectionFailure) -> ErrorClass + Send + Sync + 'static,
+    ) -> Self {
+        self.error_classifier = Some(ErrorClassifier::new(classify));
+        self
+    }
+
+    pub fn recorder(mut self, recorder: TrafficRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Event-loop options (channel size, disconnect limit, reconnect policy,
+    /// offline buffer, backpressure, credential provider, error classifier,
+    /// recorder) for [`run_homie_client_with_client_options`](super::run_homie_client_with_client_options).
+    pub fn to_client_options(&self) -> HomieClientOptions {
+        HomieClientOptions::new(self.mqtt_channel_size)
+            .max_disconnect(self.max_disconnect)
+            .reconnect_policy(self.reconnect_policy.clone())
+            .offline_buffer(self.offline_buffer.clone())
+            .max_packet_size_outgoing(self.max_packet_size_outgoing)
+            .raw_topic_filters(self.raw_topic_filters.clone())
+            .backpressure(self.backpressure)
+            .credential_provider(self.credential_provider.clone())
+            .error_classifier(self.error_classifier.clone())
+            .recorder(self.recorder.clone())
+    }
+
+    /// MQTT 3.1.1 connection options.
+    ///
+    /// Fails when the config selects [`MqttProtocolVersion::V5`]; use
+    /// [`to_mqtt_v5_options`](Self::to_mqtt_v5_options) or
+    /// [`to_endpoint_options`](Self::to_endpoint_options) instead.
     pub fn to_mqtt_options(&self) -> Result<MqttOptions, HomieClientError> {
-        let client_id = if self.client_id.is_none() {
-            format!(
-                "homie5-{}",
-                rng()
-                    .sample_iter(&Alphanumeric)
-                    .take(12) // Leave space for a prefix if needed
-                    .map(char::from)
-                    .collect::<String>()
-            )
-        } else {
-            self.client_id.clone().unwrap()
-        };
-        let mut mqttoptions =
-            rumqttc::MqttOptions::new(client_id, self.hostname.to_owned(), self.port.to_owned());
+        let endpoint = &self.broker_endpoints()[0];
+        self.mqtt_options_for(endpoint, self.effective_client_id())
+    }
+
+    /// MQTT 3.1.1 connection options for each of
+    /// [`broker_endpoints`](Self::broker_endpoints), sharing one client id,
+    /// for [`run_homie_client_with_failover`](super::run_homie_client_with_failover).
+    pub fn to_failover_mqtt_options(&self) -> Result<Vec<MqttOptions>, HomieClientError> {
+        let client_id = self.effective_client_id();
+        self.broker_endpoints()
+            .iter()
+            .map(|endpoint| self.mqtt_options_for(endpoint, client_id.clone()))
+            .collect()
+    }
+
+    fn mqtt_options_for(
+        &self,
+        endpoint: &BrokerEndpoint,
+        client_id: String,
+    ) -> Result<MqttOptions, HomieClientError> {
+        if self.protocol_version != MqttProtocolVersion::V311 {
+            return Err(HomieClientError::ProtocolVersion(
+                "config selects MQTT 5, use to_endpoint_options / run_homie_client_with_config"
+                    .to_string(),
+            ));
+        }
+        let (broker_address, transport) = self.broker_transport(endpoint)?;
+        let mut mqttoptions = rumqttc::MqttOptions::new(client_id, broker_address, endpoint.port);
         if !self.username.is_empty() && !self.password.is_empty() {
             mqttoptions.set_credentials(self.username.to_owned(), self.password.to_owned());
         }
@@ -192,55 +517,197 @@ impl MqttClientConfig {
             mqttoptions.set_last_will(HomieMQTTClient::map_last_will(last_will.clone()));
         }
 
-        if self.use_tls {
-            let ca = match &self.ca_path {
-                Some(path) => std::fs::read(path).map_err(|e| {
-                    HomieClientError::TlsConfig(format!(
-                        "failed to read CA certificate '{}': {e}",
-                        path.display()
-                    ))
-                })?,
-                None => Vec::new(),
-            };
-
-            let client_auth = match (&self.client_cert_path, &self.client_key_path) {
-                (Some(cert_path), Some(key_path)) => {
-                    let cert = std::fs::read(cert_path).map_err(|e| {
-                        HomieClientError::TlsConfig(format!(
-                            "failed to read client certificate '{}': {e}",
-                            cert_path.display()
-                        ))
-                    })?;
-                    let key = std::fs::read(key_path).map_err(|e| {
-                        HomieClientError::TlsConfig(format!(
-                            "failed to read client key '{}': {e}",
-                            key_path.display()
-                        ))
-                    })?;
-                    Some((cert, key))
-                }
-                (Some(_), None) => {
-                    return Err(HomieClientError::TlsConfig(
-                        "client_cert_path requires client_key_path".to_string(),
-                    ));
-                }
-                (None, Some(_)) => {
-                    return Err(HomieClientError::TlsConfig(
-                        "client_key_path requires client_cert_path".to_string(),
-                    ));
-                }
-                (None, None) => None,
-            };
-
-            let transport = if ca.is_empty() {
-                Transport::tls_with_default_config()
-            } else {
-                Transport::tls(ca, client_auth, None)
-            };
-
-            mqttoptions.set_transport(transport);
+        mqttoptions.set_transport(transport);
+        #[cfg(feature = "websocket")]
+        if let Some(headers) = self.ws_header_map()? {
+            mqttoptions.set_request_modifier(add_ws_headers(headers));
+        }
+
+        Ok(mqttoptions)
+    }
+
+    /// MQTT 5 connection options, including the session expiry interval.
+    ///
+    /// The message expiry interval is applied per publish by
+    /// [`run_homie_client_v5`](super::run_homie_client_v5). MQTT 5 negotiates
+    /// the outgoing packet size limit with the broker, so only
+    /// `max_packet_size_incoming` is used.
+    pub fn to_mqtt_v5_options(&self) -> Result<rumqttc::v5::MqttOptions, HomieClientError> {
+        let endpoint = &self.broker_endpoints()[0];
+        self.mqtt_v5_options_for(endpoint, self.effective_client_id())
+    }
+
+    /// MQTT 5 counterpart of
+    /// [`to_failover_mqtt_options`](Self::to_failover_mqtt_options), for
+    /// [`run_homie_client_v5_with_failover`](super::run_homie_client_v5_with_failover).
+    pub fn to_failover_mqtt_v5_options(
+        &self,
+    ) -> Result<Vec<rumqttc::v5::MqttOptions>, HomieClientError> {
+        let client_id = self.effective_client_id();
+        self.broker_endpoints()
+            .iter()
+            .map(|endpoint| self.mqtt_v5_options_for(endpoint, client_id.clone()))
+            .collect()
+    }
+
+    /// Connection options of every broker endpoint in the configured
+    /// protocol version, for
+    /// [`run_homie_client_with_endpoints`](super::run_homie_client_with_endpoints).
+    pub fn to_endpoint_options(&self) -> Result<MqttEndpointOptions, HomieClientError> {
+        Ok(match self.protocol_version {
+            MqttProtocolVersion::V311 => {
+                MqttEndpointOptions::V311(self.to_failover_mqtt_options()?)
+            }
+            MqttProtocolVersion::V5 => MqttEndpointOptions::V5 {
+                endpoints: self.to_failover_mqtt_v5_options()?,
+                message_expiry_interval: self.message_expiry_interval,
+            },
+        })
+    }
+
+    fn mqtt_v5_options_for(
+        &self,
+        endpoint: &BrokerEndpoint,
+        client_id: String,
+    ) -> Result<rumqttc::v5::MqttOptions, HomieClientError> {
+        let (broker_address, transport) = self.broker_transport(endpoint)?;
+        let mut mqttoptions =
+            rumqttc::v5::MqttOptions::new(client_id, broker_address, endpoint.port);
+        if !self.username.is_empty() && !self.password.is_empty() {
+            mqttoptions.set_credentials(self.username.to_owned(), self.password.to_owned());
+        }
+        mqttoptions.set_keep_alive(Duration::from_secs(self.keep_alive));
+        mqttoptions.set_clean_start(self.clean_session);
+        mqttoptions.set_max_packet_size(Some(
+            u32::try_from(self.max_packet_size_incoming).unwrap_or(u32::MAX),
+        ));
+        mqttoptions.set_session_expiry_interval(self.session_expiry_interval);
+
+        if let Some(last_will) = &self.last_will {
+            mqttoptions.set_last_will(MqttV5Client::map_last_will(last_will.clone()));
+        }
+
+        mqttoptions.set_transport(transport);
+        #[cfg(feature = "websocket")]
+        if let Some(headers) = self.ws_header_map()? {
+            mqttoptions.set_request_modifier(add_ws_headers(headers));
         }
 
         Ok(mqttoptions)
     }
+
+    fn effective_client_id(&self) -> String {
+        match &self.client_id {
+            Some(client_id) => client_id.clone(),
+            None => format!(
+                "homie5-{}",
+                rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(12) // Leave space for a prefix if needed
+                    .map(char::from)
+                    .collect::<String>()
+            ),
+        }
+    }
+
+    /// `endpoints`, or `hostname` and `port` when there are none.
+    pub fn broker_endpoints(&self) -> Vec<BrokerEndpoint> {
+        if self.endpoints.is_empty() {
+            vec![BrokerEndpoint::new(&self.hostname, self.port)]
+        } else {
+            self.endpoints.clone()
+        }
+    }
+
+    /// The transport selected by `transport` and `use_tls`.
+    pub fn effective_transport(&self) -> MqttTransportKind {
+        self.transport.with_tls(self.use_tls)
+    }
+
+    /// Broker address as expected by `rumqttc` (the full URL for WebSocket
+    /// transports) and the matching transport.
+    fn broker_transport(
+        &self,
+        endpoint: &BrokerEndpoint,
+    ) -> Result<(String, Transport), HomieClientError> {
+        let kind = self.effective_transport();
+        let address = match kind {
+            MqttTransportKind::Tcp | MqttTransportKind::Tls => endpoint.host.clone(),
+            MqttTransportKind::Ws | MqttTransportKind::Wss => {
+                let scheme = if kind == MqttTransportKind::Ws {
+                    "ws"
+                } else {
+                    "wss"
+                };
+                let path = self.ws_path.trim_start_matches('/');
+                format!("{scheme}://{endpoint}/{path}")
+            }
+        };
+        let transport = match kind {
+            MqttTransportKind::Tcp => Transport::tcp(),
+            MqttTransportKind::Tls => Transport::tls_with_config(self.tls_configuration()?),
+            #[cfg(feature = "websocket")]
+            MqttTransportKind::Ws => Transport::ws(),
+            #[cfg(feature = "websocket")]
+            MqttTransportKind::Wss => Transport::wss_with_config(self.tls_configuration()?),
+            #[cfg(not(feature = "websocket"))]
+            MqttTransportKind::Ws | MqttTransportKind::Wss => {
+                return Err(HomieClientError::TransportConfig(
+                    "WebSocket transports require the `websocket` feature".to_string(),
+                ));
+            }
+        };
+        Ok((address, transport))
+    }
+
+    /// Validated WebSocket upgrade headers, `None` when there are none.
+    #[cfg(feature = "websocket")]
+    fn ws_header_map(&self) -> Result<Option<http::HeaderMap>, HomieClientError> {
+        if self.ws_headers.is_empty() || !self.effective_transport().is_websocket() {
+            return Ok(None);
+        }
+        let mut headers = http::HeaderMap::new();
+        for (name, value) in &self.ws_headers {
+            let name = http::HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+                HomieClientError::TransportConfig(format!("invalid header name '{name}': {e}"))
+            })?;
+            let value = http::HeaderValue::from_str(value).map_err(|e| {
+                HomieClientError::TransportConfig(format!("invalid value for header '{name}': {e}"))
+            })?;
+            headers.append(name, value);
+        }
+        Ok(Some(headers))
+    }
+
+    fn tls_configuration(&self) -> Result<TlsConfiguration, HomieClientError> {
+        // In-memory sources take precedence over the path fields.
+        let material = TlsMaterial {
+            ca: self
+                .ca
+                .clone()
+                .or_else(|| self.ca_path.clone().map(TlsSource::File)),
+            client_cert: self
+                .client_cert
+                .clone()
+                .or_else(|| self.client_cert_path.clone().map(TlsSource::File)),
+            client_key: self
+                .client_key
+                .clone()
+                .or_else(|| self.client_key_path.clone().map(TlsSource::File)),
+            alpn_protocols: &self.alpn_protocols,
+            insecure_skip_verify: self.tls_insecure_skip_verify,
+        };
+        Ok(material.to_tls_configuration()?)
+    }
+}
+
+/// Request modifier adding `headers` to the WebSocket upgrade request.
+#[cfg(feature = "websocket")]
+fn add_ws_headers(
+    headers: http::HeaderMap,
+) -> impl Fn(http::Request<()>) -> std::future::Ready<http::Request<()>> + Send + Sync + 'static {
+    move |mut request| {
+        request.headers_mut().extend(headers.clone());
+        std::future::ready(request)
+    }
 }
@@ -0,0 +1,89 @@
+//! Credentials fetched before every connection attempt.
+//!
+//! `MqttClientConfig::username`/`password` are fixed once the MQTT options
+//! are built. Brokers that authenticate with short-lived tokens (e.g. JWTs
+//! as password) need fresh credentials for every reconnect instead: the
+//! client event loop asks its [`CredentialProvider`] before each connection
+//! attempt and hands the result to the [`MqttEventLoop`](super::MqttEventLoop)
+//! via [`set_credentials`](super::MqttEventLoop::set_credentials). The
+//! client, its subscriptions and the event receiver stay untouched.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Username and password of an MQTT connection.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+/// Error of a [`CredentialProvider`].
+pub type CredentialError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Future returned by [`CredentialProvider::credentials`].
+pub type CredentialsFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Credentials, CredentialError>> + Send + 'a>>;
+
+/// Source of the credentials used for the next connection attempt, asked
+/// before every attempt instead of the static `username` and `password`.
+/// The client, its subscriptions and the event receiver stay the same
+/// across reconnects.
+///
+/// Implemented for async closures returning
+/// `Result<Credentials, CredentialError>`. If the provider fails, the error
+/// is logged and the attempt uses the previous credentials.
+pub trait CredentialProvider: Send + Sync + 'static {
+    fn credentials(&self) -> CredentialsFuture<'_>;
+}
+
+impl<F, Fut> CredentialProvider for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Credentials, CredentialError>> + Send + 'static,
+{
+    fn credentials(&self) -> CredentialsFuture<'_> {
+        Box::pin(self())
+    }
+}
+
+/// Shareable [`CredentialProvider`], as stored in the client config and
+/// options.
+#[derive(Clone)]
+pub struct SharedCredentialProvider(Arc<dyn CredentialProvider>);
+
+impl SharedCredentialProvider {
+    pub fn new(provider: impl CredentialProvider) -> Self {
+        Self(Arc::new(provider))
+    }
+
+    pub async fn credentials(&self) -> Result<Credentials, CredentialError> {
+        self.0.credentials().await
+    }
+}
+
+impl fmt::Debug for SharedCredentialProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedCredentialProvider(..)")
+    }
+}
@@ -0,0 +1,204 @@
+//! Broker endpoints and failover between several brokers.
+//!
+//! [`MqttClientConfig::endpoints`](super::MqttClientConfig::endpoints) holds
+//! an ordered list of brokers. [`run_homie_client_with_failover`](super::run_homie_client_with_failover)
+//! (and its MQTT 5 counterpart) connect to the first one and move on to the
+//! next endpoint whenever a connection attempt fails, wrapping around at the
+//! end of the list. An established connection that drops is retried on the
+//! same endpoint first.
+
+use rumqttc::{EventLoop, MqttOptions};
+
+use super::{ConnectionFailure, Credentials, HomieClientError, MqttEventLoop, TransportEvent};
+
+/// Host and port of an MQTT broker.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BrokerEndpoint {
+    pub host: String,
+    pub port: u16,
+}
+
+impl BrokerEndpoint {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+        }
+    }
+
+    /// Parses `host`, `host:port` or `[ipv6]:port`. `default_port` is used
+    /// when no port is given.
+    pub fn parse(s: &str, default_port: u16) -> Result<Self, HomieClientError> {
+        let s = s.trim();
+        let invalid = || HomieClientError::InvalidEndpoint(s.to_string());
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            match rest {
+                "" => (host, None),
+                _ => (host, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+            }
+        } else {
+            match s.split_once(':') {
+                // More than one colon: an unbracketed IPv6 address.
+                Some((host, port)) if !port.contains(':') => (host, Some(port)),
+                _ => (s, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid())?,
+            None => default_port,
+        };
+        Ok(Self::new(host, port))
+    }
+
+    /// Parses a comma-separated list of endpoints (see [`parse`](Self::parse)).
+    pub fn parse_list(s: &str, default_port: u16) -> Result<Vec<Self>, HomieClientError> {
+        s.split(',')
+            .map(|endpoint| Self::parse(endpoint, default_port))
+            .collect()
+    }
+
+    /// Endpoint of a `rumqttc` broker address, which is a URL for WebSocket
+    /// transports.
+    pub(crate) fn from_broker_address((address, port): (String, u16)) -> Self {
+        let host = match address.split_once("://") {
+            Some((_, rest)) => {
+                let authority = rest.split('/').next().unwrap_or_default();
+                match authority.rsplit_once(':') {
+                    Some((host, _)) => host.trim_start_matches('[').trim_end_matches(']'),
+                    None => authority,
+                }
+                .to_string()
+            }
+            None => address,
+        };
+        Self::new(host, port)
+    }
+}
+
+impl std::fmt::Display for BrokerEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Connection options per endpoint, in failover order.
+#[derive(Debug)]
+pub(crate) struct EndpointRotation<O> {
+    options: Vec<O>,
+    current: usize,
+}
+
+impl<O: Clone> EndpointRotation<O> {
+    /// The event loop is expected to start with the first entry.
+    pub fn new(options: Vec<O>) -> Self {
+        Self {
+            options,
+            current: 0,
+        }
+    }
+
+    /// Options of the next endpoint; `None` with fewer than two endpoints.
+    pub fn advance(&mut self) -> Option<O> {
+        if self.options.len() < 2 {
+            return None;
+        }
+        self.current = (self.current + 1) % self.options.len();
+        Some(self.options[self.current].clone())
+    }
+
+    /// Options of all endpoints, e.g. to update their credentials.
+    pub fn options_mut(&mut self) -> &mut [O] {
+        &mut self.options
+    }
+}
+
+/// `rumqttc` event loop that switches to the next broker after a failed
+/// connection attempt.
+pub struct FailoverEventLoop {
+    eventloop: EventLoop,
+    endpoints: EndpointRotation<MqttOptions>,
+}
+
+impl FailoverEventLoop {
+    /// `eventloop` must have been created with the first entry of
+    /// `endpoints`.
+    pub fn new(eventloop: EventLoop, endpoints: Vec<MqttOptions>) -> Self {
+        Self {
+            eventloop,
+            endpoints: EndpointRotation::new(endpoints),
+        }
+    }
+}
+
+impl MqttEventLoop for FailoverEventLoop {
+    type Error = rumqttc::ConnectionError;
+
+    fn poll(
+        &mut self,
+    ) -> impl std::future::Future<Output = Result<TransportEvent, Self::Error>> + Send {
+        MqttEventLoop::poll(&mut self.eventloop)
+    }
+
+    fn endpoint(&self) -> Option<BrokerEndpoint> {
+        self.eventloop.endpoint()
+    }
+
+    fn failover(&mut self) {
+        // rumqttc reads its options on every (re)connect.
+        if let Some(options) = self.endpoints.advance() {
+            self.eventloop.mqtt_options = options;
+        }
+    }
+
+    fn describe_error(&self, err: &Self::Error) -> ConnectionFailure {
+        self.eventloop.describe_error(err)
+    }
+
+    fn set_credentials(&mut self, credentials: &Credentials) {
+        self.eventloop.set_credentials(credentials);
+        for options in self.endpoints.options_mut() {
+            options.set_credentials(&credentials.username, &credentials.password);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BrokerEndpoint;
+
+    #[test]
+    fn parse_endpoints() {
+        assert_eq!(
+            BrokerEndpoint::parse_list("mqtt-a, mqtt-b:8883,[fd00::1]:1884,::1", 1883).unwrap(),
+            vec![
+                BrokerEndpoint::new("mqtt-a", 1883),
+                BrokerEndpoint::new("mqtt-b", 8883),
+                BrokerEndpoint::new("fd00::1", 1884),
+                BrokerEndpoint::new("::1", 1883),
+            ]
+        );
+        assert!(BrokerEndpoint::parse("mqtt-a:port", 1883).is_err());
+        assert!(BrokerEndpoint::parse(":1883", 1883).is_err());
+        assert!(BrokerEndpoint::parse_list("mqtt-a,,mqtt-b", 1883).is_err());
+    }
+
+    #[test]
+    fn endpoint_of_websocket_url() {
+        assert_eq!(
+            BrokerEndpoint::from_broker_address(("wss://[fd00::1]:443/mqtt".to_string(), 443)),
+            BrokerEndpoint::new("fd00::1", 443)
+        );
+        assert_eq!(
+            BrokerEndpoint::from_broker_address(("mqtt-a".to_string(), 1883)),
+            BrokerEndpoint::new("mqtt-a", 1883)
+        );
+    }
+}
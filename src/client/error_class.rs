@@ -0,0 +1,116 @@
+//! Classification of connection errors into recoverable and fatal ones.
+//!
+//! Most connection errors (network down, broker restarting, timeouts) go
+//! away by themselves, so the client loop retries them according to its
+//! [`ReconnectPolicy`](super::ReconnectPolicy). Some never will: a broker
+//! refusing the credentials or the client id answers every retry the same
+//! way. The [`MqttEventLoop`](super::MqttEventLoop) describes each error as
+//! a library-agnostic [`ConnectionFailure`]; an [`ErrorClassifier`] (the
+//! default one unless configured) decides whether it is fatal. A fatal
+//! error stops the client loop with
+//! [`HomieClientEvent::Fatal`](super::HomieClientEvent::Fatal).
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::ReasonCode;
+
+/// Whether retrying after a connection error can succeed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Reconnect according to the reconnect policy.
+    Recoverable,
+    /// Stop the client loop.
+    Fatal,
+}
+
+/// What went wrong, as far as the client loop needs to know.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionFailureKind {
+    /// The broker refused the connection with this CONNACK reason code
+    /// (MQTT 3.1.1 return codes are mapped to their MQTT 5 equivalents).
+    Refused(ReasonCode),
+    /// TLS handshake or configuration error.
+    Tls,
+    /// Network error, including a connection closed by the broker.
+    Io,
+    /// Connect, ping or flush timeout.
+    Timeout,
+    /// Anything else (protocol state errors, WebSocket errors, ...).
+    Other,
+}
+
+/// A connection error, described independently of the MQTT library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionFailure {
+    pub kind: ConnectionFailureKind,
+    /// The error message of the transport.
+    pub message: String,
+}
+
+impl ConnectionFailure {
+    pub fn new(kind: ConnectionFailureKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// The broker rejected the credentials (bad username or password, not
+    /// authorized, bad authentication method).
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(
+            self.kind,
+            ConnectionFailureKind::Refused(ReasonCode(0x86 | 0x87 | 0x8C))
+        )
+    }
+
+    /// Default classification: refusals that a retry with the same
+    /// connect options cannot fix are fatal (unsupported protocol version,
+    /// invalid client id, auth failures, banned). Everything else is
+    /// recoverable.
+    pub fn default_class(&self) -> ErrorClass {
+        match self.kind {
+            ConnectionFailureKind::Refused(ReasonCode(0x84 | 0x85 | 0x8A)) => ErrorClass::Fatal,
+            _ if self.is_auth_failure() => ErrorClass::Fatal,
+            _ => ErrorClass::Recoverable,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// App-provided classification of connection errors, overriding
+/// [`ConnectionFailure::default_class`].
+///
+/// A fatal error is reported as
+/// [`HomieClientEvent::Fatal`](super::HomieClientEvent::Fatal) followed by
+/// `Stop`, and the client task ends with
+/// [`HomieClientError::FatalConnection`](super::HomieClientError::FatalConnection).
+/// Without a classifier, refused credentials stay recoverable when a
+/// [`CredentialProvider`](super::CredentialProvider) is configured, since
+/// the next attempt uses fresh ones.
+#[derive(Clone)]
+pub struct ErrorClassifier(Arc<dyn Fn(&ConnectionFailure) -> ErrorClass + Send + Sync>);
+
+impl ErrorClassifier {
+    pub fn new(
+        classify: impl Fn(&ConnectionFailure) -> ErrorClass + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(classify))
+    }
+
+    pub fn classify(&self, failure: &ConnectionFailure) -> ErrorClass {
+        (self.0)(failure)
+    }
+}
+
+impl fmt::Debug for ErrorClassifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorClassifier(..)")
+    }
+}
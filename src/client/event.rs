@@ -1,13 +1,59 @@
-use homie5::Homie5Message;
+use std::time::Duration;
+
+use bytes::Bytes;
+use homie5::{client::QoS, Homie5Message};
 use rumqttc::ConnectionError;
 
-#[derive(Debug)]
-pub enum HomieClientEvent {
-    Connect,
+use super::{BrokerEndpoint, BrokerReason, ConnectionFailure};
+
+/// Event emitted by the homie client event loop.
+///
+/// `E` is the connection error type of the underlying
+/// [`MqttEventLoop`](super::MqttEventLoop) (`rumqttc::ConnectionError` by
+/// default).
+#[allow(clippy::large_enum_variant)] // Suppress the Clippy warning for large enum variants - most
+// events will be HomieMessage
+#[derive(Debug, Clone)]
+pub enum HomieClientEvent<E = ConnectionError> {
+    /// Connected to the broker at `endpoint` (`None` if the transport does
+    /// not know it).
+    Connect {
+        endpoint: Option<BrokerEndpoint>,
+    },
     Disconnect,
     Stop,
-    HomieMessage(Homie5Message),
+    /// A Homie message; `retain` is set for retained messages delivered on
+    /// subscribe.
+    HomieMessage {
+        message: Homie5Message,
+        retain: bool,
+    },
     #[cfg(feature = "ext-meta")]
     MetaMessage(homie5::extensions::meta::MetaMessage),
-    Error(ConnectionError),
+    /// A connection error; the client reconnects.
+    Error(E),
+    /// A connection error classified as fatal (see
+    /// [`ErrorClassifier`](super::ErrorClassifier)). `Stop` follows and
+    /// the client task ends with
+    /// [`HomieClientError::FatalConnection`](super::HomieClientError::FatalConnection).
+    Fatal(ConnectionFailure),
+    /// The connection attempt failed and the next one is scheduled after
+    /// `delay` (see [`ReconnectPolicy`](super::ReconnectPolicy)). `attempt`
+    /// counts consecutive failures, starting at 1.
+    ReconnectScheduled {
+        attempt: u32,
+        delay: Duration,
+    },
+    /// CONNACK, SUBACK or DISCONNECT reason codes reported by an MQTT 5
+    /// broker.
+    Reason(BrokerReason),
+    /// A message that is not a Homie message, on a topic matching one of
+    /// the configured raw topic filters
+    /// (see [`MqttClientConfig::raw_topic_filter`](super::MqttClientConfig::raw_topic_filter)).
+    Raw {
+        topic: String,
+        payload: Bytes,
+        retain: bool,
+        qos: QoS,
+    },
 }
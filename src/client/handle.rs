@@ -1,14 +1,20 @@
 use std::time::Duration;
 
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch};
 
-use super::{FlushTimeout, HomieClientError, PendingPublishObserver};
+use super::{
+    ClientStats, ClientStatsSnapshot, ConnectionStatus, EventFilter, EventHub, FlushTimeout,
+    HomieClientError, HubEvent, PendingPublishObserver,
+};
 
 pub struct HomieClientHandle {
     pub(super) stop_sender: watch::Sender<bool>, // Shutdown signal
     pub(super) handle: tokio::task::JoinHandle<Result<(), HomieClientError>>,
     /// Observes queued plus in-flight (unacknowledged) publishes.
     pub(super) pending_publishes: PendingPublishObserver,
+    pub(super) stats: ClientStats,
+    pub(super) hub: EventHub,
+    pub(super) status: watch::Receiver<ConnectionStatus>,
 }
 
 impl HomieClientHandle {
@@ -49,6 +55,39 @@ impl HomieClientHandle {
         self.pending_publishes.clone()
     }
 
+    /// Snapshot of the connection and traffic counters.
+    pub fn stats(&self) -> ClientStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Returns a shared handle to the counters, e.g. for a metrics exporter
+    /// that outlives borrowing the handle.
+    pub fn stats_handle(&self) -> ClientStats {
+        self.stats.clone()
+    }
+
+    /// Returns a receiver of the connection status, maintained by the
+    /// client task. Its `changed()` fails once the task ended.
+    pub fn connection_status(&self) -> watch::Receiver<ConnectionStatus> {
+        self.status.clone()
+    }
+
+    /// Subscribes to the client events matching `filter`, in addition to
+    /// the event receiver returned when starting the client. Connection
+    /// lifecycle events are delivered to every subscriber.
+    ///
+    /// Subscribe before issuing MQTT subscriptions to not miss retained
+    /// messages. See [`EventHub`] for the delivery semantics.
+    pub fn subscribe(&self, filter: EventFilter) -> mpsc::Receiver<HubEvent> {
+        self.hub.subscribe(filter)
+    }
+
+    /// Returns a shared handle to the subscription hub, e.g. to hand out
+    /// subscriptions from components that don't own the handle.
+    pub fn event_hub(&self) -> EventHub {
+        self.hub.clone()
+    }
+
     /// Waits until all publishes issued before this call have been
     /// acknowledged by the broker.
     ///
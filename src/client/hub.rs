@@ -0,0 +1,380 @@
+//! Topic-filtered fan-out of client events to several consumers.
+//!
+//! Every event the client loop emits is also offered to the subscribers of
+//! the [`EventHub`] (see [`HomieClientHandle::subscribe`](super::HomieClientHandle::subscribe)).
+//! Each subscriber gets its own bounded channel and only the messages
+//! matching its [`EventFilter`]; lifecycle events (`Connect`, `Disconnect`,
+//! `Stop`, `Error`, `Fatal`, `ReconnectScheduled`, `Reason`) are broadcast to all
+//! subscribers. Subscriber channels are closed after the `Stop` event.
+//!
+//! Delivery to subscribers never waits: when a subscriber's channel is full,
+//! the event is dropped for that subscriber and counted in
+//! [`ClientStatsSnapshot::hub_dropped_events`](super::ClientStatsSnapshot::hub_dropped_events).
+//! A slow subscriber thus neither stalls the client loop nor the other
+//! consumers.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use homie5::{DeviceRef, Homie5Message, HomieDomain, HomieID, PropertyRef};
+use tokio::sync::mpsc;
+
+use super::{ClientStats, HomieClientError, HomieClientEvent};
+
+/// Event delivered to hub subscribers. The connection error of `Error` is
+/// carried in its `Debug` representation, so events can be cloned for every
+/// subscriber regardless of the transport.
+pub type HubEvent = HomieClientEvent<String>;
+
+/// Message kinds an [`EventFilter`] can select.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    DeviceState,
+    DeviceDescription,
+    DeviceLog,
+    DeviceAlert,
+    DeviceRemoval,
+    PropertyValue,
+    PropertyTarget,
+    PropertySet,
+    Broadcast,
+    #[cfg(feature = "ext-meta")]
+    Meta,
+    /// [`HomieClientEvent::Raw`] messages.
+    Raw,
+}
+
+impl EventKind {
+    fn of_message(message: &Homie5Message) -> Self {
+        match message {
+            Homie5Message::DeviceState { .. } => Self::DeviceState,
+            Homie5Message::DeviceDescription { .. } => Self::DeviceDescription,
+            Homie5Message::DeviceLog { .. } => Self::DeviceLog,
+            Homie5Message::DeviceAlert { .. } => Self::DeviceAlert,
+            Homie5Message::DeviceRemoval { .. } => Self::DeviceRemoval,
+            Homie5Message::PropertyValue { .. } => Self::PropertyValue,
+            Homie5Message::PropertyTarget { .. } => Self::PropertyTarget,
+            Homie5Message::PropertySet { .. } => Self::PropertySet,
+            Homie5Message::Broadcast { .. } => Self::Broadcast,
+        }
+    }
+}
+
+/// Selects the messages a hub subscriber receives. All set criteria must
+/// match; the default filter matches every message.
+///
+/// Messages without a domain, device or property (e.g. broadcasts for a
+/// device filter, raw messages for any of them) do not match a filter that
+/// constrains it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    pub domain: Option<HomieDomain>,
+    pub device: Option<DeviceRef>,
+    pub property: Option<PropertyRef>,
+    /// Message kinds to pass. Empty: all kinds.
+    pub kinds: Vec<EventKind>,
+}
+
+impl EventFilter {
+    /// Matches every message.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn domain(mut self, domain: HomieDomain) -> Self {
+        self.domain = Some(domain);
+        self
+    }
+
+    pub fn device(mut self, device: DeviceRef) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    pub fn property(mut self, property: PropertyRef) -> Self {
+        self.property = Some(property);
+        self
+    }
+
+    /// Adds a message kind to pass. Can be called repeatedly.
+    pub fn kind(mut self, kind: EventKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    /// Whether `event` is delivered to a subscriber with this filter.
+    /// Lifecycle events always are.
+    pub fn matches<E>(&self, event: &HomieClientEvent<E>) -> bool {
+        let (kind, refs) = match event {
+            HomieClientEvent::HomieMessage { message, .. } => {
+                (EventKind::of_message(message), message_refs(message))
+            }
+            #[cfg(feature = "ext-meta")]
+            HomieClientEvent::MetaMessage(message) => (EventKind::Meta, meta_refs(message)),
+            HomieClientEvent::Raw { .. } => (EventKind::Raw, MessageRefs::default()),
+            _ => return true,
+        };
+        if !self.kinds.is_empty() && !self.kinds.contains(&kind) {
+            return false;
+        }
+        if let Some(domain) = &self.domain {
+            if refs.domain != Some(domain) {
+                return false;
+            }
+        }
+        if let Some(device) = &self.device {
+            if refs.device != Some((device.homie_domain(), device.device_id())) {
+                return false;
+            }
+        }
+        match &self.property {
+            Some(property) => refs.property == Some(property),
+            None => true,
+        }
+    }
+}
+
+/// Domain, device and property a message refers to.
+#[derive(Default)]
+struct MessageRefs<'a> {
+    domain: Option<&'a HomieDomain>,
+    device: Option<(&'a HomieDomain, &'a HomieID)>,
+    property: Option<&'a PropertyRef>,
+}
+
+fn message_refs(message: &Homie5Message) -> MessageRefs<'_> {
+    match message {
+        Homie5Message::DeviceState { device, .. }
+        | Homie5Message::DeviceDescription { device, .. }
+        | Homie5Message::DeviceLog { device, .. }
+        | Homie5Message::DeviceAlert { device, .. }
+        | Homie5Message::DeviceRemoval { device } => MessageRefs {
+            domain: Some(device.homie_domain()),
+            device: Some((device.homie_domain(), device.device_id())),
+            property: None,
+        },
+        Homie5Message::PropertyValue { property, .. }
+        | Homie5Message::PropertyTarget { property, .. }
+        | Homie5Message::PropertySet { property, .. } => MessageRefs {
+            domain: Some(property.homie_domain()),
+            device: Some((property.homie_domain(), property.device_id())),
+            property: Some(property),
+        },
+        Homie5Message::Broadcast { homie_domain, .. } => MessageRefs {
+            domain: Some(homie_domain),
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(feature = "ext-meta")]
+fn meta_refs(message: &homie5::extensions::meta::MetaMessage) -> MessageRefs<'_> {
+    use homie5::extensions::meta::MetaMessage;
+    match message {
+        MetaMessage::ProviderInfo { homie_domain, .. }
+        | MetaMessage::ProviderRemoval { homie_domain, .. } => MessageRefs {
+            domain: Some(homie_domain),
+            ..Default::default()
+        },
+        MetaMessage::DeviceOverlay {
+            homie_domain,
+            device_id,
+            ..
+        }
+        | MetaMessage::DeviceOverlayRemoval {
+            homie_domain,
+            device_id,
+            ..
+        } => MessageRefs {
+            domain: Some(homie_domain),
+            device: Some((homie_domain, device_id)),
+            property: None,
+        },
+    }
+}
+
+struct Subscriber {
+    filter: EventFilter,
+    sender: mpsc::Sender<HubEvent>,
+}
+
+#[derive(Default)]
+struct HubState {
+    subscribers: Vec<Subscriber>,
+    closed: bool,
+}
+
+/// Subscription hub of a client connection. Cloning yields another handle
+/// to the same hub.
+///
+/// Each subscriber has a channel of `mqtt_channel_size` capacity, closed
+/// after the `Stop` event. Delivery never waits: events that do not fit
+/// into a subscriber's channel are dropped for that subscriber and counted
+/// in [`ClientStatsSnapshot::hub_dropped_events`](super::ClientStatsSnapshot::hub_dropped_events),
+/// so a slow subscriber stalls neither the connection nor the other
+/// consumers.
+#[derive(Clone)]
+pub struct EventHub {
+    state: Arc<Mutex<HubState>>,
+    capacity: usize,
+    stats: Option<ClientStats>,
+}
+
+impl EventHub {
+    /// `capacity` is the channel size of every subscriber.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::default(),
+            capacity: capacity.max(1),
+            stats: None,
+        }
+    }
+
+    /// Counts events dropped for full subscriber channels in `stats`.
+    pub(crate) fn with_stats(mut self, stats: ClientStats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    fn state(&self) -> MutexGuard<'_, HubState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns a receiver of the events matching `filter`, plus all
+    /// lifecycle events. The receiver ends after the client loop stopped.
+    pub fn subscribe(&self, filter: EventFilter) -> mpsc::Receiver<HubEvent> {
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        let mut state = self.state();
+        if !state.closed {
+            state.subscribers.push(Subscriber { filter, sender });
+        }
+        receiver
+    }
+
+    /// Number of subscribers whose receiver is still alive.
+    pub fn subscriber_count(&self) -> usize {
+        let mut state = self.state();
+        state.subscribers.retain(|s| !s.sender.is_closed());
+        state.subscribers.len()
+    }
+
+    /// Converts `event` for the subscribers whose filter matches it.
+    fn route<E: std::fmt::Debug>(&self, event: &HomieClientEvent<E>) -> Option<Delivery> {
+        let senders: Vec<_> = {
+            let mut state = self.state();
+            state.subscribers.retain(|s| !s.sender.is_closed());
+            state
+                .subscribers
+                .iter()
+                .filter(|s| s.filter.matches(event))
+                .map(|s| s.sender.clone())
+                .collect()
+        };
+        (!senders.is_empty()).then(|| Delivery {
+            event: to_hub_event(event),
+            senders,
+            stats: self.stats.clone(),
+        })
+    }
+
+    /// Drops all subscriber channels; later subscriptions end immediately.
+    fn close(&self) {
+        let mut state = self.state();
+        state.closed = true;
+        state.subscribers.clear();
+    }
+}
+
+struct Delivery {
+    event: HubEvent,
+    senders: Vec<mpsc::Sender<HubEvent>>,
+    stats: Option<ClientStats>,
+}
+
+impl Delivery {
+    fn send(self) {
+        for sender in self.senders {
+            match sender.try_send(self.event.clone()) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    if let Some(stats) = &self.stats {
+                        stats.record_hub_dropped_event();
+                    }
+                }
+                // A receiver dropped meanwhile is removed on the next event.
+                Err(mpsc::error::TrySendError::Closed(_)) => {}
+            }
+        }
+    }
+}
+
+fn to_hub_event<E: std::fmt::Debug>(event: &HomieClientEvent<E>) -> HubEvent {
+    match event {
+        HomieClientEvent::Connect { endpoint } => HomieClientEvent::Connect {
+            endpoint: endpoint.clone(),
+        },
+        HomieClientEvent::Disconnect => HomieClientEvent::Disconnect,
+        HomieClientEvent::Stop => HomieClientEvent::Stop,
+        HomieClientEvent::HomieMessage { message, retain } => HomieClientEvent::HomieMessage {
+            message: message.clone(),
+            retain: *retain,
+        },
+        #[cfg(feature = "ext-meta")]
+        HomieClientEvent::MetaMessage(message) => HomieClientEvent::MetaMessage(message.clone()),
+        HomieClientEvent::Error(err) => HomieClientEvent::Error(format!("{err:?}")),
+        HomieClientEvent::Fatal(failure) => HomieClientEvent::Fatal(failure.clone()),
+        HomieClientEvent::ReconnectScheduled { attempt, delay } => {
+            HomieClientEvent::ReconnectScheduled {
+                attempt: *attempt,
+                delay: *delay,
+            }
+        }
+        HomieClientEvent::Reason(reason) => HomieClientEvent::Reason(reason.clone()),
+        HomieClientEvent::Raw {
+            topic,
+            payload,
+            retain,
+            qos,
+        } => HomieClientEvent::Raw {
+            topic: topic.clone(),
+            payload: payload.clone(),
+            retain: *retain,
+            qos: qos.clone(),
+        },
+    }
+}
+
+/// Delivers client events to the main event channel and the hub.
+pub(crate) struct EventSink<E> {
+    sender: mpsc::Sender<HomieClientEvent<E>>,
+    hub: EventHub,
+}
+
+impl<E: std::fmt::Debug> EventSink<E> {
+    pub fn new(sender: mpsc::Sender<HomieClientEvent<E>>, hub: EventHub) -> Self {
+        Self { sender, hub }
+    }
+
+    pub async fn send(&self, event: HomieClientEvent<E>) -> Result<(), HomieClientError> {
+        if let Some(delivery) = self.hub.route(&event) {
+            delivery.send();
+        }
+        match self.sender.send(event).await {
+            Ok(()) => Ok(()),
+            // Consumers may rely on hub subscriptions alone and drop the
+            // main receiver.
+            Err(_) if self.hub.subscriber_count() > 0 => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Nobody receives events anymore.
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed() && self.hub.subscriber_count() == 0
+    }
+}
+
+impl<E> Drop for EventSink<E> {
+    fn drop(&mut self) {
+        self.hub.close();
+    }
+}
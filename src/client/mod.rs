@@ -1,15 +1,47 @@
+mod backpressure;
 mod bridge_setup;
 mod config;
+mod credentials;
+mod endpoint;
+mod error_class;
 mod event;
 mod handle;
+mod hub;
+mod mqtt5;
 pub mod mqtt_client;
+mod offline;
 mod pending;
+mod reconnect;
+mod recording;
+mod response;
 mod run;
+mod stats;
+mod status;
+mod subscriptions;
+mod tls;
+mod transport;
+mod versioned;
 
+pub use backpressure::BackpressurePolicy;
 pub use bridge_setup::*;
 pub use config::*;
+pub use credentials::*;
+pub use endpoint::*;
+pub use error_class::*;
 pub use event::*;
 pub use handle::*;
-pub use mqtt_client::HomieMQTTClient;
+pub use hub::{EventFilter, EventHub, EventKind, HubEvent};
+pub use mqtt5::*;
+pub use mqtt_client::{HomieMQTTClient, DEFAULT_MAX_PACKET_SIZE_OUTGOING};
+pub use offline::*;
 pub use pending::*;
+pub use reconnect::*;
+pub use recording::*;
+pub use response::*;
 pub use run::*;
+pub use stats::*;
+pub use status::ConnectionStatus;
+pub use subscriptions::*;
+pub use tls::*;
+pub use transport::*;
+pub use versioned::*;
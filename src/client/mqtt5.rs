@@ -0,0 +1,384 @@
+//! MQTT 5 transport on top of `rumqttc`'s v5 client.
+//!
+//! [`run_homie_client_v5`] is the MQTT 5 counterpart of
+//! [`run_homie_client`](super::run_homie_client). On top of the 3.1.1 feature
+//! set it attaches a message expiry to non-retained publishes, supports user
+//! properties via
+//! [`HomieMQTTClient::homie_publish_with_user_properties`](super::HomieMQTTClient::homie_publish_with_user_properties)
+//! and reports CONNACK, SUBACK and DISCONNECT reason codes as
+//! [`HomieClientEvent::Reason`](super::HomieClientEvent::Reason).
+
+use std::future::Future;
+
+use homie5::client::QoS;
+use rumqttc::{
+    v5::{
+        self,
+        mqttbytes::{
+            v5::{ConnectReturnCode, Filter, Packet, PublishProperties, SubscribeReasonCode},
+            QoS as V5QoS,
+        },
+        ConnectionError, Event, StateError,
+    },
+    Outgoing,
+};
+
+use super::{
+    endpoint::EndpointRotation, run_homie_client_with_transport, BrokerEndpoint, BrokerReason,
+    ConnectionFailure, ConnectionFailureKind, Credentials, HomieClientError, HomieClientOptions,
+    HomieClientParts, MqttEventLoop, MqttMessage, MqttTransport, ReasonCode, TransportEvent,
+};
+
+/// Outgoing half of an MQTT 5 connection.
+///
+/// Wraps [`rumqttc::v5::AsyncClient`] and applies the configured message
+/// expiry interval to every non-retained publish.
+#[derive(Debug, Clone)]
+pub struct MqttV5Client {
+    client: v5::AsyncClient,
+    message_expiry_interval: Option<u32>,
+}
+
+impl MqttV5Client {
+    pub fn new(client: v5::AsyncClient, message_expiry_interval: Option<u32>) -> Self {
+        Self {
+            client,
+            message_expiry_interval,
+        }
+    }
+
+    /// The underlying `rumqttc` v5 client.
+    pub fn inner(&self) -> &v5::AsyncClient {
+        &self.client
+    }
+
+    pub fn map_qos(qos: &QoS) -> V5QoS {
+        match qos {
+            QoS::AtLeastOnce => V5QoS::AtLeastOnce,
+            QoS::AtMostOnce => V5QoS::AtMostOnce,
+            QoS::ExactlyOnce => V5QoS::ExactlyOnce,
+        }
+    }
+
+    pub fn map_last_will(last_will: homie5::client::LastWill) -> v5::mqttbytes::v5::LastWill {
+        v5::mqttbytes::v5::LastWill::new(
+            last_will.topic,
+            last_will.message,
+            Self::map_qos(&last_will.qos),
+            last_will.retain,
+            None,
+        )
+    }
+}
+
+impl MqttTransport for MqttV5Client {
+    type Error = v5::ClientError;
+
+    fn publish(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.publish_with_user_properties(topic, qos, retain, payload, Vec::new())
+    }
+
+    async fn publish_with_user_properties(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+        user_properties: Vec<(String, String)>,
+    ) -> Result<(), Self::Error> {
+        // Retained messages describe current state and must not vanish from
+        // the broker; only transient publishes (e.g. `$target`/`set` of
+        // non-retained properties) expire.
+        let message_expiry_interval = if retain {
+            None
+        } else {
+            self.message_expiry_interval
+        };
+        if message_expiry_interval.is_none() && user_properties.is_empty() {
+            return self
+                .client
+                .publish(topic, Self::map_qos(&qos), retain, payload)
+                .await;
+        }
+        let properties = PublishProperties {
+            message_expiry_interval,
+            user_properties,
+            ..Default::default()
+        };
+        self.client
+            .publish_with_properties(topic, Self::map_qos(&qos), retain, payload, properties)
+            .await
+    }
+
+    fn subscribe(
+        &self,
+        topic: String,
+        qos: QoS,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.client.subscribe(topic, Self::map_qos(&qos))
+    }
+
+    fn subscribe_many(
+        &self,
+        topics: Vec<(String, QoS)>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.client.subscribe_many(
+            topics
+                .into_iter()
+                .map(|(topic, qos)| Filter::new(topic, Self::map_qos(&qos))),
+        )
+    }
+
+    // rumqttc's v5 client has no multi-topic unsubscribe either, so
+    // `unsubscribe_many` keeps the per-filter default.
+
+    fn unsubscribe(&self, topic: String) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.client.unsubscribe(topic)
+    }
+
+    fn disconnect(&self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.client.disconnect()
+    }
+}
+
+/// Incoming half of an MQTT 5 connection.
+///
+/// Wraps [`rumqttc::v5::EventLoop`] and turns CONNACK, SUBACK and broker
+/// DISCONNECT packets into [`TransportEvent::Reason`] events. Where one
+/// `rumqttc` event maps to two transport events (a CONNACK is both a reason
+/// and [`TransportEvent::Connected`]), the second one is returned by the next
+/// poll.
+pub struct MqttV5EventLoop {
+    eventloop: v5::EventLoop,
+    pending: Option<Result<TransportEvent, ConnectionError>>,
+    endpoints: EndpointRotation<v5::MqttOptions>,
+}
+
+impl MqttV5EventLoop {
+    pub fn new(eventloop: v5::EventLoop) -> Self {
+        Self {
+            eventloop,
+            pending: None,
+            endpoints: EndpointRotation::new(Vec::new()),
+        }
+    }
+
+    /// Switch to the next of `endpoints` after a failed connection attempt
+    /// (see [`MqttEventLoop::failover`]). The event loop must have been
+    /// created with the first entry.
+    pub fn with_failover(mut self, endpoints: Vec<v5::MqttOptions>) -> Self {
+        self.endpoints = EndpointRotation::new(endpoints);
+        self
+    }
+
+    /// The underlying `rumqttc` v5 event loop.
+    pub fn inner(&self) -> &v5::EventLoop {
+        &self.eventloop
+    }
+}
+
+impl MqttEventLoop for MqttV5EventLoop {
+    type Error = ConnectionError;
+
+    async fn poll(&mut self) -> Result<TransportEvent, Self::Error> {
+        if let Some(pending) = self.pending.take() {
+            return pending;
+        }
+        let event = match self.eventloop.poll().await {
+            Ok(event) => event,
+            Err(err) => {
+                // Report the reason first, the error itself follows.
+                return match reason_for_error(&err) {
+                    Some(reason) => {
+                        self.pending = Some(Err(err));
+                        Ok(TransportEvent::Reason(reason))
+                    }
+                    None => Err(err),
+                };
+            }
+        };
+        let event = match event {
+            Event::Incoming(Packet::Publish(p)) => TransportEvent::Message(MqttMessage {
+                topic: String::from_utf8_lossy(&p.topic).into_owned(),
+                payload: p.payload,
+                qos: map_incoming_qos(p.qos),
+                retain: p.retain,
+            }),
+            Event::Incoming(Packet::ConnAck(ack)) => {
+                self.pending = Some(Ok(TransportEvent::Connected));
+                TransportEvent::Reason(BrokerReason::ConnAck {
+                    code: connack_code(ack.code),
+                    session_present: ack.session_present,
+                    reason_string: ack.properties.and_then(|p| p.reason_string),
+                })
+            }
+            Event::Incoming(Packet::SubAck(ack)) => TransportEvent::Reason(BrokerReason::SubAck {
+                pkid: ack.pkid,
+                codes: ack.return_codes.into_iter().map(suback_code).collect(),
+                reason_string: ack.properties.and_then(|p| p.reason_string),
+            }),
+            Event::Outgoing(Outgoing::Publish(pkid)) => TransportEvent::PublishSent(pkid),
+            Event::Incoming(Packet::PubAck(ack)) => TransportEvent::PublishAcked(ack.pkid),
+            Event::Incoming(Packet::PubComp(comp)) => TransportEvent::PublishAcked(comp.pkid),
+            Event::Outgoing(Outgoing::Disconnect) => TransportEvent::Disconnected,
+            _ => TransportEvent::Other,
+        };
+        Ok(event)
+    }
+
+    fn endpoint(&self) -> Option<BrokerEndpoint> {
+        Some(BrokerEndpoint::from_broker_address(
+            self.eventloop.options.broker_address(),
+        ))
+    }
+
+    fn failover(&mut self) {
+        if let Some(options) = self.endpoints.advance() {
+            self.eventloop.options = options;
+        }
+    }
+
+    fn describe_error(&self, err: &Self::Error) -> ConnectionFailure {
+        ConnectionFailure::from(err)
+    }
+
+    fn set_credentials(&mut self, credentials: &Credentials) {
+        self.eventloop
+            .options
+            .set_credentials(&credentials.username, &credentials.password);
+        for options in self.endpoints.options_mut() {
+            options.set_credentials(&credentials.username, &credentials.password);
+        }
+    }
+}
+
+impl From<&ConnectionError> for ConnectionFailure {
+    fn from(err: &ConnectionError) -> Self {
+        let kind = match err {
+            ConnectionError::ConnectionRefused(code) => {
+                ConnectionFailureKind::Refused(connack_code(*code))
+            }
+            ConnectionError::Tls(_) => ConnectionFailureKind::Tls,
+            ConnectionError::Io(_) => ConnectionFailureKind::Io,
+            ConnectionError::Timeout(_) => ConnectionFailureKind::Timeout,
+            _ => ConnectionFailureKind::Other,
+        };
+        ConnectionFailure::new(kind, err.to_string())
+    }
+}
+
+/// Like [`run_homie_client_with_client_options`](super::run_homie_client_with_client_options),
+/// but connects with MQTT 5.
+///
+/// `message_expiry_interval` (seconds) is attached to every non-retained
+/// publish; see [`MqttClientConfig::message_expiry_interval`](super::MqttClientConfig::message_expiry_interval).
+pub fn run_homie_client_v5(
+    mqttoptions: v5::MqttOptions,
+    message_expiry_interval: Option<u32>,
+    options: HomieClientOptions,
+) -> Result<HomieClientParts<MqttV5Client, ConnectionError>, HomieClientError> {
+    log::trace!("Connecting to mqtt (v5): {}", mqttoptions.client_id());
+    let (mqtt_client, eventloop) = v5::AsyncClient::new(mqttoptions, options.channel_size);
+    run_homie_client_with_transport(
+        MqttV5Client::new(mqtt_client, message_expiry_interval),
+        MqttV5EventLoop::new(eventloop),
+        options,
+    )
+}
+
+/// Like [`run_homie_client_v5`], but fails over between several brokers
+/// (see [`run_homie_client_with_failover`](super::run_homie_client_with_failover)).
+pub fn run_homie_client_v5_with_failover(
+    endpoints: Vec<v5::MqttOptions>,
+    message_expiry_interval: Option<u32>,
+    options: HomieClientOptions,
+) -> Result<HomieClientParts<MqttV5Client, ConnectionError>, HomieClientError> {
+    let first = endpoints.first().cloned().ok_or_else(|| {
+        HomieClientError::TransportConfig("no broker endpoints given".to_string())
+    })?;
+    log::trace!("Connecting to mqtt (v5): {}", first.client_id());
+    let (mqtt_client, eventloop) = v5::AsyncClient::new(first, options.channel_size);
+    run_homie_client_with_transport(
+        MqttV5Client::new(mqtt_client, message_expiry_interval),
+        MqttV5EventLoop::new(eventloop).with_failover(endpoints),
+        options,
+    )
+}
+
+fn reason_for_error(err: &ConnectionError) -> Option<BrokerReason> {
+    match err {
+        ConnectionError::ConnectionRefused(code)
+        | ConnectionError::MqttState(StateError::ConnFail { reason: code }) => {
+            Some(BrokerReason::ConnAck {
+                code: connack_code(*code),
+                session_present: false,
+                reason_string: None,
+            })
+        }
+        ConnectionError::MqttState(StateError::ServerDisconnect {
+            reason_code,
+            reason_string,
+        }) => Some(BrokerReason::Disconnect {
+            code: ReasonCode(*reason_code as u8),
+            reason_string: reason_string.clone(),
+        }),
+        _ => None,
+    }
+}
+
+fn connack_code(code: ConnectReturnCode) -> ReasonCode {
+    ReasonCode(match code {
+        ConnectReturnCode::Success => 0x00,
+        ConnectReturnCode::UnspecifiedError => 0x80,
+        ConnectReturnCode::MalformedPacket => 0x81,
+        ConnectReturnCode::ProtocolError => 0x82,
+        ConnectReturnCode::ImplementationSpecificError => 0x83,
+        ConnectReturnCode::UnsupportedProtocolVersion
+        | ConnectReturnCode::RefusedProtocolVersion => 0x84,
+        ConnectReturnCode::ClientIdentifierNotValid | ConnectReturnCode::BadClientId => 0x85,
+        ConnectReturnCode::BadUserNamePassword => 0x86,
+        ConnectReturnCode::NotAuthorized => 0x87,
+        ConnectReturnCode::ServerUnavailable | ConnectReturnCode::ServiceUnavailable => 0x88,
+        ConnectReturnCode::ServerBusy => 0x89,
+        ConnectReturnCode::Banned => 0x8A,
+        ConnectReturnCode::BadAuthenticationMethod => 0x8C,
+        ConnectReturnCode::TopicNameInvalid => 0x90,
+        ConnectReturnCode::PacketTooLarge => 0x95,
+        ConnectReturnCode::QuotaExceeded => 0x97,
+        ConnectReturnCode::PayloadFormatInvalid => 0x99,
+        ConnectReturnCode::RetainNotSupported => 0x9A,
+        ConnectReturnCode::QoSNotSupported => 0x9B,
+        ConnectReturnCode::UseAnotherServer => 0x9C,
+        ConnectReturnCode::ServerMoved => 0x9D,
+        ConnectReturnCode::ConnectionRateExceeded => 0x9F,
+    })
+}
+
+fn suback_code(code: SubscribeReasonCode) -> ReasonCode {
+    ReasonCode(match code {
+        SubscribeReasonCode::Success(qos) => qos as u8,
+        SubscribeReasonCode::Failure | SubscribeReasonCode::Unspecified => 0x80,
+        SubscribeReasonCode::ImplementationSpecific => 0x83,
+        SubscribeReasonCode::NotAuthorized => 0x87,
+        SubscribeReasonCode::TopicFilterInvalid => 0x8F,
+        SubscribeReasonCode::PkidInUse => 0x91,
+        SubscribeReasonCode::QuotaExceeded => 0x97,
+        SubscribeReasonCode::SharedSubscriptionsNotSupported => 0x9E,
+        SubscribeReasonCode::SubscriptionIdNotSupported => 0xA1,
+        SubscribeReasonCode::WildcardSubscriptionsNotSupported => 0xA2,
+    })
+}
+
+fn map_incoming_qos(qos: V5QoS) -> QoS {
+    match qos {
+        V5QoS::AtMostOnce => QoS::AtMostOnce,
+        V5QoS::AtLeastOnce => QoS::AtLeastOnce,
+        V5QoS::ExactlyOnce => QoS::ExactlyOnce,
+    }
+}
@@ -1,45 +1,61 @@
-use std::ops::{Deref, DerefMut};
+use std::time::Duration;
 
-use homie5::client::{Publish, Subscription, Unsubscribe};
+use homie5::client::{Publish, QoS, Subscription, Unsubscribe};
 use rumqttc::AsyncClient;
 
-use super::QueuedPublishCounter;
+use super::{
+    BufferedPublish, ClientStats, MqttMessage, MqttTransport, OfflineBuffer, PublishAck,
+    QueuedPublishCounter, RequestError, ResponseMatcher, ResponseRegistry, SubscriptionRegistry,
+};
 
-/// Wrapper around [`rumqttc::AsyncClient`] that counts every publish it
-/// enqueues so [`HomieClientHandle::flush`](super::HomieClientHandle::flush)
-/// can wait for requests the event loop has not even seen yet.
+/// Outgoing packet size limit assumed when none is configured (the
+/// `rumqttc` default).
+pub const DEFAULT_MAX_PACKET_SIZE_OUTGOING: usize = 10 * 1024;
+
+// Upper bound for everything in a (UN)SUBSCRIBE packet besides the filters:
+// fixed header (1 + up to 4 bytes remaining length), packet id and an empty
+// MQTT 5 property block.
+const SUBSCRIBE_HEADER_LEN: usize = 8;
+
+/// Wrapper around an [`MqttTransport`] (by default [`rumqttc::AsyncClient`])
+/// that counts every publish it enqueues so
+/// [`HomieClientHandle::flush`](super::HomieClientHandle::flush) can wait for
+/// requests the event loop has not even seen yet.
+///
+/// Prefer [`homie_publish`](Self::homie_publish) for non-Homie topics as
+/// well: publishes issued through [`raw_transport`](Self::raw_transport)
+/// bypass the queued counting and the [`PublishAck`] pairing.
+///
+/// Subscriptions made through [`homie_subscribe`](Self::homie_subscribe) are
+/// recorded in a shared [`SubscriptionRegistry`] and replayed by the client
+/// event loop after every reconnect; [`homie_unsubscribe`](Self::homie_unsubscribe)
+/// only sends the UNSUBSCRIBE once the last reference to a filter is
+/// released. With an [`OfflineBuffer`] attached,
+/// publishes issued while disconnected are parked there instead of the
+/// transport's request channel.
+///
+/// Multi-topic (un)subscriptions are batched into as few packets as the
+/// outgoing packet size limit allows.
 ///
-/// Publishes issued through the [`Deref`] escape hatch bypass the queued
-/// counting (they are still flush-tracked from the moment the event loop
-/// emits `Outgoing::Publish`); prefer [`homie_publish`](Self::homie_publish).
+/// [`homie_publish_tracked`](Self::homie_publish_tracked) returns a
+/// [`PublishAck`] for one publish, paired with its packet id by the client
+/// event loop.
+///
+/// [`homie_request`](Self::homie_request) publishes and waits for a matching
+/// incoming message, resolved by the client event loop through the shared
+/// [`ResponseRegistry`].
 #[derive(Debug, Clone)]
-pub struct HomieMQTTClient {
-    client: AsyncClient,
+pub struct HomieMQTTClient<T = AsyncClient> {
+    client: T,
     queued_publishes: QueuedPublishCounter,
+    subscriptions: SubscriptionRegistry,
+    responses: ResponseRegistry,
+    offline_buffer: Option<OfflineBuffer>,
+    max_packet_size: usize,
+    stats: Option<ClientStats>,
 }
 
-impl Deref for HomieMQTTClient {
-    type Target = AsyncClient;
-
-    fn deref(&self) -> &Self::Target {
-        &self.client
-    }
-}
-
-impl DerefMut for HomieMQTTClient {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.client
-    }
-}
-
-impl HomieMQTTClient {
-    pub fn new(mqtt_client: AsyncClient, queued_publishes: QueuedPublishCounter) -> Self {
-        Self {
-            client: mqtt_client,
-            queued_publishes,
-        }
-    }
-
+impl HomieMQTTClient<AsyncClient> {
     pub fn map_qos(qos: &homie5::client::QoS) -> rumqttc::QoS {
         match qos {
             homie5::client::QoS::AtLeastOnce => rumqttc::QoS::AtLeastOnce,
@@ -55,43 +71,319 @@ impl HomieMQTTClient {
             retain: last_will.retain,
         }
     }
+}
+
+impl<T: MqttTransport> HomieMQTTClient<T> {
+    pub fn new(mqtt_client: T, queued_publishes: QueuedPublishCounter) -> Self {
+        Self {
+            client: mqtt_client,
+            queued_publishes,
+            subscriptions: SubscriptionRegistry::new(),
+            responses: ResponseRegistry::new(),
+            offline_buffer: None,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE_OUTGOING,
+            stats: None,
+        }
+    }
+
+    /// Sets the outgoing packet size limit used to split batched
+    /// (un)subscriptions. Must not exceed the transport's own limit.
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    /// Attaches an offline buffer. The buffer must share this client's
+    /// queued publish counter.
+    pub fn with_offline_buffer(mut self, offline_buffer: OfflineBuffer) -> Self {
+        self.offline_buffer = Some(offline_buffer);
+        self
+    }
+
+    /// Attaches the stats that count publishes sent through this client.
+    pub fn with_stats(mut self, stats: ClientStats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// The offline buffer, if one is configured.
+    pub fn offline_buffer(&self) -> Option<&OfflineBuffer> {
+        self.offline_buffer.as_ref()
+    }
+
+    /// Registry of the filters subscribed through this client (shared by
+    /// all clones).
+    pub fn subscriptions(&self) -> &SubscriptionRegistry {
+        &self.subscriptions
+    }
+
+    /// Registry of the requests awaiting a response (shared by all clones).
+    pub fn responses(&self) -> &ResponseRegistry {
+        &self.responses
+    }
+
+    /// The underlying transport, e.g. the `rumqttc` client.
+    ///
+    /// Publishes sent through it bypass the queued counting (they are still
+    /// flush-tracked once the event loop sends them) and shift the pairing
+    /// of [`PublishAck`]s, which relies on seeing every publish in request
+    /// order. Do not mix them with
+    /// [`homie_publish_tracked`](Self::homie_publish_tracked).
+    pub fn raw_transport(&self) -> &T {
+        &self.client
+    }
+
+    /// Sends a DISCONNECT, ending the connection for good.
+    pub async fn disconnect(&self) -> Result<(), T::Error> {
+        self.client.disconnect().await
+    }
+
     // Implementation for publishing messages
-    pub async fn homie_publish(&self, p: Publish) -> Result<(), rumqttc::ClientError> {
+    pub async fn homie_publish(&self, p: Publish) -> Result<(), T::Error> {
+        self.publish_counted(BufferedPublish {
+            publish: p,
+            user_properties: Vec::new(),
+            ack: None,
+        })
+        .await
+    }
+
+    /// Like [`homie_publish`](Self::homie_publish), but returns a
+    /// [`PublishAck`] that resolves once the broker acknowledged this
+    /// publish, e.g. to confirm a `$state` change while other traffic keeps
+    /// flowing.
+    pub async fn homie_publish_tracked(&self, p: Publish) -> Result<PublishAck, T::Error> {
+        let (notifier, ack) = PublishAck::new();
+        self.publish_counted(BufferedPublish {
+            publish: p,
+            user_properties: Vec::new(),
+            ack: Some(notifier),
+        })
+        .await?;
+        Ok(ack)
+    }
+
+    /// Like [`homie_publish`](Self::homie_publish), with MQTT 5 user
+    /// properties attached. MQTT 3.1.1 transports drop the properties.
+    pub async fn homie_publish_with_user_properties(
+        &self,
+        p: Publish,
+        user_properties: Vec<(String, String)>,
+    ) -> Result<(), T::Error> {
+        self.publish_counted(BufferedPublish {
+            publish: p,
+            user_properties,
+            ack: None,
+        })
+        .await
+    }
+
+    /// Publishes `p` and waits up to `timeout` for the first incoming
+    /// message accepted by `matcher`, e.g. the `$target` or value publish
+    /// confirming a `/set`.
+    ///
+    /// The matcher is registered before publishing, so a fast response is
+    /// never missed. The client must be subscribed to the response topic.
+    /// The response is also delivered as regular client event. Fails with
+    /// [`RequestError::Timeout`] if nothing matches in time and with
+    /// [`RequestError::Closed`] once the client event loop stopped.
+    pub async fn homie_request(
+        &self,
+        p: Publish,
+        matcher: ResponseMatcher,
+        timeout: Duration,
+    ) -> Result<MqttMessage, RequestError<T::Error>> {
+        let pending = self.responses.register(matcher);
+        self.homie_publish(p).await.map_err(RequestError::Publish)?;
+        pending.wait(timeout).await
+    }
+
+    async fn publish_counted(&self, entry: BufferedPublish) -> Result<(), T::Error> {
         // Count before enqueueing so the event loop can never observe the
         // request ahead of the counter increment.
         self.queued_publishes.increment();
-        if let Err(err) = self
-            .client
-            .publish(p.topic, Self::map_qos(&p.qos), p.retain, p.payload)
-            .await
-        {
-            self.queued_publishes.decrement();
-            return Err(err);
+        let entry = match &self.offline_buffer {
+            Some(buffer) => match buffer.buffer_if_offline(entry) {
+                Some(entry) => entry,
+                None => return Ok(()),
+            },
+            None => entry,
+        };
+        self.send_counted(entry).await
+    }
+
+    /// Hands an already counted publish to the transport.
+    async fn send_counted(&self, entry: BufferedPublish) -> Result<(), T::Error> {
+        let BufferedPublish {
+            publish: p,
+            user_properties,
+            ack,
+        } = entry;
+        let payload_len = p.payload.len();
+        let enqueue = async {
+            if user_properties.is_empty() {
+                self.client
+                    .publish(p.topic, p.qos, p.retain, p.payload)
+                    .await
+            } else {
+                self.client
+                    .publish_with_user_properties(
+                        p.topic,
+                        p.qos,
+                        p.retain,
+                        p.payload,
+                        user_properties,
+                    )
+                    .await
+            }
+        };
+        let res = self.queued_publishes.enqueue(ack, enqueue).await;
+        match &res {
+            Ok(()) => {
+                if let Some(stats) = &self.stats {
+                    stats.record_message_out(payload_len);
+                }
+            }
+            Err(_) => self.queued_publishes.decrement(),
         }
-        Ok(())
+        res
+    }
+
+    /// Sends everything parked in the offline buffer, in order. Called by the
+    /// client event loop after a reconnect once
+    /// [`OfflineBuffer::set_online`] asked for a drain.
+    pub async fn drain_offline_buffer(&self) -> Result<(), T::Error> {
+        let Some(buffer) = &self.offline_buffer else {
+            return Ok(());
+        };
+        // Keep going on errors so the drain always runs to its end state.
+        let mut result = Ok(());
+        while let Some(entry) = buffer.next_to_drain() {
+            if let Err(err) = self.send_counted(entry).await {
+                result = Err(err);
+            }
+        }
+        result
     }
 
     // Implementation for subscribing to topics
+    //
+    // Every call adds a reference in the subscription registry. The
+    // SUBSCRIBE is sent even if the filter is already active, so callers
+    // still get the retained messages delivered again. Filters are sent in
+    // as few packets as the packet size limit allows; if a packet fails, its
+    // filters are released again and the remaining ones are not sent.
     pub async fn homie_subscribe(
         &self,
         subs: impl Iterator<Item = Subscription> + Send,
-    ) -> Result<(), rumqttc::ClientError> {
-        for sub in subs {
-            self.client
-                .subscribe(sub.topic, Self::map_qos(&sub.qos))
-                .await?;
+    ) -> Result<(), T::Error> {
+        let subs = subs.map(|sub| (sub.topic, sub.qos)).collect();
+        for chunk in self.subscribe_chunks(subs) {
+            for (topic, qos) in &chunk {
+                self.subscriptions.acquire(topic, qos.clone());
+            }
+            let topics: Vec<String> = chunk.iter().map(|(topic, _)| topic.clone()).collect();
+            if let Err(err) = self.client.subscribe_many(chunk).await {
+                for topic in &topics {
+                    self.subscriptions.release(topic);
+                }
+                return Err(err);
+            }
         }
         Ok(())
     }
 
     // Implementation for unsubscribing from topics
+    //
+    // Drops one registry reference per topic; the UNSUBSCRIBE is only sent
+    // for filters no other subscriber holds anymore, batched like
+    // subscriptions. The last reference is only dropped once its batch was
+    // sent, so a failed request leaves the filter registered for replay.
+    // With the `rumqttc` transports each batch still goes out as one
+    // UNSUBSCRIBE per filter (see `MqttTransport::unsubscribe_many`).
     pub async fn homie_unsubscribe(
         &self,
         subs: impl Iterator<Item = Unsubscribe> + Send,
-    ) -> Result<(), rumqttc::ClientError> {
+    ) -> Result<(), T::Error> {
+        let mut topics: Vec<String> = Vec::new();
         for sub in subs {
-            self.client.unsubscribe(sub.topic).await?;
+            if self.subscriptions.ref_count(&sub.topic) > 1 {
+                self.subscriptions.release(&sub.topic);
+            } else if !topics.contains(&sub.topic) {
+                topics.push(sub.topic);
+            }
+        }
+        for chunk in chunk_by_packet_size(topics, self.max_packet_size, |topic| 2 + topic.len()) {
+            self.client.unsubscribe_many(chunk.clone()).await?;
+            for topic in &chunk {
+                self.subscriptions.release(topic);
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-issues every registered subscription without changing the
+    /// reference counts. Called by the client event loop after a reconnect.
+    pub async fn resubscribe_all(&self) -> Result<(), T::Error> {
+        for chunk in self.subscribe_chunks(self.subscriptions.snapshot()) {
+            self.client.subscribe_many(chunk).await?;
         }
         Ok(())
     }
+
+    fn subscribe_chunks(&self, subs: Vec<(String, QoS)>) -> Vec<Vec<(String, QoS)>> {
+        // length prefix + filter + subscription options
+        chunk_by_packet_size(subs, self.max_packet_size, |(topic, _)| 2 + topic.len() + 1)
+    }
+}
+
+/// Splits `items` into consecutive chunks whose encoded (UN)SUBSCRIBE packet
+/// stays within `max_packet_size`. An item that is too large on its own
+/// still gets a chunk, so the transport reports the error.
+fn chunk_by_packet_size<I>(
+    items: Vec<I>,
+    max_packet_size: usize,
+    item_len: impl Fn(&I) -> usize,
+) -> Vec<Vec<I>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut len = SUBSCRIBE_HEADER_LEN;
+    for item in items {
+        let item_len = item_len(&item);
+        if !chunk.is_empty() && len + item_len > max_packet_size {
+            chunks.push(std::mem::take(&mut chunk));
+            len = SUBSCRIBE_HEADER_LEN;
+        }
+        len += item_len;
+        chunk.push(item);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_respect_packet_size() {
+        let topics: Vec<String> = (0..10).map(|i| format!("topic/{i}")).collect();
+        // 7 bytes per topic + 2 length prefix, 8 header bytes: three per packet.
+        let chunks = chunk_by_packet_size(topics, 8 + 3 * 9, |t| 2 + t.len());
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![3, 3, 3, 1]
+        );
+        assert_eq!(chunks[3], vec!["topic/9".to_string()]);
+    }
+
+    #[test]
+    fn oversized_item_gets_its_own_chunk() {
+        let topics = vec!["a".to_string(), "x".repeat(100), "b".to_string()];
+        let chunks = chunk_by_packet_size(topics, 20, |t| 2 + t.len());
+        assert_eq!(chunks.len(), 3);
+        assert!(chunk_by_packet_size(Vec::<String>::new(), 20, |t| t.len()).is_empty());
+    }
 }
@@ -0,0 +1,345 @@
+//! Bounded buffer for publishes issued while the broker is unreachable.
+//!
+//! Without a buffer, publishes made during a disconnect are enqueued in the
+//! transport's request channel and `homie_publish` blocks once that channel
+//! is full. With an [`OfflineBufferPolicy`] configured,
+//! [`HomieMQTTClient`](super::HomieMQTTClient) instead parks them here:
+//!
+//! - retained publishes to a topic that is already buffered replace the
+//!   older entry (only the latest state matters),
+//! - once `capacity` is reached an entry is dropped according to
+//!   [`OfflineDropPolicy`],
+//! - after reconnecting, the client event loop drains the buffer in order.
+//!   Publishes issued while the drain is running are appended to the buffer
+//!   so they cannot overtake older ones.
+//!
+//! Buffered publishes count as *queued* for
+//! [`PendingPublishObserver`](super::PendingPublishObserver): every entry
+//! that is coalesced away or dropped releases its count, so a flush waits
+//! exactly for what will still be sent.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use homie5::client::Publish;
+
+use super::{AckNotifier, QueuedPublishCounter};
+
+/// Which entry to give up when the offline buffer is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OfflineDropPolicy {
+    /// Drop the oldest non-retained publish; if only retained publishes are
+    /// buffered, drop the oldest of those.
+    #[default]
+    NonRetainedFirst,
+    /// Drop the oldest buffered publish.
+    Oldest,
+    /// Keep the buffer as is and drop the incoming publish.
+    Newest,
+}
+
+/// Size and drop behaviour of the offline buffer.
+///
+/// Publishes made while disconnected are parked in the buffer and sent in
+/// order after the next connect. A retained publish to a topic that is
+/// already buffered replaces the older entry; once `capacity` is reached
+/// an entry is dropped according to `drop_policy`. Buffered publishes count
+/// as pending, so `HomieClientHandle::flush` waits for them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfflineBufferPolicy {
+    /// Maximum number of buffered publishes.
+    pub capacity: usize,
+    pub drop_policy: OfflineDropPolicy,
+}
+
+impl OfflineBufferPolicy {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            drop_policy: OfflineDropPolicy::default(),
+        }
+    }
+
+    pub fn drop_policy(mut self, drop_policy: OfflineDropPolicy) -> Self {
+        self.drop_policy = drop_policy;
+        self
+    }
+}
+
+/// A publish waiting in the offline buffer.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BufferedPublish {
+    pub publish: Publish,
+    pub user_properties: Vec<(String, String)>,
+    /// Resolves the [`PublishAck`](super::PublishAck) of a tracked publish.
+    pub ack: Option<AckNotifier>,
+}
+
+impl std::fmt::Debug for BufferedPublish {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferedPublish")
+            .field("topic", &self.publish.topic)
+            .field("qos", &self.publish.qos)
+            .field("retain", &self.publish.retain)
+            .field("payload_len", &self.publish.payload.len())
+            .field("user_properties", &self.user_properties)
+            .field("tracked", &self.ack.is_some())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct BufferState {
+    online: bool,
+    draining: bool,
+    queue: VecDeque<BufferedPublish>,
+    dropped: u64,
+}
+
+/// Shared offline buffer. Cloning yields another handle to the same buffer.
+#[derive(Debug, Clone)]
+pub struct OfflineBuffer {
+    policy: OfflineBufferPolicy,
+    state: Arc<Mutex<BufferState>>,
+    queued_publishes: QueuedPublishCounter,
+}
+
+impl OfflineBuffer {
+    /// Creates an empty buffer in offline state (nothing is sent before the
+    /// first connect).
+    pub fn new(policy: OfflineBufferPolicy, queued_publishes: QueuedPublishCounter) -> Self {
+        Self {
+            policy,
+            state: Arc::new(Mutex::new(BufferState {
+                online: false,
+                draining: false,
+                queue: VecDeque::new(),
+                dropped: 0,
+            })),
+            queued_publishes,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, BufferState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Buffers `entry` unless the connection is up and no drain is running,
+    /// in which case it is handed back for sending right away.
+    ///
+    /// The caller has already counted `entry` as queued.
+    pub fn buffer_if_offline(&self, entry: BufferedPublish) -> Option<BufferedPublish> {
+        let mut state = self.state();
+        if state.online && !state.draining {
+            return Some(entry);
+        }
+
+        if entry.publish.retain {
+            if let Some(pos) = state
+                .queue
+                .iter()
+                .position(|b| b.publish.retain && b.publish.topic == entry.publish.topic)
+            {
+                state.queue.remove(pos);
+                self.queued_publishes.decrement();
+            }
+        }
+
+        if state.queue.len() >= self.policy.capacity {
+            let victim = match self.policy.drop_policy {
+                OfflineDropPolicy::NonRetainedFirst => Some(
+                    state
+                        .queue
+                        .iter()
+                        .position(|b| !b.publish.retain)
+                        .unwrap_or(0),
+                ),
+                OfflineDropPolicy::Oldest => Some(0),
+                OfflineDropPolicy::Newest => None,
+            };
+            state.dropped += 1;
+            self.queued_publishes.decrement();
+            match victim {
+                Some(pos) if pos < state.queue.len() => {
+                    if let Some(dropped) = state.queue.remove(pos) {
+                        log::warn!(
+                            "Offline buffer full, dropping publish to [{}]",
+                            dropped.publish.topic
+                        );
+                    }
+                }
+                _ => {
+                    log::warn!(
+                        "Offline buffer full, dropping publish to [{}]",
+                        entry.publish.topic
+                    );
+                    return None;
+                }
+            }
+        }
+        state.queue.push_back(entry);
+        None
+    }
+
+    /// Marks the connection as up. Returns `true` when buffered publishes are
+    /// waiting and the caller must start draining with
+    /// [`next_to_drain`](Self::next_to_drain).
+    pub fn set_online(&self) -> bool {
+        let mut state = self.state();
+        state.online = true;
+        if state.draining || state.queue.is_empty() {
+            return false;
+        }
+        state.draining = true;
+        true
+    }
+
+    /// Marks the connection as down; new publishes are buffered and a running
+    /// drain stops after its current entry.
+    pub fn set_offline(&self) {
+        self.state().online = false;
+    }
+
+    /// Takes the next entry to send while draining. Returns `None` (and ends
+    /// the drain) once the buffer is empty or the connection dropped again.
+    pub fn next_to_drain(&self) -> Option<BufferedPublish> {
+        let mut state = self.state();
+        let next = if state.online {
+            state.queue.pop_front()
+        } else {
+            None
+        };
+        if next.is_none() {
+            state.draining = false;
+        }
+        next
+    }
+
+    /// Discards all buffered publishes without touching the queued count.
+    /// Used when the connection is closed for good and the counts are reset
+    /// anyway.
+    pub fn clear(&self) {
+        self.state().queue.clear();
+    }
+
+    /// Number of buffered publishes.
+    pub fn len(&self) -> usize {
+        self.state().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state().queue.is_empty()
+    }
+
+    /// Total number of publishes dropped because the buffer was full.
+    pub fn dropped_count(&self) -> u64 {
+        self.state().dropped
+    }
+
+    /// Buffered topics in send order.
+    pub fn topics(&self) -> Vec<String> {
+        self.state()
+            .queue
+            .iter()
+            .map(|b| b.publish.topic.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use homie5::client::QoS;
+
+    use super::*;
+    use crate::client::PendingPublishTracker;
+
+    fn entry(topic: &str, payload: &str, retain: bool) -> BufferedPublish {
+        BufferedPublish {
+            publish: Publish {
+                topic: topic.to_string(),
+                payload: payload.as_bytes().to_vec(),
+                qos: QoS::AtLeastOnce,
+                retain,
+            },
+            user_properties: Vec::new(),
+            ack: None,
+        }
+    }
+
+    /// Counts like `HomieMQTTClient` does before handing a publish over.
+    fn buffer(buf: &OfflineBuffer, counter: &QueuedPublishCounter, e: BufferedPublish) {
+        counter.increment();
+        assert!(buf.buffer_if_offline(e).is_none());
+    }
+
+    #[test]
+    fn retained_publishes_to_same_topic_are_coalesced() {
+        let (tracker, observer) = PendingPublishTracker::new();
+        let counter = tracker.queued_counter();
+        let buf = OfflineBuffer::new(OfflineBufferPolicy::new(10), counter.clone());
+
+        buffer(&buf, &counter, entry("a", "1", true));
+        buffer(&buf, &counter, entry("b", "x", false));
+        buffer(&buf, &counter, entry("a", "2", true));
+
+        assert_eq!(buf.topics(), vec!["b", "a"]);
+        assert_eq!(observer.pending_count(), 2);
+        assert!(buf.set_online());
+        assert_eq!(buf.next_to_drain().unwrap().publish.payload, b"x");
+        assert_eq!(buf.next_to_drain().unwrap().publish.payload, b"2");
+        assert!(buf.next_to_drain().is_none());
+    }
+
+    #[test]
+    fn full_buffer_drops_non_retained_first() {
+        let (tracker, observer) = PendingPublishTracker::new();
+        let counter = tracker.queued_counter();
+        let buf = OfflineBuffer::new(OfflineBufferPolicy::new(2), counter.clone());
+
+        buffer(&buf, &counter, entry("a", "1", true));
+        buffer(&buf, &counter, entry("b", "x", false));
+        buffer(&buf, &counter, entry("c", "1", true));
+        assert_eq!(buf.topics(), vec!["a", "c"]);
+
+        // Only retained left: the oldest goes.
+        buffer(&buf, &counter, entry("d", "1", true));
+        assert_eq!(buf.topics(), vec!["c", "d"]);
+        assert_eq!(buf.dropped_count(), 2);
+        assert_eq!(observer.pending_count(), 2);
+    }
+
+    #[test]
+    fn newest_policy_rejects_incoming() {
+        let (tracker, observer) = PendingPublishTracker::new();
+        let counter = tracker.queued_counter();
+        let policy = OfflineBufferPolicy::new(1).drop_policy(OfflineDropPolicy::Newest);
+        let buf = OfflineBuffer::new(policy, counter.clone());
+
+        buffer(&buf, &counter, entry("a", "1", false));
+        buffer(&buf, &counter, entry("b", "1", false));
+        assert_eq!(buf.topics(), vec!["a"]);
+        assert_eq!(observer.pending_count(), 1);
+    }
+
+    #[test]
+    fn publishes_during_drain_are_appended() {
+        let (tracker, _observer) = PendingPublishTracker::new();
+        let counter = tracker.queued_counter();
+        let buf = OfflineBuffer::new(OfflineBufferPolicy::new(10), counter.clone());
+
+        buffer(&buf, &counter, entry("a", "1", false));
+        assert!(buf.set_online());
+        // A second connect while draining must not start another drain.
+        assert!(!buf.set_online());
+        buffer(&buf, &counter, entry("b", "1", false));
+        assert_eq!(buf.next_to_drain().unwrap().publish.topic, "a");
+        assert_eq!(buf.next_to_drain().unwrap().publish.topic, "b");
+        assert!(buf.next_to_drain().is_none());
+
+        // Drain finished: publishes go straight through again.
+        assert!(buf.buffer_if_offline(entry("c", "1", false)).is_some());
+        buf.set_offline();
+        buffer(&buf, &counter, entry("d", "1", false));
+        assert_eq!(buf.len(), 1);
+    }
+}
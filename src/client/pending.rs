@@ -16,18 +16,25 @@
 //! could observe an empty in-flight set before the event loop ever polled
 //! the request and return too early.
 //!
-//! Publishes issued through the raw [`rumqttc::AsyncClient`] (bypassing the
-//! counting wrapper) are still tracked from the moment the event loop emits
-//! `Outgoing::Publish`; only their queued (pre-event-loop) stage is
-//! invisible to flush.
+//! Individual publishes can be tracked with a [`PublishAck`]. The transport
+//! does not report which packet id it assigned to a publish, but it emits
+//! `Outgoing::Publish` in request order: the client wrapper records an
+//! entry per publish in enqueue order, and the tracker pairs each
+//! `Outgoing::Publish` with the oldest entry. Publishes sent through
+//! [`HomieMQTTClient::raw_transport`](super::HomieMQTTClient::raw_transport)
+//! bypass this queue and shift the pairing, so they must not be mixed with
+//! tracked ones.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
 use std::time::Duration;
 
 use thiserror::Error;
-use tokio::sync::watch;
+use tokio::sync::{oneshot, watch};
 
 /// Error returned by flush operations when pending publishes were not
 /// acknowledged within the allowed wait time (e.g. the broker is dead).
@@ -35,26 +42,120 @@ use tokio::sync::watch;
 #[error("timed out waiting for pending MQTT publish acknowledgements")]
 pub struct FlushTimeout;
 
+/// Error of a [`PublishAck`] whose publish can no longer be acknowledged.
+#[derive(Debug, Error)]
+#[error("publish was dropped before the broker acknowledged it")]
+pub struct PublishNotAcked;
+
+/// Resolves once the broker acknowledged one specific publish (PubAck for
+/// QoS 1, PubComp for QoS 2; QoS 0 publishes resolve once sent).
+///
+/// Fails with [`PublishNotAcked`] when the publish was dropped from the
+/// offline buffer, the connection was closed for good or the client event
+/// loop ended. A connection loss alone does not fail it: the transport
+/// retransmits the publish after reconnecting. Returned by
+/// [`HomieMQTTClient::homie_publish_tracked`](super::HomieMQTTClient::homie_publish_tracked).
+#[derive(Debug)]
+pub struct PublishAck(oneshot::Receiver<()>);
+
+impl PublishAck {
+    /// Creates an acknowledgement future and the notifier resolving it.
+    pub fn new() -> (AckNotifier, Self) {
+        let (sender, receiver) = oneshot::channel();
+        (
+            AckNotifier(Arc::new(Mutex::new(Some(sender)))),
+            Self(receiver),
+        )
+    }
+}
+
+impl Future for PublishAck {
+    type Output = Result<(), PublishNotAcked>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0)
+            .poll(cx)
+            .map(|res| res.map_err(|_| PublishNotAcked))
+    }
+}
+
+/// Sending side of a [`PublishAck`], travelling with the publish. Clones
+/// share the notification; dropping all of them fails the `PublishAck`.
+#[derive(Debug, Clone)]
+pub struct AckNotifier(Arc<Mutex<Option<oneshot::Sender<()>>>>);
+
+impl AckNotifier {
+    fn notify(&self) {
+        if let Some(sender) = self.0.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            let _ = sender.send(());
+        }
+    }
+}
+
+impl PartialEq for AckNotifier {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for AckNotifier {}
+
+/// Entries of enqueued publishes, oldest first, waiting for their
+/// `Outgoing::Publish`.
+#[derive(Debug, Default)]
+struct AckQueue {
+    /// Held across recording an entry and enqueueing its publish, so the
+    /// entries are in the same order as the requests.
+    order: tokio::sync::Mutex<()>,
+    entries: Mutex<VecDeque<Option<AckNotifier>>>,
+}
+
+impl AckQueue {
+    fn entries(&self) -> MutexGuard<'_, VecDeque<Option<AckNotifier>>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
 /// Counts publishes that have been handed to the [`rumqttc::AsyncClient`]
 /// but not yet processed by its event loop. Incremented by the publishing
 /// client wrapper, decremented by [`PendingPublishTracker::record_publish`].
 #[derive(Debug, Clone)]
-pub struct QueuedPublishCounter(Arc<AtomicUsize>);
+pub struct QueuedPublishCounter {
+    queued: Arc<AtomicUsize>,
+    acks: Arc<AckQueue>,
+}
 
 impl QueuedPublishCounter {
     /// Records a publish about to be enqueued. Call **before** awaiting the
     /// enqueue so the event loop can never observe the request first.
     pub fn increment(&self) {
-        self.0.fetch_add(1, Ordering::SeqCst);
+        self.queued.fetch_add(1, Ordering::SeqCst);
     }
 
     /// Reverts an [`increment`](Self::increment) whose enqueue failed.
     /// Saturates at zero.
     pub fn decrement(&self) {
         let _ = self
-            .0
+            .queued
             .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| v.checked_sub(1));
     }
+
+    /// Runs `enqueue`, which hands one publish to the transport, and pairs
+    /// it with `ack` (`None` for untracked publishes). Every publish must go
+    /// through here to keep the pairing in request order.
+    pub async fn enqueue<E>(
+        &self,
+        ack: Option<AckNotifier>,
+        enqueue: impl Future<Output = Result<(), E>>,
+    ) -> Result<(), E> {
+        let _order = self.acks.order.lock().await;
+        self.acks.entries().push_back(ack);
+        let res = enqueue.await;
+        if res.is_err() {
+            self.acks.entries().pop_back();
+        }
+        res
+    }
 }
 
 /// Tracks packet ids of in-flight QoS>0 publishes (plus the shared queued
@@ -66,7 +167,13 @@ impl QueuedPublishCounter {
 #[derive(Debug)]
 pub struct PendingPublishTracker {
     pending: HashSet<u16>,
+    /// In flight when the connection was lost; the transport sends them
+    /// again after reconnecting.
+    retransmit: HashSet<u16>,
+    /// Notifiers of tracked in-flight publishes.
+    tickets: HashMap<u16, AckNotifier>,
     queued: Arc<AtomicUsize>,
+    acks: Arc<AckQueue>,
     count_tx: watch::Sender<usize>,
 }
 
@@ -78,7 +185,10 @@ impl PendingPublishTracker {
         (
             Self {
                 pending: HashSet::new(),
+                retransmit: HashSet::new(),
+                tickets: HashMap::new(),
                 queued: Arc::clone(&queued),
+                acks: Arc::default(),
                 count_tx,
             },
             PendingPublishObserver {
@@ -91,19 +201,35 @@ impl PendingPublishTracker {
     /// Returns the counter the publishing client wrapper must increment for
     /// every publish it enqueues.
     pub fn queued_counter(&self) -> QueuedPublishCounter {
-        QueuedPublishCounter(Arc::clone(&self.queued))
+        QueuedPublishCounter {
+            queued: Arc::clone(&self.queued),
+            acks: Arc::clone(&self.acks),
+        }
     }
 
     /// Records that the event loop emitted `Outgoing::Publish`: the request
-    /// left the queue (queued count decremented, saturating — retransmits
-    /// and uncounted raw publishes must not underflow) and, for QoS>0
-    /// (packet id != 0), is now awaiting broker acknowledgement.
+    /// left the queue (queued count decremented, saturating — uncounted raw
+    /// publishes must not underflow) and, for QoS>0 (packet id != 0), is now
+    /// awaiting broker acknowledgement. A retransmit of a publish that was
+    /// in flight at connection loss only goes back in flight.
     pub fn record_publish(&mut self, pkid: u16) {
+        if pkid != 0 && self.retransmit.remove(&pkid) {
+            self.pending.insert(pkid);
+            self.notify();
+            return;
+        }
         let _ = self
             .queued
             .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| v.checked_sub(1));
+        let ticket = self.acks.entries().pop_front().flatten();
         if pkid != 0 {
             self.pending.insert(pkid);
+            if let Some(ticket) = ticket {
+                self.tickets.insert(pkid, ticket);
+            }
+        } else if let Some(ticket) = ticket {
+            // QoS 0 is never acknowledged.
+            ticket.notify();
         }
         self.notify();
     }
@@ -111,6 +237,12 @@ impl PendingPublishTracker {
     /// Records a broker acknowledgement (PubAck for QoS 1, PubComp for
     /// QoS 2). Unknown packet ids are ignored.
     pub fn record_ack(&mut self, pkid: u16) {
+        // A QoS 2 publish acknowledged with PubRec before the connection
+        // loss only has its PubRel retransmitted.
+        self.retransmit.remove(&pkid);
+        if let Some(ticket) = self.tickets.remove(&pkid) {
+            ticket.notify();
+        }
         if self.pending.remove(&pkid) {
             self.notify();
         }
@@ -124,18 +256,23 @@ impl PendingPublishTracker {
     /// connection must not wedge a flush. The queued count is left alone —
     /// queued requests survive in rumqttc's request channel and are
     /// processed after reconnecting.
+    ///
+    /// The packet ids are remembered as retransmits, and tracked publishes
+    /// keep waiting for their acknowledgement.
     pub fn clear_in_flight(&mut self) {
-        if !self.pending.is_empty() {
-            self.pending.clear();
-        }
+        self.retransmit.extend(self.pending.drain());
         self.notify();
     }
 
     /// Clears everything (in-flight and queued). Call when the connection is
     /// shut down for good (outgoing disconnect): nothing can be acknowledged
-    /// anymore, so flush waiters must be released.
+    /// anymore, so flush waiters must be released and pending
+    /// [`PublishAck`]s fail.
     pub fn clear_all(&mut self) {
         self.pending.clear();
+        self.retransmit.clear();
+        self.tickets.clear();
+        self.acks.entries().clear();
         self.queued.store(0, Ordering::SeqCst);
         self.notify();
     }
@@ -154,6 +291,14 @@ impl PendingPublishTracker {
     }
 }
 
+impl Drop for PendingPublishTracker {
+    fn drop(&mut self) {
+        // The client event loop ended: queued tracked publishes fail
+        // instead of waiting forever.
+        self.acks.entries().clear();
+    }
+}
+
 /// Observer side of [`PendingPublishTracker`]: awaits the moment when no
 /// publish is queued or awaiting broker acknowledgement.
 #[derive(Debug, Clone)]
@@ -168,6 +313,16 @@ impl PendingPublishObserver {
         *self.rx.borrow() + self.queued.load(Ordering::SeqCst)
     }
 
+    /// Publishes handed to the transport but not yet seen by the event loop.
+    pub fn queued_count(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// QoS>0 publishes sent but not yet acknowledged by the broker.
+    pub fn in_flight_count(&self) -> usize {
+        *self.rx.borrow()
+    }
+
     /// Waits until all publishes issued **before** this call have left the
     /// request queue and been acknowledged by the broker.
     ///
@@ -230,7 +385,7 @@ mod tests {
     #[test]
     fn uncounted_raw_publish_does_not_underflow_queue() {
         let (mut tracker, observer) = PendingPublishTracker::new();
-        // No queued increment (raw AsyncClient publish or retransmit).
+        // No queued increment (raw transport publish or retransmit).
         tracker.record_publish(7);
         assert_eq!(observer.pending_count(), 1); // in-flight only
         tracker.record_ack(7);
@@ -281,6 +436,80 @@ mod tests {
         assert_eq!(observer.pending_count(), 0);
     }
 
+    /// Enqueues a publish the way `HomieMQTTClient` does.
+    async fn enqueue(counter: &QueuedPublishCounter, ack: Option<AckNotifier>) {
+        counter.increment();
+        counter
+            .enqueue(ack, async { Ok::<_, ()>(()) })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn tracked_publish_resolves_on_its_own_ack() {
+        let (mut tracker, _observer) = PendingPublishTracker::new();
+        let counter = tracker.queued_counter();
+        let (first, mut first_ack) = PublishAck::new();
+        let (second, second_ack) = PublishAck::new();
+        enqueue(&counter, Some(first)).await;
+        enqueue(&counter, None).await;
+        enqueue(&counter, Some(second)).await;
+        tracker.record_publish(1);
+        tracker.record_publish(2);
+        tracker.record_publish(3);
+
+        tracker.record_ack(3);
+        second_ack.await.unwrap();
+        tracker.record_ack(2);
+        assert!(poll_once(&mut first_ack).is_none());
+        tracker.record_ack(1);
+        first_ack.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tracked_publish_survives_retransmit() {
+        let (mut tracker, observer) = PendingPublishTracker::new();
+        let counter = tracker.queued_counter();
+        let (first, first_ack) = PublishAck::new();
+        let (second, second_ack) = PublishAck::new();
+        enqueue(&counter, Some(first)).await;
+        tracker.record_publish(1);
+        enqueue(&counter, Some(second)).await;
+        tracker.clear_in_flight(); // connection loss
+
+        // Retransmit of pkid 1, then the queued publish goes out as pkid 2.
+        tracker.record_publish(1);
+        assert_eq!(observer.queued_count(), 1);
+        tracker.record_publish(2);
+        assert_eq!(observer.pending_count(), 2);
+        tracker.record_ack(2);
+        second_ack.await.unwrap();
+        tracker.record_ack(1);
+        first_ack.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tracked_publish_fails_when_cleared() {
+        let (mut tracker, _observer) = PendingPublishTracker::new();
+        let counter = tracker.queued_counter();
+        let (sent, sent_ack) = PublishAck::new();
+        let (queued, queued_ack) = PublishAck::new();
+        enqueue(&counter, Some(sent)).await;
+        enqueue(&counter, Some(queued)).await;
+        tracker.record_publish(1);
+        tracker.clear_all();
+        assert!(sent_ack.await.is_err());
+        assert!(queued_ack.await.is_err());
+    }
+
+    fn poll_once(ack: &mut PublishAck) -> Option<Result<(), PublishNotAcked>> {
+        let waker = std::task::Waker::noop();
+        match Pin::new(ack).poll(&mut Context::from_waker(waker)) {
+            Poll::Ready(res) => Some(res),
+            Poll::Pending => None,
+        }
+    }
+
     #[tokio::test]
     async fn flush_returns_immediately_when_no_pending() {
         let (_tracker, observer) = PendingPublishTracker::new();
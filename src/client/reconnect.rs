@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use rand::{rng, RngExt};
+
+/// Controls how the homie client event loop waits between connection attempts.
+///
+/// After the `n`-th consecutive failed attempt the loop waits
+/// `initial_delay * multiplier^(n-1)`, capped at `max_delay`. With a non-zero
+/// `jitter` the delay is randomly shortened by up to that fraction, so many
+/// clients that lost the broker at the same moment do not reconnect in
+/// lockstep. The attempt counter is reset by every successful connect.
+///
+/// The default is a fixed 5 second delay without jitter, retrying forever.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Delay after the first failed attempt.
+    pub initial_delay: Duration,
+    /// Upper bound for the delay, before jitter is applied.
+    pub max_delay: Duration,
+    /// Growth factor applied per consecutive failed attempt (`>= 1.0`).
+    pub multiplier: f64,
+    /// Fraction (`0.0..=1.0`) by which each delay may randomly be shortened.
+    pub jitter: f64,
+    /// Number of consecutive failed attempts after which the client gives up.
+    /// The last failure is still reported as `HomieClientEvent::Error`,
+    /// followed by `Stop`, and the client task ends with
+    /// [`HomieClientError::RecoverableConnection`](super::HomieClientError::RecoverableConnection)
+    /// (returned by `HomieClientHandle::stop`), so giving up can be told
+    /// apart from a requested stop. `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::fixed(Duration::from_secs(5))
+    }
+}
+
+impl ReconnectPolicy {
+    /// Always wait `delay` between attempts.
+    pub fn fixed(delay: Duration) -> Self {
+        Self {
+            initial_delay: delay,
+            max_delay: delay,
+            multiplier: 1.0,
+            jitter: 0.0,
+            max_attempts: None,
+        }
+    }
+
+    /// Start with `initial_delay` and double it after each failed attempt up
+    /// to `max_delay`.
+    pub fn exponential(initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            max_delay,
+            multiplier: 2.0,
+            jitter: 0.0,
+            max_attempts: None,
+        }
+    }
+
+    // Builder methods
+
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Set the growth factor. Values below `1.0` are treated as `1.0`.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = if multiplier.is_finite() {
+            multiplier.max(1.0)
+        } else {
+            1.0
+        };
+        self
+    }
+
+    /// Set the jitter fraction. Values are clamped to `0.0..=1.0`.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = if jitter.is_finite() {
+            jitter.clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        self
+    }
+
+    pub fn max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Delay before the next attempt after `attempt` consecutive failures
+    /// (1-based), without jitter.
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let secs = self.initial_delay.as_secs_f64() * self.multiplier.max(1.0).powi(exponent);
+        if !secs.is_finite() || secs >= self.max_delay.as_secs_f64() {
+            self.max_delay
+        } else {
+            Duration::from_secs_f64(secs)
+        }
+    }
+
+    /// Delay before the next attempt after `attempt` consecutive failures
+    /// (1-based), with jitter applied.
+    pub fn next_delay(&self, attempt: u32) -> Duration {
+        let base = self.base_delay(attempt);
+        let jitter = if self.jitter.is_finite() {
+            self.jitter.clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        if jitter == 0.0 || base.is_zero() {
+            return base;
+        }
+        base.mul_f64(1.0 - rng().random_range(0.0..=jitter))
+    }
+
+    /// Returns `true` once `attempt` consecutive failures reach
+    /// [`max_attempts`](Self::max_attempts).
+    pub fn attempts_exhausted(&self, attempt: u32) -> bool {
+        self.max_attempts.is_some_and(|max| attempt >= max)
+    }
+}
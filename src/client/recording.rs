@@ -0,0 +1,310 @@
+//! Recording and replay of incoming MQTT traffic.
+//!
+//! A [`TrafficRecorder`] attached to the client options writes every
+//! incoming publish, with the time it was received, as one JSON object per
+//! line. A [`Recording`] loaded from such a file can be replayed without a
+//! broker: the messages run through the same parsing as in the live client
+//! and come out as [`HomieClientEvent`]s, so discovery and app logic can be
+//! rerun on traffic captured in the field.
+//!
+//! Line format:
+//!
+//! ```json
+//! {"timestamp":"2026-01-05T10:00:00.123Z","topic":"homie/5/dev/$state","payload":"ready","qos":"AtLeastOnce","retain":true}
+//! ```
+//!
+//! Payloads that are not valid UTF-8 are stored as `payload_bytes` (an array
+//! of numbers) instead of `payload`.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc as std_mpsc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use homie5::client::QoS;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use super::{
+    backpressure::EventSender, hub::EventSink, run::handle_message, ClientStats, EventHub,
+    HomieClientError, HomieClientEvent, HomieClientOptions, MqttMessage, PendingPublishTracker,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecordingError {
+    #[error("Recording I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid recording line {line}: {source}")]
+    Parse {
+        line: usize,
+        source: serde_json::Error,
+    },
+}
+
+/// One line of a recording.
+#[derive(Serialize, Deserialize)]
+struct RecordLine {
+    timestamp: DateTime<Utc>,
+    topic: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload_bytes: Option<Vec<u8>>,
+    qos: QoS,
+    retain: bool,
+}
+
+impl RecordLine {
+    fn new(timestamp: DateTime<Utc>, message: &MqttMessage) -> Self {
+        let (payload, payload_bytes) = match std::str::from_utf8(&message.payload) {
+            Ok(text) => (Some(text.to_owned()), None),
+            Err(_) => (None, Some(message.payload.to_vec())),
+        };
+        Self {
+            timestamp,
+            topic: message.topic.clone(),
+            payload,
+            payload_bytes,
+            qos: message.qos.clone(),
+            retain: message.retain,
+        }
+    }
+
+    fn into_recorded(self) -> RecordedMessage {
+        let payload = match (self.payload, self.payload_bytes) {
+            (Some(text), _) => Bytes::from(text),
+            (None, Some(bytes)) => Bytes::from(bytes),
+            (None, None) => Bytes::new(),
+        };
+        RecordedMessage {
+            timestamp: self.timestamp,
+            message: MqttMessage {
+                topic: self.topic,
+                payload,
+                qos: self.qos,
+                retain: self.retain,
+            },
+        }
+    }
+}
+
+/// How often buffered lines are written out at the latest.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+enum RecorderCommand {
+    Line(Vec<u8>),
+    Flush(oneshot::Sender<io::Result<()>>),
+}
+
+/// Writes incoming publishes to a JSON-lines file.
+///
+/// Cheap to clone; clones write to the same file. Lines are handed to a
+/// writer thread, so recording never blocks the client event loop on file
+/// I/O. The writer flushes at least once per second, on
+/// [`flush`](Self::flush) and when the last clone is dropped; the client
+/// event loop flushes when it ends.
+#[derive(Clone)]
+pub struct TrafficRecorder {
+    commands: std_mpsc::Sender<RecorderCommand>,
+}
+
+impl TrafficRecorder {
+    /// Records to `path`, truncating an existing file.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        Ok(Self::from_writer(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Records to any writer, which is moved to a writer thread.
+    pub fn from_writer(writer: impl Write + Send + 'static) -> Self {
+        let (commands, receiver) = std_mpsc::channel();
+        std::thread::Builder::new()
+            .name("homie-recorder".to_string())
+            .spawn(move || write_records(writer, receiver))
+            .expect("failed to spawn the recorder thread");
+        Self { commands }
+    }
+
+    /// Queues `message`, received now, for writing.
+    pub fn record(&self, message: &MqttMessage) -> Result<(), RecordingError> {
+        let mut line =
+            serde_json::to_vec(&RecordLine::new(Utc::now(), message)).map_err(io::Error::from)?;
+        line.push(b'\n');
+        self.commands
+            .send(RecorderCommand::Line(line))
+            .map_err(|_| writer_stopped())?;
+        Ok(())
+    }
+
+    /// Waits until every line recorded so far is written and flushed.
+    pub async fn flush(&self) -> Result<(), RecordingError> {
+        let (done, result) = oneshot::channel();
+        self.commands
+            .send(RecorderCommand::Flush(done))
+            .map_err(|_| writer_stopped())?;
+        Ok(result.await.map_err(|_| writer_stopped())??)
+    }
+}
+
+fn writer_stopped() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "recorder stopped after a write error",
+    )
+}
+
+/// Body of the writer thread. Ends on the first write error or once all
+/// recorders are dropped.
+fn write_records(mut writer: impl Write, commands: std_mpsc::Receiver<RecorderCommand>) {
+    let mut unflushed_since: Option<Instant> = None;
+    loop {
+        let timeout = unflushed_since.map_or(FLUSH_INTERVAL, |since| {
+            FLUSH_INTERVAL.saturating_sub(since.elapsed())
+        });
+        let result = match commands.recv_timeout(timeout) {
+            Ok(RecorderCommand::Line(line)) => {
+                unflushed_since.get_or_insert_with(Instant::now);
+                writer.write_all(&line)
+            }
+            Ok(RecorderCommand::Flush(done)) => {
+                unflushed_since = None;
+                match writer.flush() {
+                    Ok(()) => {
+                        let _ = done.send(Ok(()));
+                        Ok(())
+                    }
+                    Err(err) => {
+                        let _ = done.send(Err(io::Error::new(err.kind(), err.to_string())));
+                        Err(err)
+                    }
+                }
+            }
+            Err(std_mpsc::RecvTimeoutError::Timeout) => {
+                unflushed_since = None;
+                writer.flush()
+            }
+            Err(std_mpsc::RecvTimeoutError::Disconnected) => {
+                if let Err(err) = writer.flush() {
+                    log::warn!("HOMIE: Failed to write recording: {err}");
+                }
+                return;
+            }
+        };
+        if let Err(err) = result {
+            log::warn!("HOMIE: Failed to write recording, stopping the recorder: {err}");
+            return;
+        }
+        if unflushed_since.is_some_and(|since| since.elapsed() >= FLUSH_INTERVAL) {
+            unflushed_since = None;
+            if let Err(err) = writer.flush() {
+                log::warn!("HOMIE: Failed to write recording, stopping the recorder: {err}");
+                return;
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for TrafficRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TrafficRecorder(..)")
+    }
+}
+
+/// A message read from a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMessage {
+    /// When the client received the message.
+    pub timestamp: DateTime<Utc>,
+    pub message: MqttMessage,
+}
+
+/// How fast [`Recording::replay`] feeds the messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayPace {
+    /// Keep the time between messages as recorded.
+    #[default]
+    Original,
+    /// Send every message as soon as the consumer takes it.
+    AsFastAsPossible,
+}
+
+/// The messages of a recording, in the order they were received.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub messages: Vec<RecordedMessage>,
+}
+
+impl Recording {
+    /// Reads a recording written by [`TrafficRecorder`]. Empty lines are
+    /// skipped.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> Result<Self, RecordingError> {
+        let mut messages = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: RecordLine =
+                serde_json::from_str(&line).map_err(|source| RecordingError::Parse {
+                    line: index + 1,
+                    source,
+                })?;
+            messages.push(record.into_recorded());
+        }
+        Ok(Self { messages })
+    }
+
+    /// Feeds the recording to a new event channel, as the client event
+    /// loop would have: messages are parsed into Homie, meta and raw events
+    /// honoring `options.raw_topic_filters` and `options.backpressure`.
+    /// A [`HomieClientEvent::Stop`] follows the last message. No
+    /// `Connect` event is sent, and nothing is published anywhere.
+    ///
+    /// The task ends early with [`HomieClientError::ChannelClosed`] when the
+    /// receiver is dropped. Must be called within a tokio runtime.
+    pub fn replay(
+        self,
+        pace: ReplayPace,
+        options: HomieClientOptions,
+    ) -> (
+        JoinHandle<Result<(), HomieClientError>>,
+        Receiver<HomieClientEvent>,
+    ) {
+        let (pending_publishes, pending_publishes_observer) = PendingPublishTracker::new();
+        let stats = ClientStats::new(pending_publishes_observer);
+        let (sender, receiver) = mpsc::channel(options.channel_size);
+        let sink = EventSink::new(sender, EventHub::new(options.channel_size));
+        let sender = EventSender::new(
+            sink,
+            options.backpressure,
+            options.channel_size,
+            stats.clone(),
+        );
+        let raw_topic_filters = options.raw_topic_filters;
+
+        let handle = tokio::spawn(async move {
+            let _pending_publishes = pending_publishes;
+            let start = tokio::time::Instant::now();
+            let first = self.messages.first().map(|m| m.timestamp);
+            for recorded in self.messages {
+                if let (ReplayPace::Original, Some(first)) = (pace, first) {
+                    // Clock jumps in the recording never move backwards.
+                    let offset = (recorded.timestamp - first).to_std().unwrap_or_default();
+                    tokio::time::sleep_until(start + offset).await;
+                }
+                handle_message(&sender, &raw_topic_filters, &stats, recorded.message).await?;
+            }
+            sender.send(HomieClientEvent::Stop).await?;
+            Ok(())
+        });
+        (handle, receiver)
+    }
+}
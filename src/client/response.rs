@@ -0,0 +1,280 @@
+//! Correlation of a publish with the incoming message answering it.
+//!
+//! [`HomieMQTTClient::homie_request`](super::HomieMQTTClient::homie_request)
+//! registers a one-shot [`ResponseMatcher`] in the client's
+//! [`ResponseRegistry`] before publishing. The client event loop offers
+//! every incoming message to the registry; the first message that matches
+//! resolves the request. Matched messages are still delivered as regular
+//! client events.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use homie5::{PropertyRef, ToTopic};
+use rumqttc::mqttbytes;
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+use super::MqttMessage;
+
+/// Error of [`HomieMQTTClient::homie_request`](super::HomieMQTTClient::homie_request).
+#[derive(Debug, Error)]
+pub enum RequestError<E> {
+    #[error("Error publishing the request: {0}")]
+    Publish(E),
+    #[error("No matching response within {0:?}")]
+    Timeout(Duration),
+    #[error("The homie client event loop stopped before a response arrived")]
+    Closed,
+}
+
+type PayloadPredicate = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// Selects the incoming message that answers a request: a topic filter
+/// (MQTT wildcards allowed) and an optional payload condition.
+///
+/// The client must be subscribed to the response topic; the matcher only
+/// observes messages the broker delivers.
+#[derive(Clone)]
+pub struct ResponseMatcher {
+    topic_filter: String,
+    payload: Option<PayloadPredicate>,
+}
+
+impl fmt::Debug for ResponseMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseMatcher")
+            .field("topic_filter", &self.topic_filter)
+            .field("payload", &self.payload.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl ResponseMatcher {
+    /// Matches any message on `topic_filter`.
+    pub fn topic(topic_filter: impl Into<String>) -> Self {
+        Self {
+            topic_filter: topic_filter.into(),
+            payload: None,
+        }
+    }
+
+    /// Matches the value publish of `property`.
+    pub fn property_value(property: &PropertyRef) -> Self {
+        Self::topic(property.to_topic().build())
+    }
+
+    /// Matches the `$target` publish of `property`.
+    pub fn property_target(property: &PropertyRef) -> Self {
+        Self::topic(property.to_topic().add_attr("$target").build())
+    }
+
+    /// Additionally requires the payload to equal `payload`.
+    pub fn payload(self, payload: impl Into<Vec<u8>>) -> Self {
+        let payload = payload.into();
+        self.payload_matches(move |p| p == payload.as_slice())
+    }
+
+    /// Additionally requires `predicate` to accept the payload.
+    pub fn payload_matches(
+        mut self,
+        predicate: impl Fn(&[u8]) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.payload = Some(Arc::new(predicate));
+        self
+    }
+
+    pub fn matches(&self, message: &MqttMessage) -> bool {
+        mqttbytes::matches(&message.topic, &self.topic_filter)
+            && self.payload.as_ref().is_none_or(|p| p(&message.payload))
+    }
+}
+
+struct PendingResponse {
+    id: u64,
+    matcher: ResponseMatcher,
+    sender: oneshot::Sender<MqttMessage>,
+}
+
+#[derive(Default)]
+struct RegistryState {
+    pending: Vec<PendingResponse>,
+    next_id: u64,
+    closed: bool,
+}
+
+/// Shared registry of requests awaiting a response. Cloning yields another
+/// handle to the same registry.
+#[derive(Clone, Default)]
+pub struct ResponseRegistry(Arc<Mutex<RegistryState>>);
+
+impl fmt::Debug for ResponseRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseRegistry")
+            .field("pending", &self.len())
+            .finish()
+    }
+}
+
+impl ResponseRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, RegistryState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Number of requests awaiting a response.
+    pub fn len(&self) -> usize {
+        self.state().pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Registers `matcher`. The returned guard resolves with the first
+    /// matching message and unregisters the matcher when dropped.
+    pub fn register(&self, matcher: ResponseMatcher) -> PendingResponseGuard {
+        let (sender, receiver) = oneshot::channel();
+        let mut state = self.state();
+        let id = state.next_id;
+        state.next_id += 1;
+        // A closed registry drops the sender right away: the wait ends
+        // with `Closed`.
+        if !state.closed {
+            state.pending.push(PendingResponse {
+                id,
+                matcher,
+                sender,
+            });
+        }
+        PendingResponseGuard {
+            registry: self.clone(),
+            id,
+            receiver,
+        }
+    }
+
+    /// Resolves every pending request that `message` matches. Returns
+    /// whether any did.
+    pub fn resolve(&self, message: &MqttMessage) -> bool {
+        let mut state = self.state();
+        if state.pending.is_empty() {
+            return false;
+        }
+        let mut resolved = false;
+        let mut index = 0;
+        while index < state.pending.len() {
+            if state.pending[index].matcher.matches(message) {
+                let pending = state.pending.swap_remove(index);
+                resolved |= pending.sender.send(message.clone()).is_ok();
+            } else {
+                index += 1;
+            }
+        }
+        resolved
+    }
+
+    /// Fails all pending and future requests with
+    /// [`RequestError::Closed`]. Called when the client event loop ends.
+    pub fn close(&self) {
+        let mut state = self.state();
+        state.closed = true;
+        state.pending.clear();
+    }
+
+    fn remove(&self, id: u64) {
+        self.state().pending.retain(|p| p.id != id);
+    }
+
+    /// Closes the registry once the returned guard is dropped.
+    pub(crate) fn close_on_drop(&self) -> CloseOnDrop {
+        CloseOnDrop(self.clone())
+    }
+}
+
+pub(crate) struct CloseOnDrop(ResponseRegistry);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// A registered [`ResponseMatcher`], see [`ResponseRegistry::register`].
+pub struct PendingResponseGuard {
+    registry: ResponseRegistry,
+    id: u64,
+    receiver: oneshot::Receiver<MqttMessage>,
+}
+
+impl PendingResponseGuard {
+    /// Waits up to `timeout` for the matching message.
+    pub async fn wait<E>(mut self, timeout: Duration) -> Result<MqttMessage, RequestError<E>> {
+        match tokio::time::timeout(timeout, &mut self.receiver).await {
+            Ok(Ok(message)) => Ok(message),
+            Ok(Err(_)) => Err(RequestError::Closed),
+            Err(_) => Err(RequestError::Timeout(timeout)),
+        }
+    }
+}
+
+impl Drop for PendingResponseGuard {
+    fn drop(&mut self) {
+        self.registry.remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use homie5::client::QoS;
+
+    use super::*;
+
+    fn message(topic: &str, payload: &str) -> MqttMessage {
+        MqttMessage {
+            topic: topic.to_string(),
+            payload: Bytes::copy_from_slice(payload.as_bytes()),
+            qos: QoS::AtMostOnce,
+            retain: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolves_matching_message_once() {
+        let registry = ResponseRegistry::new();
+        let guard = registry.register(ResponseMatcher::topic("homie/5/dev/+/temp").payload("21"));
+        assert!(!registry.resolve(&message("homie/5/dev/node/temp", "20")));
+        assert!(registry.resolve(&message("homie/5/dev/node/temp", "21")));
+        assert!(registry.is_empty());
+        let response = guard.wait::<()>(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(response.payload, "21");
+    }
+
+    #[tokio::test]
+    async fn test_timeout_and_close() {
+        let registry = ResponseRegistry::new();
+        let guard = registry.register(ResponseMatcher::topic("a"));
+        assert!(matches!(
+            guard.wait::<()>(Duration::from_millis(10)).await,
+            Err(RequestError::Timeout(_))
+        ));
+        assert!(registry.is_empty());
+
+        let guard = registry.register(ResponseMatcher::topic("a"));
+        registry.close();
+        assert!(matches!(
+            guard.wait::<()>(Duration::from_secs(1)).await,
+            Err(RequestError::Closed)
+        ));
+        let guard = registry.register(ResponseMatcher::topic("a"));
+        assert!(matches!(
+            guard.wait::<()>(Duration::from_secs(1)).await,
+            Err(RequestError::Closed)
+        ));
+    }
+}
@@ -3,15 +3,126 @@ use std::time::Duration;
 #[cfg(feature = "ext-meta")]
 use homie5::extensions::meta::parse_meta_message;
 use homie5::parse_mqtt_message;
-use rumqttc::{AsyncClient, MqttOptions};
+use rumqttc::{mqttbytes, AsyncClient, MqttOptions};
 use tokio::sync::{
     mpsc::{self, Receiver},
     watch,
 };
 
 use super::{
-    HomieClientError, HomieClientEvent, HomieClientHandle, HomieMQTTClient, PendingPublishTracker,
+    backpressure::EventSender, hub::EventSink, status::ConnectionStatusTracker, BackpressurePolicy,
+    ClientStats, ErrorClass, ErrorClassifier, EventHub, FailoverEventLoop, HomieClientError,
+    HomieClientEvent, HomieClientHandle, HomieMQTTClient, MqttEventLoop, MqttMessage,
+    MqttTransport, OfflineBuffer, OfflineBufferPolicy, PendingPublishTracker, ReconnectPolicy,
+    SharedCredentialProvider, TrafficRecorder, TransportEvent, DEFAULT_MAX_PACKET_SIZE_OUTGOING,
 };
+use crate::connection::ConnectionState;
+
+/// Options of the homie client event loop itself, as opposed to the MQTT
+/// connection options.
+///
+/// Usually derived from a config via
+/// [`MqttClientConfig::to_client_options`](super::MqttClientConfig::to_client_options).
+#[derive(Debug, Clone)]
+pub struct HomieClientOptions {
+    /// Capacity of the request and event channels.
+    pub channel_size: usize,
+    /// Maximum time the client will retry after disconnect before giving up.
+    /// Default: `None` (retry forever).
+    pub max_disconnect: Option<Duration>,
+    /// Delays between reconnect attempts.
+    pub reconnect_policy: ReconnectPolicy,
+    /// Buffer publishes while disconnected (see [`OfflineBuffer`]).
+    /// Default: `None` (publishes queue up in the transport).
+    pub offline_buffer: Option<OfflineBufferPolicy>,
+    /// Outgoing packet size limit, used to batch (un)subscriptions. Must
+    /// not exceed the transport's limit.
+    /// Default: [`DEFAULT_MAX_PACKET_SIZE_OUTGOING`].
+    pub max_packet_size_outgoing: usize,
+    /// Topic filters whose non-Homie messages are forwarded as
+    /// [`HomieClientEvent::Raw`] instead of being logged as errors.
+    /// Default: empty.
+    pub raw_topic_filters: Vec<String>,
+    /// What to do when the event consumer falls behind.
+    /// Default: [`BackpressurePolicy::Block`].
+    pub backpressure: BackpressurePolicy,
+    /// Asked for fresh credentials before every connection attempt.
+    /// Default: `None` (the credentials of the MQTT options are used).
+    pub credential_provider: Option<SharedCredentialProvider>,
+    /// Decides which connection errors are fatal. Default: `None`
+    /// ([`ConnectionFailure::default_class`](super::ConnectionFailure::default_class);
+    /// auth failures stay recoverable with a credential provider, as fresh
+    /// credentials may fix them).
+    pub error_classifier: Option<ErrorClassifier>,
+    /// Writes every incoming publish to a recording (see [`Recording`](super::Recording)).
+    /// Default: `None`.
+    pub recorder: Option<TrafficRecorder>,
+}
+
+impl HomieClientOptions {
+    pub fn new(channel_size: usize) -> Self {
+        Self {
+            channel_size,
+            max_disconnect: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            offline_buffer: None,
+            max_packet_size_outgoing: DEFAULT_MAX_PACKET_SIZE_OUTGOING,
+            raw_topic_filters: Vec::new(),
+            backpressure: BackpressurePolicy::default(),
+            credential_provider: None,
+            error_classifier: None,
+            recorder: None,
+        }
+    }
+
+    pub fn max_disconnect(mut self, max_disconnect: Option<Duration>) -> Self {
+        self.max_disconnect = max_disconnect;
+        self
+    }
+
+    pub fn reconnect_policy(mut self, reconnect_policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = reconnect_policy;
+        self
+    }
+
+    pub fn offline_buffer(mut self, offline_buffer: Option<OfflineBufferPolicy>) -> Self {
+        self.offline_buffer = offline_buffer;
+        self
+    }
+
+    pub fn max_packet_size_outgoing(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size_outgoing = max_packet_size;
+        self
+    }
+
+    pub fn raw_topic_filters(mut self, raw_topic_filters: Vec<String>) -> Self {
+        self.raw_topic_filters = raw_topic_filters;
+        self
+    }
+
+    pub fn backpressure(mut self, backpressure: BackpressurePolicy) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    pub fn credential_provider(
+        mut self,
+        credential_provider: Option<SharedCredentialProvider>,
+    ) -> Self {
+        self.credential_provider = credential_provider;
+        self
+    }
+
+    pub fn error_classifier(mut self, error_classifier: Option<ErrorClassifier>) -> Self {
+        self.error_classifier = error_classifier;
+        self
+    }
+
+    pub fn recorder(mut self, recorder: Option<TrafficRecorder>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+}
 
 pub fn run_homie_client(
     mqttoptions: MqttOptions,
@@ -24,7 +135,7 @@ pub fn run_homie_client(
     ),
     HomieClientError,
 > {
-    run_homie_client_with_options(mqttoptions, channel_size, None)
+    run_homie_client_with_client_options(mqttoptions, HomieClientOptions::new(channel_size))
 }
 
 pub fn run_homie_client_with_options(
@@ -38,19 +149,218 @@ pub fn run_homie_client_with_options(
         Receiver<HomieClientEvent>,
     ),
     HomieClientError,
+> {
+    run_homie_client_with_client_options(
+        mqttoptions,
+        HomieClientOptions::new(channel_size).max_disconnect(max_disconnect),
+    )
+}
+
+/// Runs the homie client on the default `rumqttc` transport with the given
+/// event-loop options.
+pub fn run_homie_client_with_client_options(
+    mqttoptions: MqttOptions,
+    options: HomieClientOptions,
+) -> Result<
+    (
+        HomieClientHandle,
+        HomieMQTTClient,
+        Receiver<HomieClientEvent>,
+    ),
+    HomieClientError,
 > {
     log::trace!("Connecting to mqtt: {}", mqttoptions.client_id());
-    let (sender, receiver) = mpsc::channel(channel_size);
+    let (mqtt_client, eventloop) = AsyncClient::new(mqttoptions, options.channel_size);
+    run_homie_client_with_transport(mqtt_client, eventloop, options)
+}
+
+/// Like [`run_homie_client_with_client_options`], but with an ordered list
+/// of brokers: after a failed connection attempt the client switches to the
+/// next one (see [`FailoverEventLoop`]). All options should only differ in
+/// the broker address, see
+/// [`MqttClientConfig::to_failover_mqtt_options`](super::MqttClientConfig::to_failover_mqtt_options).
+pub fn run_homie_client_with_failover(
+    endpoints: Vec<MqttOptions>,
+    options: HomieClientOptions,
+) -> Result<
+    (
+        HomieClientHandle,
+        HomieMQTTClient,
+        Receiver<HomieClientEvent>,
+    ),
+    HomieClientError,
+> {
+    let first = endpoints.first().cloned().ok_or_else(|| {
+        HomieClientError::TransportConfig("no broker endpoints given".to_string())
+    })?;
+    log::trace!("Connecting to mqtt: {}", first.client_id());
+    let (mqtt_client, eventloop) = AsyncClient::new(first, options.channel_size);
+    run_homie_client_with_transport(
+        mqtt_client,
+        FailoverEventLoop::new(eventloop, endpoints),
+        options,
+    )
+}
+
+/// Parses an incoming message and forwards it as Homie, meta or raw event.
+pub(super) async fn handle_message<E: std::fmt::Debug + Send + 'static>(
+    sender: &EventSender<E>,
+    raw_topic_filters: &[String],
+    stats: &ClientStats,
+    p: MqttMessage,
+) -> Result<(), HomieClientError> {
+    stats.record_message_in(p.payload.len());
+    let homie_err = match parse_mqtt_message(&p.topic, &p.payload) {
+        Ok(event) => {
+            sender
+                .send_message(
+                    HomieClientEvent::HomieMessage {
+                        message: event,
+                        retain: p.retain,
+                    },
+                    &p.topic,
+                    p.retain,
+                )
+                .await?;
+            return Ok(());
+        }
+        Err(homie_err) => homie_err,
+    };
+
+    #[cfg(feature = "ext-meta")]
+    let (meta_failure, error) = match parse_meta_message(&p.topic, &p.payload) {
+        Ok(Some(meta_msg)) => {
+            sender
+                .send_message(HomieClientEvent::MetaMessage(meta_msg), &p.topic, p.retain)
+                .await?;
+            return Ok(());
+        }
+        Ok(None) => (false, format!("Homie parse error: {homie_err}")),
+        Err(meta_err) => (
+            true,
+            format!("Homie parse error: {homie_err}\n  Meta parse error: {meta_err}"),
+        ),
+    };
+    #[cfg(not(feature = "ext-meta"))]
+    let (meta_failure, error) = (false, format!("Homie parse error: {homie_err}"));
+
+    // Messages on opted-in topics are expected to be non-Homie; anything
+    // else is unexpected and logged as error.
+    if raw_topic_filters
+        .iter()
+        .any(|filter| mqttbytes::matches(&p.topic, filter))
+    {
+        sender
+            .send(HomieClientEvent::Raw {
+                topic: p.topic,
+                payload: p.payload,
+                retain: p.retain,
+                qos: p.qos,
+            })
+            .await?;
+        return Ok(());
+    }
+    log::error!(
+        "Error parsing MQTT message.\n  Topic: [{}]\n  Payload: [{:?}]\n  {}",
+        p.topic,
+        p.payload,
+        error,
+    );
+    if meta_failure {
+        stats.record_meta_parse_failure();
+    } else {
+        stats.record_homie_parse_failure();
+    }
+    Ok(())
+}
 
-    let (mqtt_client, mut eventloop) = AsyncClient::new(mqttoptions, channel_size);
+/// Handle, client and event receiver of a running homie client on
+/// transport `T` with connection error type `E`.
+pub type HomieClientParts<T, E> = (
+    HomieClientHandle,
+    HomieMQTTClient<T>,
+    Receiver<HomieClientEvent<E>>,
+);
+
+/// Runs the homie client event loop on top of any [`MqttTransport`] /
+/// [`MqttEventLoop`] pair.
+///
+/// [`run_homie_client`], [`run_homie_client_with_options`] and
+/// [`run_homie_client_with_client_options`] are thin wrappers that create the
+/// default `rumqttc` transport.
+pub fn run_homie_client_with_transport<T, L>(
+    transport: T,
+    mut eventloop: L,
+    options: HomieClientOptions,
+) -> Result<HomieClientParts<T, L::Error>, HomieClientError>
+where
+    T: MqttTransport,
+    L: MqttEventLoop,
+{
+    let HomieClientOptions {
+        channel_size,
+        max_disconnect,
+        reconnect_policy,
+        offline_buffer,
+        max_packet_size_outgoing,
+        raw_topic_filters,
+        backpressure,
+        credential_provider,
+        error_classifier,
+        recorder,
+    } = options;
     let (stop_sender, mut stop_receiver) = watch::channel(false);
     let (mut pending_publishes, pending_publishes_observer) = PendingPublishTracker::new();
-    let queued_counter = pending_publishes.queued_counter();
+    let stats = ClientStats::new(pending_publishes_observer.clone());
+
+    let hub = EventHub::new(channel_size).with_stats(stats.clone());
+    let (sender, receiver) = mpsc::channel(channel_size);
+    let sink = EventSink::new(sender, hub.clone());
+    let sender = EventSender::new(sink, backpressure, channel_size, stats.clone());
+    let mut client = HomieMQTTClient::new(transport, pending_publishes.queued_counter())
+        .with_max_packet_size(max_packet_size_outgoing)
+        .with_stats(stats.clone());
+    if let Some(policy) = offline_buffer {
+        client = client.with_offline_buffer(OfflineBuffer::new(
+            policy,
+            pending_publishes.queued_counter(),
+        ));
+    }
+    let replay_client = client.clone();
+    let loop_stats = stats.clone();
+    let responses = client.responses().close_on_drop();
+    let (status, status_receiver) = ConnectionStatusTracker::new();
 
     let handle = tokio::task::spawn(async move {
+        // Pending requests fail once the loop ends, however it ends.
+        let _responses = responses;
         let mut connected = false;
+        let mut connected_before = false;
         let mut first_disconnect_at: Option<tokio::time::Instant> = None;
+        let mut failed_attempts: u32 = 0;
+        let mut refresh_credentials = true;
+        // Set when a connection error ends the loop.
+        let mut exit_error = None;
         loop {
+            if let (true, Some(provider)) = (refresh_credentials, &credential_provider) {
+                let fetched = tokio::select! {
+                    fetched = provider.credentials() => fetched,
+                    _exit = stop_receiver.changed() => {
+                        if *stop_receiver.borrow() {
+                            log::trace!("Received stop signal. Exiting...");
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                match fetched {
+                    Ok(credentials) => eventloop.set_credentials(&credentials),
+                    Err(err) => log::error!(
+                        "HOMIE: Credential provider failed, connecting with the previous credentials: {err}"
+                    ),
+                }
+                refresh_credentials = false;
+            }
             let poll_res = tokio::select! {
                 poll_res = eventloop.poll() => poll_res,
                 _exit = stop_receiver.changed() => {
@@ -63,87 +373,105 @@ pub fn run_homie_client_with_options(
             };
 
             match poll_res {
-                Ok(event) => match &event {
-                    rumqttc::Event::Incoming(rumqttc::Packet::Publish(p)) => {
-                        match parse_mqtt_message(&p.topic, &p.payload) {
-                            Ok(event) => {
-                                sender.send(HomieClientEvent::HomieMessage(event)).await?;
-                            }
-                            Err(homie_err) => {
-                                #[cfg(feature = "ext-meta")]
-                                {
-                                    match parse_meta_message(&p.topic, &p.payload) {
-                                        Ok(Some(meta_msg)) => {
-                                            sender
-                                                .send(HomieClientEvent::MetaMessage(meta_msg))
-                                                .await?;
-                                        }
-                                        Ok(None) => {
-                                            log::error!(
-                                                "Error parsing MQTT message.\n  Topic: [{}]\n  Payload: [{:?}]\n  Homie parse error: {}",
-                                                p.topic,
-                                                p.payload,
-                                                homie_err,
-                                            );
-                                        }
-                                        Err(meta_err) => {
-                                            log::error!(
-                                                "Error parsing MQTT message.\n  Topic: [{}]\n  Payload: [{:?}]\n  Homie parse error: {}\n  Meta parse error: {}",
-                                                p.topic,
-                                                p.payload,
-                                                homie_err,
-                                                meta_err
-                                            );
-                                        }
-                                    }
-                                }
-                                #[cfg(not(feature = "ext-meta"))]
-                                {
-                                    log::error!(
-                                        "Error parsing MQTT message.\n  Topic: [{}]\n  Payload: [{:?}]\n  Homie parse error: {}",
-                                        p.topic,
-                                        p.payload,
-                                        homie_err,
-                                    );
-                                }
+                Ok(event) => match event {
+                    TransportEvent::Message(p) => {
+                        if let Some(recorder) = &recorder {
+                            if let Err(err) = recorder.record(&p) {
+                                log::warn!("HOMIE: Failed to record message on {}: {err}", p.topic);
                             }
                         }
+                        replay_client.responses().resolve(&p);
+                        handle_message(&sender, &raw_topic_filters, &loop_stats, p).await?;
                     }
-                    rumqttc::Event::Incoming(rumqttc::Incoming::ConnAck(_)) => {
-                        log::trace!("HOMIE: Connected");
+                    TransportEvent::Connected => {
+                        let endpoint = eventloop.endpoint();
+                        match &endpoint {
+                            Some(endpoint) => log::trace!("HOMIE: Connected to {endpoint}"),
+                            None => log::trace!("HOMIE: Connected"),
+                        }
                         connected = true;
+                        status.set_state(ConnectionState::Connected);
                         first_disconnect_at = None;
-                        sender.send(HomieClientEvent::Connect).await?;
+                        failed_attempts = 0;
+                        // The broker dropped our subscriptions with the old
+                        // session; replay them, then send what was buffered
+                        // while offline. This runs in a separate task: the
+                        // request queue is drained by this loop, so awaiting
+                        // it here could deadlock on a full queue.
+                        let replay = connected_before && !replay_client.subscriptions().is_empty();
+                        let drain = replay_client
+                            .offline_buffer()
+                            .is_some_and(|buffer| buffer.set_online());
+                        if replay || drain {
+                            let replay_client = replay_client.clone();
+                            tokio::spawn(async move {
+                                if replay {
+                                    if let Err(err) = replay_client.resubscribe_all().await {
+                                        log::error!("HOMIE: Failed to replay subscriptions: {err}");
+                                    }
+                                }
+                                if drain {
+                                    if let Err(err) = replay_client.drain_offline_buffer().await {
+                                        log::error!("HOMIE: Failed to flush offline buffer: {err}");
+                                    }
+                                }
+                            });
+                        }
+                        loop_stats.record_connected(connected_before);
+                        connected_before = true;
+                        sender.send(HomieClientEvent::Connect { endpoint }).await?;
                     }
                     // Pending-publish tracking: pkids are recorded on outgoing
                     // publish and released on broker acknowledgement so
                     // `HomieClientHandle::flush` can await an empty in-flight set.
-                    rumqttc::Event::Outgoing(rumqttc::Outgoing::Publish(pkid)) => {
-                        pending_publishes.record_publish(*pkid);
+                    TransportEvent::PublishSent(pkid) => {
+                        pending_publishes.record_publish(pkid);
                     }
-                    rumqttc::Event::Incoming(rumqttc::Incoming::PubAck(ack)) => {
-                        pending_publishes.record_ack(ack.pkid);
+                    TransportEvent::PublishAcked(pkid) => {
+                        pending_publishes.record_ack(pkid);
                     }
-                    rumqttc::Event::Incoming(rumqttc::Incoming::PubComp(comp)) => {
-                        pending_publishes.record_ack(comp.pkid);
-                    }
-                    rumqttc::Event::Outgoing(rumqttc::Outgoing::Disconnect) => {
+                    TransportEvent::Disconnected => {
                         log::trace!("HOMIE: Connection closed from our side.",);
+                        loop_stats.record_disconnected();
+                        status.set_state(ConnectionState::Disconnected);
                         // Nothing can be acknowledged after the disconnect —
                         // release any flush waiters instead of letting them
                         // run into their max_wait.
+                        if let Some(buffer) = replay_client.offline_buffer() {
+                            buffer.set_offline();
+                            buffer.clear();
+                        }
                         pending_publishes.clear_all();
                         sender.send(HomieClientEvent::Disconnect).await?;
 
                         break;
                     }
-                    _ => {}
+                    TransportEvent::Reason(reason) => {
+                        log::debug!("HOMIE: Broker reason: {:?}", reason);
+                        sender.send(HomieClientEvent::Reason(reason)).await?;
+                    }
+                    TransportEvent::Other => {}
                 },
 
                 Err(err) => {
+                    let endpoint = eventloop.endpoint();
+                    let failure = eventloop.describe_error(&err);
+                    status.record_error(failure.clone());
+                    status.set_state(ConnectionState::Disconnected);
+                    // The next poll reconnects.
+                    refresh_credentials = true;
+                    loop_stats.record_disconnected();
+                    if let Some(buffer) = replay_client.offline_buffer() {
+                        buffer.set_offline();
+                    }
                     if connected {
                         connected = false;
                         sender.send(HomieClientEvent::Disconnect).await?;
+                    } else {
+                        // The connection attempt failed: try the next broker,
+                        // if any. A dropped connection is retried on the same
+                        // broker first.
+                        eventloop.failover();
                     }
                     // Connection lost: rumqttc retransmits in-flight QoS>0
                     // publishes itself after reconnecting (re-emitted as
@@ -152,6 +480,32 @@ pub fn run_homie_client_with_options(
                     // request channel and are kept.
                     pending_publishes.clear_in_flight();
 
+                    let class = match &error_classifier {
+                        Some(classifier) => classifier.classify(&failure),
+                        None if credential_provider.is_some() && failure.is_auth_failure() => {
+                            ErrorClass::Recoverable
+                        }
+                        None => failure.default_class(),
+                    };
+                    if class == ErrorClass::Fatal {
+                        log::error!("HomieClient: Fatal connection error, stopping. {failure}");
+                        sender
+                            .send(HomieClientEvent::Fatal(failure.clone()))
+                            .await?;
+                        exit_error = Some(HomieClientError::connection(failure, class));
+                        break;
+                    }
+
+                    // Report every non-fatal failure, including the one that
+                    // makes the loop give up below.
+                    match endpoint {
+                        Some(endpoint) => {
+                            log::error!("HomieClient: Error connecting mqtt ({endpoint}). {err:#?}")
+                        }
+                        None => log::error!("HomieClient: Error connecting mqtt. {:#?}", err),
+                    }
+                    sender.send(HomieClientEvent::Error(err)).await?;
+
                     if first_disconnect_at.is_none() {
                         first_disconnect_at = Some(tokio::time::Instant::now());
                     }
@@ -161,27 +515,70 @@ pub fn run_homie_client_with_options(
                                 "MQTT broker unreachable for {:?}, giving up",
                                 since.elapsed()
                             );
+                            // Giving up after `max_disconnect` ends the task
+                            // with `Ok(())`, as it always did.
                             break;
                         }
                     }
+                    failed_attempts = failed_attempts.saturating_add(1);
+                    if reconnect_policy.attempts_exhausted(failed_attempts) {
+                        log::error!(
+                            "MQTT connection failed {} times in a row, giving up",
+                            failed_attempts
+                        );
+                        exit_error = Some(HomieClientError::connection(failure, class));
+                        break;
+                    }
 
-                    log::error!("HomieClient: Error connecting mqtt. {:#?}", err);
-                    sender.send(HomieClientEvent::Error(err)).await?;
-                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    let delay = reconnect_policy.next_delay(failed_attempts);
+                    log::debug!(
+                        "HomieClient: reconnect attempt {} scheduled in {:?}",
+                        failed_attempts,
+                        delay
+                    );
+                    sender
+                        .send(HomieClientEvent::ReconnectScheduled {
+                            attempt: failed_attempts,
+                            delay,
+                        })
+                        .await?;
+                    // Backoff delays can be long — stay responsive to stop(),
+                    // but only a change to `true` ends the delay early.
+                    let stopped = tokio::select! {
+                        _ = tokio::time::sleep(delay) => false,
+                        Ok(_) = stop_receiver.wait_for(|stop| *stop) => true,
+                    };
+                    if stopped {
+                        log::trace!("Received stop signal. Exiting...");
+                        break;
+                    }
                 }
             };
         }
+        loop_stats.record_disconnected();
+        status.set_state(ConnectionState::Disconnected);
+        if let Some(recorder) = &recorder {
+            if let Err(err) = recorder.flush().await {
+                log::warn!("HOMIE: Failed to flush the recording: {err}");
+            }
+        }
         sender.send(HomieClientEvent::Stop).await?;
         log::trace!("Exiting homie client eventloop...");
-        Ok(())
+        match exit_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
     });
     Ok((
         HomieClientHandle {
             handle,
             stop_sender,
             pending_publishes: pending_publishes_observer,
+            stats,
+            hub,
+            status: status_receiver,
         },
-        HomieMQTTClient::new(mqtt_client, queued_counter),
+        client,
         receiver,
     ))
 }
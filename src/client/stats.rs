@@ -0,0 +1,187 @@
+//! Connection and traffic counters of the homie client event loop.
+//!
+//! The event loop and [`HomieMQTTClient`](super::HomieMQTTClient) update a
+//! shared [`ClientStats`]; [`HomieClientHandle::stats`](super::HomieClientHandle::stats)
+//! and [`DeviceManager::stats`](crate::controller::DeviceManager::stats)
+//! return a [`ClientStatsSnapshot`]. Updating and reading only touches
+//! atomics (and a short lock for the connection time), so snapshots are
+//! cheap enough to poll from metrics exporters.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::PendingPublishObserver;
+
+/// Point-in-time copy of the client counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientStatsSnapshot {
+    /// Messages received from the broker.
+    pub messages_in: u64,
+    /// Messages published through [`HomieMQTTClient`](super::HomieMQTTClient)
+    /// and handed to the transport.
+    pub messages_out: u64,
+    /// Payload bytes received.
+    pub bytes_in: u64,
+    /// Payload bytes published.
+    pub bytes_out: u64,
+    /// Received messages that are neither Homie (nor meta) messages and
+    /// were not passed on as `HomieClientEvent::Raw`.
+    pub homie_parse_failures: u64,
+    /// Received messages on meta topics that failed to parse.
+    pub meta_parse_failures: u64,
+    /// Successful connects after the first one.
+    pub reconnects: u64,
+    /// Events dropped because the consumer fell behind (see
+    /// [`BackpressurePolicy`](super::BackpressurePolicy)).
+    pub dropped_events: u64,
+    /// Queued property values replaced by a newer value (see
+    /// [`BackpressurePolicy::CoalesceRetained`](super::BackpressurePolicy::CoalesceRetained)).
+    pub coalesced_events: u64,
+    /// Events not delivered to an [`EventHub`](super::EventHub) subscriber
+    /// because its channel was full, summed over all subscribers.
+    pub hub_dropped_events: u64,
+    /// Duration of the current connection, `None` while disconnected.
+    pub connected_for: Option<Duration>,
+    /// Total time connected, including the current connection.
+    pub connected_total: Duration,
+    /// Publishes not yet processed by the event loop (see
+    /// [`PendingPublishObserver::queued_count`]).
+    pub queued_publishes: usize,
+    /// QoS>0 publishes awaiting broker acknowledgement.
+    pub in_flight_publishes: usize,
+}
+
+#[derive(Debug, Default)]
+struct ConnectionTime {
+    since: Option<Instant>,
+    total: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    messages_in: AtomicU64,
+    messages_out: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    homie_parse_failures: AtomicU64,
+    meta_parse_failures: AtomicU64,
+    reconnects: AtomicU64,
+    dropped_events: AtomicU64,
+    coalesced_events: AtomicU64,
+    hub_dropped_events: AtomicU64,
+    connection_time: Mutex<ConnectionTime>,
+}
+
+/// Shared client counters. Cloning yields another handle to the same
+/// counters. Taking a [`snapshot`](Self::snapshot) only reads atomics, so it
+/// is cheap to poll from a metrics exporter.
+#[derive(Debug, Clone)]
+pub struct ClientStats {
+    counters: Arc<Counters>,
+    pending_publishes: PendingPublishObserver,
+}
+
+impl ClientStats {
+    pub fn new(pending_publishes: PendingPublishObserver) -> Self {
+        Self {
+            counters: Arc::default(),
+            pending_publishes,
+        }
+    }
+
+    fn connection_time(&self) -> MutexGuard<'_, ConnectionTime> {
+        self.counters
+            .connection_time
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn record_message_in(&self, payload_len: usize) {
+        self.counters.messages_in.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .bytes_in
+            .fetch_add(payload_len as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_message_out(&self, payload_len: usize) {
+        self.counters.messages_out.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .bytes_out
+            .fetch_add(payload_len as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_homie_parse_failure(&self) {
+        self.counters
+            .homie_parse_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_meta_parse_failure(&self) {
+        self.counters
+            .meta_parse_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dropped_event(&self) {
+        self.counters.dropped_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_coalesced_event(&self) {
+        self.counters
+            .coalesced_events
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_hub_dropped_event(&self) {
+        self.counters
+            .hub_dropped_events
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a CONNACK. `reconnect` is `false` for the first connect.
+    pub fn record_connected(&self, reconnect: bool) {
+        if reconnect {
+            self.counters.reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut time = self.connection_time();
+        if time.since.is_none() {
+            time.since = Some(Instant::now());
+        }
+    }
+
+    /// Records a connection loss or shutdown.
+    pub fn record_disconnected(&self) {
+        let mut time = self.connection_time();
+        if let Some(since) = time.since.take() {
+            time.total += since.elapsed();
+        }
+    }
+
+    pub fn snapshot(&self) -> ClientStatsSnapshot {
+        let (connected_for, connected_total) = {
+            let time = self.connection_time();
+            let current = time.since.map(|since| since.elapsed());
+            (current, time.total + current.unwrap_or_default())
+        };
+        let c = &self.counters;
+        ClientStatsSnapshot {
+            messages_in: c.messages_in.load(Ordering::Relaxed),
+            messages_out: c.messages_out.load(Ordering::Relaxed),
+            bytes_in: c.bytes_in.load(Ordering::Relaxed),
+            bytes_out: c.bytes_out.load(Ordering::Relaxed),
+            homie_parse_failures: c.homie_parse_failures.load(Ordering::Relaxed),
+            meta_parse_failures: c.meta_parse_failures.load(Ordering::Relaxed),
+            reconnects: c.reconnects.load(Ordering::Relaxed),
+            dropped_events: c.dropped_events.load(Ordering::Relaxed),
+            coalesced_events: c.coalesced_events.load(Ordering::Relaxed),
+            hub_dropped_events: c.hub_dropped_events.load(Ordering::Relaxed),
+            connected_for,
+            connected_total,
+            queued_publishes: self.pending_publishes.queued_count(),
+            in_flight_publishes: self.pending_publishes.in_flight_count(),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Connection state owned by the homie client event loop.
+//!
+//! The event loop drives a [`ConnectionState`] and publishes it, together
+//! with the time of the last transition and the last connection error, on a
+//! `watch` channel. [`HomieClientHandle::connection_status`](super::HomieClientHandle::connection_status)
+//! and [`DeviceManager::connection_status`](crate::controller::DeviceManager::connection_status)
+//! hand out receivers, so components can check the status at any time
+//! without consuming the event stream. The channel closes when the event
+//! loop ends.
+
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use super::ConnectionFailure;
+use crate::connection::ConnectionState;
+
+/// Current connection status of a homie client, maintained by the client
+/// task and published through
+/// [`HomieClientHandle::connection_status`](super::HomieClientHandle::connection_status).
+/// Read it with `borrow()` or wait for a change with `changed()` /
+/// `wait_for(..)` without touching the event receiver.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    /// When `state` was entered.
+    pub since: Instant,
+    /// The most recent connection error, kept after reconnecting.
+    pub last_error: Option<ConnectionFailure>,
+}
+
+impl ConnectionStatus {
+    fn new() -> Self {
+        Self {
+            state: ConnectionState::Init,
+            since: Instant::now(),
+            last_error: None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state == ConnectionState::Connected
+    }
+
+    /// Time since the last state transition.
+    pub fn elapsed(&self) -> Duration {
+        self.since.elapsed()
+    }
+}
+
+/// Sending side of the status channel, held by the event loop.
+pub(crate) struct ConnectionStatusTracker(watch::Sender<ConnectionStatus>);
+
+impl ConnectionStatusTracker {
+    pub fn new() -> (Self, watch::Receiver<ConnectionStatus>) {
+        let (sender, receiver) = watch::channel(ConnectionStatus::new());
+        (Self(sender), receiver)
+    }
+
+    pub fn set_state(&self, state: ConnectionState) {
+        self.0.send_if_modified(|status| {
+            if status.state == state {
+                return false;
+            }
+            status.state.change_state(state);
+            status.since = Instant::now();
+            true
+        });
+    }
+
+    pub fn record_error(&self, failure: ConnectionFailure) {
+        self.0
+            .send_modify(|status| status.last_error = Some(failure));
+    }
+}
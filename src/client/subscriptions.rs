@@ -0,0 +1,137 @@
+//! Reference-counted registry of the topic filters a client is subscribed to.
+//!
+//! MQTT brokers forget subscriptions of a clean session when the connection
+//! drops. [`HomieMQTTClient`](super::HomieMQTTClient) records every filter it
+//! subscribes to here, and the client event loop re-issues all registered
+//! filters after each reconnect, so consumers such as
+//! [`HomieDiscovery`](crate::controller::HomieDiscovery) keep receiving
+//! messages without rebuilding their state on `Connect`.
+//!
+//! Filters are reference counted: independent components may subscribe to
+//! the same filter, and the broker subscription is only dropped once the
+//! last of them unsubscribed.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use homie5::client::QoS;
+
+#[derive(Debug)]
+struct Entry {
+    qos: QoS,
+    refs: usize,
+}
+
+/// Shared registry of active subscriptions, replayed after every reconnect.
+/// Each filter counts its subscribers; it stays subscribed until the last
+/// of them unsubscribed. Cloning yields another handle to the same registry.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionRegistry(Arc<Mutex<HashMap<String, Entry>>>);
+
+impl SubscriptionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+        // The map stays consistent even if a holder panicked.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds a reference to `topic`. The latest `qos` wins. Returns `true`
+    /// when this is the first reference.
+    pub fn acquire(&self, topic: &str, qos: QoS) -> bool {
+        let mut entries = self.entries();
+        match entries.get_mut(topic) {
+            Some(entry) => {
+                entry.qos = qos;
+                entry.refs += 1;
+                false
+            }
+            None => {
+                entries.insert(topic.to_owned(), Entry { qos, refs: 1 });
+                true
+            }
+        }
+    }
+
+    /// Drops a reference to `topic`. Returns `true` when the broker
+    /// subscription should be removed: the last reference is gone, or the
+    /// topic was never registered (e.g. subscribed through the raw client).
+    pub fn release(&self, topic: &str) -> bool {
+        let mut entries = self.entries();
+        match entries.get_mut(topic) {
+            Some(entry) if entry.refs > 1 => {
+                entry.refs -= 1;
+                false
+            }
+            Some(_) => {
+                entries.remove(topic);
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Number of references held for `topic` (`0` when not subscribed).
+    pub fn ref_count(&self, topic: &str) -> usize {
+        self.entries().get(topic).map_or(0, |e| e.refs)
+    }
+
+    /// All registered filters with their QoS, in no particular order.
+    pub fn snapshot(&self) -> Vec<(String, QoS)> {
+        self.entries()
+            .iter()
+            .map(|(topic, entry)| (topic.clone(), entry.qos.clone()))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_acquire_and_last_release_report_broker_changes() {
+        let registry = SubscriptionRegistry::new();
+        assert!(registry.acquire("homie/5/+/$state", QoS::AtLeastOnce));
+        assert!(!registry.acquire("homie/5/+/$state", QoS::AtLeastOnce));
+        assert_eq!(registry.ref_count("homie/5/+/$state"), 2);
+
+        assert!(!registry.release("homie/5/+/$state"));
+        assert!(registry.release("homie/5/+/$state"));
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn releasing_unknown_topic_unsubscribes() {
+        let registry = SubscriptionRegistry::new();
+        assert!(registry.release("homie/5/dev/#"));
+        assert_eq!(registry.ref_count("homie/5/dev/#"), 0);
+    }
+
+    #[test]
+    fn snapshot_keeps_latest_qos() {
+        let registry = SubscriptionRegistry::new();
+        registry.acquire("a", QoS::AtMostOnce);
+        registry.acquire("a", QoS::ExactlyOnce);
+        registry.acquire("b", QoS::AtLeastOnce);
+        let mut snapshot = registry.snapshot();
+        snapshot.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            snapshot,
+            vec![
+                ("a".to_string(), QoS::ExactlyOnce),
+                ("b".to_string(), QoS::AtLeastOnce)
+            ]
+        );
+    }
+}
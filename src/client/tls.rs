@@ -0,0 +1,394 @@
+//! TLS material loading for [`MqttClientConfig`](super::MqttClientConfig).
+//!
+//! Certificates and keys can come from files or from memory, PEM or DER
+//! encoded, or from a password protected PKCS#12 bundle (see [`TlsSource`]).
+//! The resulting rustls client config is handed to `rumqttc` as [`TlsConfiguration::Rustls`], which also allows ALPN and
+//! an insecure no-verification mode that the plain `rumqttc` TLS options do
+//! not offer. The SNI cannot be overridden: `rumqttc` derives it from the
+//! broker host it connects to.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use p12_keystore::{KeyStore, KeyStoreEntry};
+use rumqttc::tokio_rustls::rustls::{
+    self,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::CryptoProvider,
+    pki_types::{
+        pem::PemObject, CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime,
+    },
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use rumqttc::TlsConfiguration;
+use thiserror::Error;
+
+/// Where a certificate or key is loaded from.
+///
+/// Set through [`MqttClientConfig::ca`](super::MqttClientConfig::ca),
+/// [`client_cert`](super::MqttClientConfig::client_cert) and
+/// [`client_key`](super::MqttClientConfig::client_key), which take
+/// precedence over the `*_path` settings. Without a CA the platform root
+/// certificates are trusted. A client certificate needs a matching key and
+/// vice versa, except for a PKCS#12 bundle, which provides both.
+#[derive(Clone, PartialEq, Eq)]
+pub enum TlsSource {
+    /// File with PEM or DER content (detected from the content).
+    File(PathBuf),
+    /// In-memory PEM. May contain several certificates.
+    Pem(Vec<u8>),
+    /// In-memory DER: a single certificate or a PKCS#1, PKCS#8 or SEC1 key.
+    Der(Vec<u8>),
+    /// PKCS#12 (`.p12` / `.pfx`) bundle, decrypted with `password`.
+    ///
+    /// As client certificate it provides both the certificate chain and the
+    /// private key, so no separate client key is needed. As CA, every
+    /// certificate in the bundle is trusted.
+    Pkcs12 { data: Pkcs12Data, password: String },
+}
+
+/// Where a PKCS#12 bundle is loaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pkcs12Data {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+impl TlsSource {
+    /// A PKCS#12 bundle from a file or from memory.
+    pub fn pkcs12(data: impl Into<Pkcs12Data>, password: impl Into<String>) -> Self {
+        Self::Pkcs12 {
+            data: data.into(),
+            password: password.into(),
+        }
+    }
+
+    fn is_pkcs12(&self) -> bool {
+        matches!(self, Self::Pkcs12 { .. })
+    }
+
+    fn load(&self, item: TlsItem) -> Result<Encoded, TlsError> {
+        match self {
+            Self::File(path) => {
+                let data = std::fs::read(path).map_err(|source| TlsError::Read {
+                    item,
+                    path: path.clone(),
+                    source,
+                })?;
+                Ok(if looks_like_pem(&data) {
+                    Encoded::Pem(data)
+                } else {
+                    Encoded::Der(data)
+                })
+            }
+            Self::Pem(data) => Ok(Encoded::Pem(data.clone())),
+            Self::Der(data) => Ok(Encoded::Der(data.clone())),
+            Self::Pkcs12 { data, password } => Ok(Encoded::Pkcs12(data.decode(item, password)?)),
+        }
+    }
+
+    fn certificates(&self, item: TlsItem) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+        let certs = match self.load(item)? {
+            Encoded::Pem(data) => CertificateDer::pem_slice_iter(&data)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|source| TlsError::Pem { item, source })?,
+            Encoded::Der(data) => vec![CertificateDer::from(data)],
+            // The client certificate is the chain of the bundle's key, a CA
+            // is every certificate in it.
+            Encoded::Pkcs12(store) => match item {
+                TlsItem::Ca => store
+                    .entries()
+                    .flat_map(|(_, entry)| match entry {
+                        KeyStoreEntry::PrivateKeyChain(chain) => chain.chain().to_vec(),
+                        KeyStoreEntry::Certificate(cert) => vec![cert.clone()],
+                    })
+                    .map(|cert| CertificateDer::from(cert.as_der().to_vec()))
+                    .collect(),
+                _ => store
+                    .private_key_chain()
+                    .map(|(_, chain)| chain.chain())
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|cert| CertificateDer::from(cert.as_der().to_vec()))
+                    .collect(),
+            },
+        };
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificate(item));
+        }
+        Ok(certs)
+    }
+
+    fn private_key(&self) -> Result<PrivateKeyDer<'static>, TlsError> {
+        let item = TlsItem::ClientKey;
+        match self.load(item)? {
+            Encoded::Pem(data) => PrivateKeyDer::from_pem_slice(&data).map_err(|e| match e {
+                rustls::pki_types::pem::Error::NoItemsFound => TlsError::NoPrivateKey,
+                source => TlsError::Pem { item, source },
+            }),
+            Encoded::Der(data) => PrivateKeyDer::try_from(data).map_err(TlsError::InvalidKey),
+            // PKCS#12 bundles store keys as PKCS#8.
+            Encoded::Pkcs12(store) => store
+                .private_key_chain()
+                .map(|(_, chain)| PrivatePkcs8KeyDer::from(chain.key().to_vec()).into())
+                .ok_or(TlsError::NoPrivateKey),
+        }
+    }
+}
+
+impl std::fmt::Debug for TlsSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+            Self::Pem(data) => f.debug_tuple("Pem").field(data).finish(),
+            Self::Der(data) => f.debug_tuple("Der").field(data).finish(),
+            Self::Pkcs12 { data, .. } => f
+                .debug_struct("Pkcs12")
+                .field("data", data)
+                .field("password", &"***")
+                .finish(),
+        }
+    }
+}
+
+impl From<PathBuf> for TlsSource {
+    fn from(path: PathBuf) -> Self {
+        Self::File(path)
+    }
+}
+
+impl From<&Path> for TlsSource {
+    fn from(path: &Path) -> Self {
+        Self::File(path.to_path_buf())
+    }
+}
+
+impl Pkcs12Data {
+    fn decode(&self, item: TlsItem, password: &str) -> Result<KeyStore, TlsError> {
+        let read;
+        let data = match self {
+            Self::File(path) => {
+                read = std::fs::read(path).map_err(|source| TlsError::Read {
+                    item,
+                    path: path.clone(),
+                    source,
+                })?;
+                &read
+            }
+            Self::Bytes(data) => data,
+        };
+        KeyStore::from_pkcs12(data, password).map_err(|err| match err {
+            // A failed integrity check or padding of the decrypted data: the
+            // key derived from the password is wrong.
+            p12_keystore::error::Error::MacError(_) | p12_keystore::error::Error::UnpadError => {
+                TlsError::Pkcs12WrongPassword(item)
+            }
+            source => TlsError::Pkcs12Malformed { item, source },
+        })
+    }
+}
+
+impl From<PathBuf> for Pkcs12Data {
+    fn from(path: PathBuf) -> Self {
+        Self::File(path)
+    }
+}
+
+impl From<&Path> for Pkcs12Data {
+    fn from(path: &Path) -> Self {
+        Self::File(path.to_path_buf())
+    }
+}
+
+impl From<Vec<u8>> for Pkcs12Data {
+    fn from(data: Vec<u8>) -> Self {
+        Self::Bytes(data)
+    }
+}
+
+enum Encoded {
+    Pem(Vec<u8>),
+    Der(Vec<u8>),
+    Pkcs12(KeyStore),
+}
+
+fn looks_like_pem(data: &[u8]) -> bool {
+    data.windows(11).any(|w| w == b"-----BEGIN ")
+}
+
+/// The piece of TLS material an error refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsItem {
+    Ca,
+    ClientCert,
+    ClientKey,
+}
+
+impl std::fmt::Display for TlsItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Ca => "CA certificate",
+            Self::ClientCert => "client certificate",
+            Self::ClientKey => "client key",
+        })
+    }
+}
+
+/// Error loading TLS material or building the TLS client config, reported
+/// as [`HomieClientError::TlsConfig`](super::HomieClientError::TlsConfig).
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("failed to read {item} '{}': {source}", path.display())]
+    Read {
+        item: TlsItem,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid PEM in {item}: {source}")]
+    Pem {
+        item: TlsItem,
+        source: rustls::pki_types::pem::Error,
+    },
+    #[error("no certificate found in {0}")]
+    NoCertificate(TlsItem),
+    #[error("no private key found in client key")]
+    NoPrivateKey,
+    #[error("invalid DER client key: {0}")]
+    InvalidKey(&'static str),
+    #[error("wrong password for PKCS#12 {0}")]
+    Pkcs12WrongPassword(TlsItem),
+    #[error("invalid PKCS#12 bundle in {item}: {source}")]
+    Pkcs12Malformed {
+        item: TlsItem,
+        source: p12_keystore::error::Error,
+    },
+    #[error("client certificate requires a client key")]
+    MissingClientKey,
+    #[error("client key requires a client certificate")]
+    MissingClientCert,
+    #[error("no trusted root certificates: no CA given and none found in the platform store")]
+    NoRootCertificates,
+    #[error("rustls: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+/// TLS settings of a [`MqttClientConfig`](super::MqttClientConfig), resolved
+/// to sources.
+pub(super) struct TlsMaterial<'a> {
+    pub ca: Option<TlsSource>,
+    pub client_cert: Option<TlsSource>,
+    pub client_key: Option<TlsSource>,
+    pub alpn_protocols: &'a [Vec<u8>],
+    pub insecure_skip_verify: bool,
+}
+
+impl TlsMaterial<'_> {
+    pub fn to_tls_configuration(&self) -> Result<TlsConfiguration, TlsError> {
+        let roots = if self.insecure_skip_verify {
+            RootCertStore::empty()
+        } else {
+            self.root_store()?
+        };
+        let builder = ClientConfig::builder().with_root_certificates(roots.clone());
+        // A PKCS#12 bundle carries both the client certificate and its key.
+        let client_cert = self
+            .client_cert
+            .as_ref()
+            .or_else(|| self.client_key.as_ref().filter(|key| key.is_pkcs12()));
+        let client_key = self
+            .client_key
+            .as_ref()
+            .or_else(|| self.client_cert.as_ref().filter(|cert| cert.is_pkcs12()));
+        let mut config = match (client_cert, client_key) {
+            (Some(cert), Some(key)) => builder.with_client_auth_cert(
+                cert.certificates(TlsItem::ClientCert)?,
+                key.private_key()?,
+            )?,
+            (Some(_), None) => return Err(TlsError::MissingClientKey),
+            (None, Some(_)) => return Err(TlsError::MissingClientCert),
+            (None, None) => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = self.alpn_protocols.to_vec();
+
+        let provider = config.crypto_provider().clone();
+        if self.insecure_skip_verify {
+            log::warn!("TLS certificate verification is disabled");
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoVerification(provider)));
+        }
+        Ok(TlsConfiguration::Rustls(Arc::new(config)))
+    }
+
+    fn root_store(&self) -> Result<RootCertStore, TlsError> {
+        let mut roots = RootCertStore::empty();
+        match &self.ca {
+            Some(ca) => {
+                for cert in ca.certificates(TlsItem::Ca)? {
+                    roots.add(cert)?;
+                }
+            }
+            None => {
+                let native = rustls_native_certs::load_native_certs();
+                for err in &native.errors {
+                    log::warn!("Failed to load platform certificate: {err}");
+                }
+                roots.add_parsable_certificates(native.certs);
+            }
+        }
+        if roots.is_empty() {
+            return Err(TlsError::NoRootCertificates);
+        }
+        Ok(roots)
+    }
+}
+
+/// Accepts any broker certificate. Handshake signatures are still checked,
+/// so the connection is encrypted, but not authenticated.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
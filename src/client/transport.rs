@@ -0,0 +1,334 @@
+//! Transport abstraction between the Homie client layer and an MQTT client
+//! library.
+//!
+//! A transport is split into two halves, mirroring how MQTT client libraries
+//! are usually structured:
+//!
+//! - [`MqttTransport`] — the cheaply clonable, outgoing side (publish,
+//!   subscribe, unsubscribe, disconnect). [`HomieMQTTClient`](super::HomieMQTTClient)
+//!   wraps it.
+//! - [`MqttEventLoop`] — the incoming side that drives the connection and
+//!   yields [`TransportEvent`]s. [`run_homie_client_with_transport`](super::run_homie_client_with_transport)
+//!   polls it.
+//!
+//! `rumqttc` is the default implementation ([`rumqttc::AsyncClient`] and
+//! [`rumqttc::EventLoop`]). Alternative clients, loopback transports for tests
+//! or recording proxies can be plugged in by implementing both traits.
+
+use std::future::Future;
+
+use bytes::Bytes;
+use homie5::client::QoS;
+use rumqttc::{
+    AsyncClient, ClientError, ConnectReturnCode, ConnectionError, Event, EventLoop, Incoming,
+    Outgoing,
+};
+
+use super::{
+    BrokerEndpoint, ConnectionFailure, ConnectionFailureKind, Credentials, HomieMQTTClient,
+};
+
+/// Outgoing half of an MQTT connection.
+///
+/// Implementations only enqueue requests; the paired [`MqttEventLoop`]
+/// performs the actual network I/O.
+pub trait MqttTransport: Clone + Send + Sync + 'static {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    fn publish(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    fn subscribe(
+        &self,
+        topic: String,
+        qos: QoS,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    fn unsubscribe(&self, topic: String) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    fn disconnect(&self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Subscribe to several filters with a single SUBSCRIBE packet.
+    ///
+    /// The caller keeps the packet within the outgoing size limit. The
+    /// default implementation sends one [`subscribe`](Self::subscribe) per
+    /// filter.
+    fn subscribe_many(
+        &self,
+        topics: Vec<(String, QoS)>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async move {
+            for (topic, qos) in topics {
+                self.subscribe(topic, qos).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Unsubscribe from several filters with a single UNSUBSCRIBE packet.
+    ///
+    /// The default implementation sends one
+    /// [`unsubscribe`](Self::unsubscribe) per filter. Both `rumqttc`
+    /// transports keep this default, since `rumqttc` offers no multi-topic
+    /// UNSUBSCRIBE, so e.g. `unsubscribe_props` for a large device still
+    /// sends one packet per property there; only custom transports benefit
+    /// from overriding it.
+    fn unsubscribe_many(
+        &self,
+        topics: Vec<String>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async move {
+            for topic in topics {
+                self.unsubscribe(topic).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Publish with MQTT 5 user properties attached.
+    ///
+    /// Transports without user property support (MQTT 3.1.1) drop the
+    /// properties and fall back to [`publish`](Self::publish).
+    fn publish_with_user_properties(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+        user_properties: Vec<(String, String)>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        if !user_properties.is_empty() {
+            log::debug!("Transport does not support user properties, dropping them for [{topic}]");
+        }
+        self.publish(topic, qos, retain, payload)
+    }
+}
+
+/// Incoming half of an MQTT connection: the stream of events that drives it.
+///
+/// [`poll`](Self::poll) is called in a loop. An `Err` reports a connection
+/// failure; polling again is expected to reconnect.
+pub trait MqttEventLoop: Send + 'static {
+    type Error: std::fmt::Debug + Send + 'static;
+
+    fn poll(&mut self) -> impl Future<Output = Result<TransportEvent, Self::Error>> + Send;
+
+    /// Broker the event loop connects (or is connected) to, reported with
+    /// [`HomieClientEvent::Connect`](super::HomieClientEvent::Connect).
+    /// `None` when unknown.
+    fn endpoint(&self) -> Option<BrokerEndpoint> {
+        None
+    }
+
+    /// Called after a failed connection attempt, before the reconnect
+    /// delay. Event loops with several brokers switch to the next one; the
+    /// default does nothing.
+    fn failover(&mut self) {}
+
+    /// Credentials for the next connection attempt, from the
+    /// [`CredentialProvider`](super::CredentialProvider). The default
+    /// ignores them.
+    fn set_credentials(&mut self, _credentials: &Credentials) {}
+
+    /// Describes `err` for the [`ErrorClassifier`](super::ErrorClassifier).
+    /// The default reports every error as
+    /// [`ConnectionFailureKind::Other`], which is recoverable.
+    fn describe_error(&self, err: &Self::Error) -> ConnectionFailure {
+        ConnectionFailure::new(ConnectionFailureKind::Other, format!("{err:?}"))
+    }
+}
+
+/// A PUBLISH received from the broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Bytes,
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+/// Library-agnostic event yielded by an [`MqttEventLoop`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportEvent {
+    /// An incoming publish.
+    Message(MqttMessage),
+    /// The broker accepted the connection (CONNACK).
+    Connected,
+    /// A publish left the request queue and was written to the connection.
+    /// Carries the packet id (`0` for QoS 0).
+    PublishSent(u16),
+    /// The broker acknowledged a publish (PubAck for QoS 1, PubComp for
+    /// QoS 2).
+    PublishAcked(u16),
+    /// A disconnect requested through [`MqttTransport::disconnect`] was sent;
+    /// the connection is closed for good.
+    Disconnected,
+    /// Reason codes reported by the broker (MQTT 5 transports only).
+    Reason(BrokerReason),
+    /// Any other protocol traffic (pings, subscription acks, ...).
+    Other,
+}
+
+/// MQTT 5 reason code as defined by the specification.
+///
+/// Values below `0x80` indicate success, everything else a failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReasonCode(pub u8);
+
+impl ReasonCode {
+    pub const SUCCESS: ReasonCode = ReasonCode(0x00);
+
+    pub fn is_success(self) -> bool {
+        self.0 < 0x80
+    }
+}
+
+impl std::fmt::Display for ReasonCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0x{:02X}", self.0)
+    }
+}
+
+/// Broker response carrying MQTT 5 reason codes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrokerReason {
+    /// Answer to our CONNECT. A failure code is followed by a connection
+    /// error.
+    ConnAck {
+        code: ReasonCode,
+        session_present: bool,
+        reason_string: Option<String>,
+    },
+    /// Answer to a SUBSCRIBE, one code per requested filter.
+    SubAck {
+        pkid: u16,
+        codes: Vec<ReasonCode>,
+        reason_string: Option<String>,
+    },
+    /// The broker closed the connection.
+    Disconnect {
+        code: ReasonCode,
+        reason_string: Option<String>,
+    },
+}
+
+// ── rumqttc implementation ────────────────────────
+
+impl MqttTransport for AsyncClient {
+    type Error = ClientError;
+
+    fn publish(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        AsyncClient::publish(self, topic, HomieMQTTClient::map_qos(&qos), retain, payload)
+    }
+
+    fn subscribe(
+        &self,
+        topic: String,
+        qos: QoS,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        AsyncClient::subscribe(self, topic, HomieMQTTClient::map_qos(&qos))
+    }
+
+    fn subscribe_many(
+        &self,
+        topics: Vec<(String, QoS)>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        AsyncClient::subscribe_many(
+            self,
+            topics.into_iter().map(|(topic, qos)| {
+                rumqttc::SubscribeFilter::new(topic, HomieMQTTClient::map_qos(&qos))
+            }),
+        )
+    }
+
+    // rumqttc has no multi-topic unsubscribe, so `unsubscribe_many` keeps the
+    // per-filter default.
+
+    fn unsubscribe(&self, topic: String) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        AsyncClient::unsubscribe(self, topic)
+    }
+
+    fn disconnect(&self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        AsyncClient::disconnect(self)
+    }
+}
+
+impl MqttEventLoop for EventLoop {
+    type Error = ConnectionError;
+
+    async fn poll(&mut self) -> Result<TransportEvent, Self::Error> {
+        let event = match EventLoop::poll(self).await? {
+            Event::Incoming(Incoming::Publish(p)) => TransportEvent::Message(MqttMessage {
+                topic: p.topic,
+                payload: p.payload,
+                qos: map_incoming_qos(p.qos),
+                retain: p.retain,
+            }),
+            Event::Incoming(Incoming::ConnAck(_)) => TransportEvent::Connected,
+            Event::Outgoing(Outgoing::Publish(pkid)) => TransportEvent::PublishSent(pkid),
+            Event::Incoming(Incoming::PubAck(ack)) => TransportEvent::PublishAcked(ack.pkid),
+            Event::Incoming(Incoming::PubComp(comp)) => TransportEvent::PublishAcked(comp.pkid),
+            Event::Outgoing(Outgoing::Disconnect) => TransportEvent::Disconnected,
+            _ => TransportEvent::Other,
+        };
+        Ok(event)
+    }
+
+    fn endpoint(&self) -> Option<BrokerEndpoint> {
+        Some(BrokerEndpoint::from_broker_address(
+            self.mqtt_options.broker_address(),
+        ))
+    }
+
+    fn set_credentials(&mut self, credentials: &Credentials) {
+        self.mqtt_options
+            .set_credentials(&credentials.username, &credentials.password);
+    }
+
+    fn describe_error(&self, err: &Self::Error) -> ConnectionFailure {
+        ConnectionFailure::from(err)
+    }
+}
+
+impl From<&ConnectionError> for ConnectionFailure {
+    fn from(err: &ConnectionError) -> Self {
+        let kind = match err {
+            ConnectionError::ConnectionRefused(code) => {
+                ConnectionFailureKind::Refused(ReasonCode(match code {
+                    ConnectReturnCode::Success => 0x00,
+                    ConnectReturnCode::RefusedProtocolVersion => 0x84,
+                    ConnectReturnCode::BadClientId => 0x85,
+                    ConnectReturnCode::ServiceUnavailable => 0x88,
+                    ConnectReturnCode::BadUserNamePassword => 0x86,
+                    ConnectReturnCode::NotAuthorized => 0x87,
+                }))
+            }
+            ConnectionError::Tls(_) => ConnectionFailureKind::Tls,
+            ConnectionError::Io(_) => ConnectionFailureKind::Io,
+            ConnectionError::NetworkTimeout | ConnectionError::FlushTimeout => {
+                ConnectionFailureKind::Timeout
+            }
+            _ => ConnectionFailureKind::Other,
+        };
+        ConnectionFailure::new(kind, err.to_string())
+    }
+}
+
+fn map_incoming_qos(qos: rumqttc::QoS) -> QoS {
+    match qos {
+        rumqttc::QoS::AtMostOnce => QoS::AtMostOnce,
+        rumqttc::QoS::AtLeastOnce => QoS::AtLeastOnce,
+        rumqttc::QoS::ExactlyOnce => QoS::ExactlyOnce,
+    }
+}
@@ -0,0 +1,232 @@
+//! Transport pair speaking the MQTT version selected by an
+//! [`MqttClientConfig`].
+//!
+//! [`run_homie_client_with_config`] starts the client on [`MqttClient`] and
+//! [`MqttClientEventLoop`], which dispatch to the `rumqttc` 3.1.1 transport
+//! or to [`MqttV5Client`] / [`MqttV5EventLoop`] depending on
+//! [`MqttClientConfig::protocol_version`]. Config-driven entry points
+//! ([`DeviceManager::new`](crate::controller::DeviceManager::new),
+//! [`BridgeMqttSetup::run`](super::BridgeMqttSetup::run)) are built on it.
+
+use homie5::client::QoS;
+use rumqttc::{v5, AsyncClient};
+use thiserror::Error;
+
+use super::{
+    run_homie_client_with_transport, BrokerEndpoint, ConnectionFailure, Credentials,
+    FailoverEventLoop, HomieClientError, HomieClientOptions, HomieClientParts, MqttClientConfig,
+    MqttEventLoop, MqttProtocolVersion, MqttTransport, MqttV5Client, MqttV5EventLoop,
+    TransportEvent,
+};
+
+/// Connection options of every broker endpoint, in the protocol version
+/// selected by the config (see [`MqttClientConfig::to_endpoint_options`]).
+#[derive(Debug, Clone)]
+pub enum MqttEndpointOptions {
+    V311(Vec<rumqttc::MqttOptions>),
+    V5 {
+        endpoints: Vec<v5::MqttOptions>,
+        /// Attached to every non-retained publish, see
+        /// [`MqttClientConfig::message_expiry_interval`].
+        message_expiry_interval: Option<u32>,
+    },
+}
+
+impl MqttEndpointOptions {
+    pub fn protocol_version(&self) -> MqttProtocolVersion {
+        match self {
+            Self::V311(_) => MqttProtocolVersion::V311,
+            Self::V5 { .. } => MqttProtocolVersion::V5,
+        }
+    }
+}
+
+/// Outgoing half of a connection started from an [`MqttClientConfig`]:
+/// MQTT 3.1.1 or MQTT 5, depending on its protocol version.
+#[derive(Debug, Clone)]
+pub enum MqttClient {
+    V311(AsyncClient),
+    V5(MqttV5Client),
+}
+
+/// Error of an [`MqttClient`] request.
+#[derive(Debug, Error)]
+pub enum MqttClientError {
+    #[error(transparent)]
+    V311(#[from] rumqttc::ClientError),
+    #[error(transparent)]
+    V5(#[from] v5::ClientError),
+}
+
+impl MqttTransport for MqttClient {
+    type Error = MqttClientError;
+
+    async fn publish(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+    ) -> Result<(), Self::Error> {
+        match self {
+            Self::V311(client) => {
+                Ok(MqttTransport::publish(client, topic, qos, retain, payload).await?)
+            }
+            Self::V5(client) => Ok(client.publish(topic, qos, retain, payload).await?),
+        }
+    }
+
+    async fn publish_with_user_properties(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+        user_properties: Vec<(String, String)>,
+    ) -> Result<(), Self::Error> {
+        match self {
+            Self::V311(client) => Ok(client
+                .publish_with_user_properties(topic, qos, retain, payload, user_properties)
+                .await?),
+            Self::V5(client) => Ok(client
+                .publish_with_user_properties(topic, qos, retain, payload, user_properties)
+                .await?),
+        }
+    }
+
+    async fn subscribe(&self, topic: String, qos: QoS) -> Result<(), Self::Error> {
+        match self {
+            Self::V311(client) => Ok(MqttTransport::subscribe(client, topic, qos).await?),
+            Self::V5(client) => Ok(client.subscribe(topic, qos).await?),
+        }
+    }
+
+    async fn subscribe_many(&self, topics: Vec<(String, QoS)>) -> Result<(), Self::Error> {
+        match self {
+            Self::V311(client) => Ok(MqttTransport::subscribe_many(client, topics).await?),
+            Self::V5(client) => Ok(client.subscribe_many(topics).await?),
+        }
+    }
+
+    async fn unsubscribe(&self, topic: String) -> Result<(), Self::Error> {
+        match self {
+            Self::V311(client) => Ok(MqttTransport::unsubscribe(client, topic).await?),
+            Self::V5(client) => Ok(client.unsubscribe(topic).await?),
+        }
+    }
+
+    async fn unsubscribe_many(&self, topics: Vec<String>) -> Result<(), Self::Error> {
+        match self {
+            Self::V311(client) => Ok(client.unsubscribe_many(topics).await?),
+            Self::V5(client) => Ok(client.unsubscribe_many(topics).await?),
+        }
+    }
+
+    async fn disconnect(&self) -> Result<(), Self::Error> {
+        match self {
+            Self::V311(client) => Ok(MqttTransport::disconnect(client).await?),
+            Self::V5(client) => Ok(client.disconnect().await?),
+        }
+    }
+}
+
+/// Incoming half of a connection started from an [`MqttClientConfig`].
+#[allow(clippy::large_enum_variant)] // Owned by the client task, never moved around
+pub enum MqttClientEventLoop {
+    V311(FailoverEventLoop),
+    V5(MqttV5EventLoop),
+}
+
+/// Connection error of an [`MqttClientEventLoop`].
+#[derive(Debug, Error)]
+pub enum MqttConnectionError {
+    #[error(transparent)]
+    V311(#[from] rumqttc::ConnectionError),
+    #[error(transparent)]
+    V5(#[from] v5::ConnectionError),
+}
+
+impl MqttEventLoop for MqttClientEventLoop {
+    type Error = MqttConnectionError;
+
+    async fn poll(&mut self) -> Result<TransportEvent, Self::Error> {
+        match self {
+            Self::V311(eventloop) => Ok(eventloop.poll().await?),
+            Self::V5(eventloop) => Ok(eventloop.poll().await?),
+        }
+    }
+
+    fn endpoint(&self) -> Option<BrokerEndpoint> {
+        match self {
+            Self::V311(eventloop) => eventloop.endpoint(),
+            Self::V5(eventloop) => eventloop.endpoint(),
+        }
+    }
+
+    fn failover(&mut self) {
+        match self {
+            Self::V311(eventloop) => eventloop.failover(),
+            Self::V5(eventloop) => eventloop.failover(),
+        }
+    }
+
+    fn set_credentials(&mut self, credentials: &Credentials) {
+        match self {
+            Self::V311(eventloop) => eventloop.set_credentials(credentials),
+            Self::V5(eventloop) => eventloop.set_credentials(credentials),
+        }
+    }
+
+    fn describe_error(&self, err: &Self::Error) -> ConnectionFailure {
+        match (self, err) {
+            (Self::V311(eventloop), MqttConnectionError::V311(err)) => {
+                eventloop.describe_error(err)
+            }
+            (Self::V5(eventloop), MqttConnectionError::V5(err)) => eventloop.describe_error(err),
+            (_, MqttConnectionError::V311(err)) => ConnectionFailure::from(err),
+            (_, MqttConnectionError::V5(err)) => ConnectionFailure::from(err),
+        }
+    }
+}
+
+/// Starts the homie client for `config`: connects with the MQTT version it
+/// selects and fails over between its broker endpoints.
+pub fn run_homie_client_with_config(
+    config: &MqttClientConfig,
+) -> Result<HomieClientParts<MqttClient, MqttConnectionError>, HomieClientError> {
+    run_homie_client_with_endpoints(config.to_endpoint_options()?, config.to_client_options())
+}
+
+/// Like [`run_homie_client_with_config`], with connection options prepared
+/// beforehand (e.g. by [`MqttClientConfig::into_bridge_setup`]).
+pub fn run_homie_client_with_endpoints(
+    endpoints: MqttEndpointOptions,
+    options: HomieClientOptions,
+) -> Result<HomieClientParts<MqttClient, MqttConnectionError>, HomieClientError> {
+    let no_endpoints =
+        || HomieClientError::TransportConfig("no broker endpoints given".to_string());
+    let (client, eventloop) = match endpoints {
+        MqttEndpointOptions::V311(endpoints) => {
+            let first = endpoints.first().cloned().ok_or_else(no_endpoints)?;
+            log::trace!("Connecting to mqtt: {}", first.client_id());
+            let (client, eventloop) = AsyncClient::new(first, options.channel_size);
+            (
+                MqttClient::V311(client),
+                MqttClientEventLoop::V311(FailoverEventLoop::new(eventloop, endpoints)),
+            )
+        }
+        MqttEndpointOptions::V5 {
+            endpoints,
+            message_expiry_interval,
+        } => {
+            let first = endpoints.first().cloned().ok_or_else(no_endpoints)?;
+            log::trace!("Connecting to mqtt (v5): {}", first.client_id());
+            let (client, eventloop) = v5::AsyncClient::new(first, options.channel_size);
+            (
+                MqttClient::V5(MqttV5Client::new(client, message_expiry_interval)),
+                MqttClientEventLoop::V5(MqttV5EventLoop::new(eventloop).with_failover(endpoints)),
+            )
+        }
+    };
+    run_homie_client_with_transport(client, eventloop, options)
+}
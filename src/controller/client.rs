@@ -1,15 +1,16 @@
 use homie5::{Homie5ControllerProtocol, HomieValue, PropertyRef};
+use rumqttc::AsyncClient;
 
-use crate::client::HomieMQTTClient;
+use crate::client::{HomieMQTTClient, MqttTransport};
 
 #[derive(Clone)]
-pub struct HomieControllerClient {
+pub struct HomieControllerClient<T = AsyncClient> {
     protocol: Homie5ControllerProtocol,
-    homie_client: HomieMQTTClient,
+    homie_client: HomieMQTTClient<T>,
 }
 
-impl HomieControllerClient {
-    pub fn new(protocol: Homie5ControllerProtocol, homie_client: HomieMQTTClient) -> Self {
+impl<T: MqttTransport> HomieControllerClient<T> {
+    pub fn new(protocol: Homie5ControllerProtocol, homie_client: HomieMQTTClient<T>) -> Self {
         Self {
             protocol,
             homie_client,
@@ -20,7 +21,7 @@ impl HomieControllerClient {
         &self,
         prop: &PropertyRef,
         value: &HomieValue,
-    ) -> Result<(), rumqttc::ClientError> {
+    ) -> Result<(), T::Error> {
         self.homie_client
             .homie_publish(self.protocol.set_command(prop, value))
             .await?;
@@ -31,7 +32,7 @@ impl HomieControllerClient {
         &self.protocol
     }
 
-    pub fn homie_client(&self) -> &HomieMQTTClient {
+    pub fn homie_client(&self) -> &HomieMQTTClient<T> {
         &self.homie_client
     }
 }
@@ -1,88 +1,281 @@
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
 
-use homie5::{Homie5ControllerProtocol, Homie5Message, HomieDomain, HomieValue, PropertyRef};
-use tokio::sync::{mpsc, RwLock};
+use chrono::Utc;
+use homie5::{
+    DeviceRef, Homie5ControllerProtocol, Homie5Message, HomieDeviceStatus, HomieDomain, HomieValue,
+    PropertyRef,
+};
+use tokio::sync::{mpsc, watch, RwLock};
 
 use crate::{
     client::{
-        run_homie_client, FlushTimeout, HomieClientError, HomieClientEvent, HomieClientHandle,
-        MqttClientConfig, PendingPublishObserver,
+        run_homie_client_with_config, ClientStats, ClientStatsSnapshot, ConnectionStatus,
+        FlushTimeout, HomieClientError, HomieClientEvent, HomieClientHandle, MqttClient,
+        MqttClientConfig, MqttClientError, MqttConnectionError, PendingPublishObserver,
     },
     model::DiscoveryAction,
     store::DeviceStore,
 };
 
-use super::{DiscoveryError, HomieControllerClient, HomieDiscovery};
+use super::{
+    set_command_outcome, DiscoveryError, DomainSet, HomieControllerClient, HomieDiscovery,
+    InitialSyncTracker, SetCommandOutcome, SyncPhase,
+};
 
+/// Discovers and controls the devices of one or more Homie domains on one
+/// client connection, speaking the MQTT version selected by the config.
 #[derive(Clone)]
 pub struct DeviceManager {
     devices: Arc<RwLock<DeviceStore>>,
-    ctrl_client: HomieControllerClient,
-    discovery: HomieDiscovery,
-    homie_domain: HomieDomain,
+    ctrl_client: HomieControllerClient<MqttClient>,
+    discovery: HomieDiscovery<MqttClient>,
+    /// Configured domains; discovered while `discovering` is set.
+    domains: Arc<Mutex<DomainSet>>,
+    discovering: Arc<AtomicBool>,
+    initial_sync: InitialSyncTracker,
+    /// Signalled after every discovery message applied to `devices`.
+    store_changes: watch::Sender<()>,
     /// Observes queued plus in-flight publishes of the underlying homie
     /// client connection (see [`DeviceManager::flush`]).
     pending_publishes: PendingPublishObserver,
+    stats: ClientStats,
+    connection_status: watch::Receiver<ConnectionStatus>,
 }
 
 impl DeviceManager {
+    /// Starts the client connection for a single domain or a [`DomainSet`]
+    /// (use [`DomainSet::all`] for every domain).
     pub fn new(
-        homie_domain: HomieDomain,
+        homie_domains: impl Into<DomainSet>,
         homie_client_options: &MqttClientConfig,
-    ) -> Result<(Self, HomieClientHandle, mpsc::Receiver<HomieClientEvent>), HomieClientError> {
-        let (homie_client_handle, homie_mqtt_client, homie_event_receiver) = run_homie_client(
-            homie_client_options.to_mqtt_options()?,
-            homie_client_options.mqtt_channel_size,
-        )?;
+    ) -> Result<
+        (
+            Self,
+            HomieClientHandle,
+            mpsc::Receiver<HomieClientEvent<MqttConnectionError>>,
+        ),
+        HomieClientError,
+    > {
+        let (homie_client_handle, homie_mqtt_client, homie_event_receiver) =
+            run_homie_client_with_config(homie_client_options)?;
 
         let devices = Arc::new(RwLock::new(DeviceStore::new()));
         let discovery = HomieDiscovery::new(homie_mqtt_client.clone());
         let ctrl_client =
             HomieControllerClient::new(Homie5ControllerProtocol::new(), homie_mqtt_client);
         let pending_publishes = homie_client_handle.pending_publishes();
+        let stats = homie_client_handle.stats_handle();
+        let connection_status = homie_client_handle.connection_status();
+        let initial_sync = InitialSyncTracker::new();
+        tokio::spawn(
+            initial_sync
+                .clone()
+                .run(Arc::clone(&devices), connection_status.clone()),
+        );
 
         Ok((
             Self {
                 devices,
                 discovery,
                 ctrl_client,
-                homie_domain,
+                domains: Arc::new(Mutex::new(homie_domains.into())),
+                discovering: Arc::default(),
+                initial_sync,
+                store_changes: watch::Sender::new(()),
                 pending_publishes,
+                stats,
+                connection_status,
             },
             homie_client_handle,
             homie_event_receiver,
         ))
     }
 
-    pub async fn discover(&self) -> Result<(), DiscoveryError> {
-        self.discovery.discover(&self.homie_domain).await?;
+    /// Sets how long discovery traffic has to be quiet before the initial
+    /// sync completes (default
+    /// [`DEFAULT_SYNC_SETTLE_TIME`](super::DEFAULT_SYNC_SETTLE_TIME)).
+    pub fn sync_settle_time(self, settle: Duration) -> Self {
+        self.initial_sync.set_settle_time(settle);
+        self
+    }
+
+    /// Sets how long after (re-)arming the initial sync completes even if
+    /// devices are still incomplete (default
+    /// [`DEFAULT_SYNC_MAX_WAIT`](super::DEFAULT_SYNC_MAX_WAIT)).
+    pub fn sync_max_wait(self, max_wait: Duration) -> Self {
+        self.initial_sync.set_max_wait(max_wait);
+        self
+    }
+
+    /// Sets how many property payloads are buffered per device while its
+    /// description is missing (see [`HomieDiscovery::early_payload_limit`]).
+    pub fn early_payload_limit(mut self, limit: usize) -> Self {
+        self.discovery = self.discovery.early_payload_limit(limit);
+        self
+    }
+
+    fn domain_set(&self) -> MutexGuard<'_, DomainSet> {
+        self.domains.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts discovery of all configured domains. Domains that are already
+    /// discovered are not subscribed again. (Re-)arms the initial sync, see
+    /// [`initial_sync_complete`](Self::initial_sync_complete).
+    pub async fn discover(&self) -> Result<(), DiscoveryError<MqttClientError>> {
+        self.discovering.store(true, Ordering::SeqCst);
+        self.initial_sync.arm();
+        let domains = self.domains();
+        for domain in &domains {
+            if !self.discovery.domains().contains(domain) {
+                self.discovery.discover(domain).await?;
+            }
+        }
         Ok(())
     }
 
-    pub async fn stop_discover(&self) -> Result<(), DiscoveryError> {
-        self.discovery.stop_discover(&self.homie_domain).await?;
+    pub async fn stop_discover(&self) -> Result<(), DiscoveryError<MqttClientError>> {
+        self.discovering.store(false, Ordering::SeqCst);
+        self.initial_sync.disarm();
+        for domain in &self.discovery.domains() {
+            self.discovery.stop_discover(domain).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds a domain at runtime. It is discovered right away if discovery
+    /// is running (re-arming the initial sync), otherwise with the next
+    /// [`discover`](Self::discover).
+    pub async fn add_domain(
+        &self,
+        homie_domain: HomieDomain,
+    ) -> Result<(), DiscoveryError<MqttClientError>> {
+        if !self.domain_set().insert(homie_domain.clone()) {
+            return Ok(());
+        }
+        if self.discovering.load(Ordering::SeqCst)
+            && !self.discovery.domains().contains(&homie_domain)
+        {
+            self.initial_sync.arm();
+            self.discovery.discover(&homie_domain).await?;
+        }
         Ok(())
     }
 
+    /// Removes a domain at runtime: stops its discovery and removes the
+    /// devices no other configured domain covers from the store. Returns a
+    /// [`DiscoveryAction::DeviceRemoved`] per removed device.
+    pub async fn remove_domain(
+        &self,
+        homie_domain: &HomieDomain,
+    ) -> Result<Vec<DiscoveryAction>, DiscoveryError<MqttClientError>> {
+        self.domain_set().remove(homie_domain);
+        let mut devices = self.devices.write().await;
+        self.discovery
+            .remove_domain(homie_domain, &mut devices)
+            .await
+    }
+
+    /// The configured domains.
+    pub fn domains(&self) -> DomainSet {
+        self.domain_set().clone()
+    }
+
+    /// Applies a discovery message, received with the `retain` flag, to the
+    /// store and returns the resulting actions (see
+    /// [`HomieDiscovery::handle_event`]).
     pub async fn discovery_handle_event(
         &self,
         message: Homie5Message,
-    ) -> Result<Option<DiscoveryAction>, DiscoveryError> {
+        retain: bool,
+    ) -> Result<Vec<DiscoveryAction>, DiscoveryError<MqttClientError>> {
+        self.initial_sync.touch();
         let mut devices = self.devices.write().await;
-        self.discovery.handle_event(message, &mut devices).await
+        let actions = self
+            .discovery
+            .handle_event(message, retain, &mut devices)
+            .await;
+        self.store_changes.send_replace(());
+        actions
+    }
+
+    /// Resolves once the retained snapshot of the discovered domains has been
+    /// received: every ready device in the store has its description and
+    /// retained property values, and no discovery message arrived for the
+    /// settle time (see [`sync_settle_time`](Self::sync_settle_time)).
+    ///
+    /// The sync is armed by [`discover`](Self::discover) and re-armed after
+    /// every reconnect. Messages only count once passed to
+    /// [`discovery_handle_event`](Self::discovery_handle_event). Devices
+    /// that are not ready are not waited for. A ready device that never
+    /// publishes a retained value holds the sync back until the max wait
+    /// (see [`sync_max_wait`](Self::sync_max_wait)) elapses; the devices
+    /// still incomplete then are returned.
+    pub async fn initial_sync_complete(&self) -> Vec<DeviceRef> {
+        self.initial_sync.complete().await
+    }
+
+    /// Receiver of the initial sync phase.
+    pub fn sync_phase(&self) -> watch::Receiver<SyncPhase> {
+        self.initial_sync.phase()
     }
 
     pub async fn set_command(
         &self,
         target: &PropertyRef,
         value: &HomieValue,
-    ) -> Result<(), rumqttc::ClientError> {
+    ) -> Result<(), MqttClientError> {
         self.ctrl_client.set_command(target, value).await?;
         Ok(())
     }
 
-    pub async fn disconnect_client(&self) -> Result<(), rumqttc::ClientError> {
+    /// Publishes a `/set` command and waits up to `timeout` for the device
+    /// to answer by reporting the property's `$target` or value.
+    ///
+    /// Answers are read from the store, so messages have to be passed to
+    /// [`discovery_handle_event`](Self::discovery_handle_event) meanwhile.
+    /// Only updates received after the command count; a different value
+    /// rejects the command unless a `$target` was reported (see
+    /// [`SetCommandOutcome`]). Non-retained properties are not stored and
+    /// therefore always time out. Nothing is sent to a device that is not
+    /// ready.
+    pub async fn set_command_confirmed(
+        &self,
+        target: &PropertyRef,
+        value: &HomieValue,
+        timeout: Duration,
+    ) -> Result<SetCommandOutcome, MqttClientError> {
+        if self
+            .devices
+            .read()
+            .await
+            .device_state_resolved(target.device_ref())
+            != Some(HomieDeviceStatus::Ready)
+        {
+            return Ok(SetCommandOutcome::DeviceNotReady);
+        }
+        let mut changes = self.store_changes.subscribe();
+        let sent_at = Utc::now();
+        self.set_command(target, value).await?;
+
+        let answer = async {
+            loop {
+                if let Some(outcome) =
+                    set_command_outcome(&*self.devices.read().await, target, value, sent_at)
+                {
+                    return outcome;
+                }
+                // The sender lives in `self`, so the channel cannot close here.
+                let _ = changes.changed().await;
+            }
+        };
+        Ok(tokio::time::timeout(timeout, answer)
+            .await
+            .unwrap_or(SetCommandOutcome::TimedOut))
+    }
+
+    pub async fn disconnect_client(&self) -> Result<(), MqttClientError> {
         self.ctrl_client.homie_client().disconnect().await?;
         Ok(())
     }
@@ -98,6 +291,17 @@ impl DeviceManager {
         self.pending_publishes.flushed(max_wait).await
     }
 
+    /// Connection and traffic counters of this manager's client connection.
+    pub fn stats(&self) -> ClientStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Receiver of the connection status of this manager's client
+    /// connection.
+    pub fn connection_status(&self) -> watch::Receiver<ConnectionStatus> {
+        self.connection_status.clone()
+    }
+
     pub async fn read(&self) -> tokio::sync::RwLockReadGuard<'_, DeviceStore> {
         self.devices.read().await
     }
@@ -109,8 +313,4 @@ impl DeviceManager {
     pub fn devices(&self) -> &Arc<RwLock<DeviceStore>> {
         &self.devices
     }
-
-    pub fn homie_domain(&self) -> &HomieDomain {
-        &self.homie_domain
-    }
 }
@@ -1,44 +1,104 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
 #[cfg(feature = "ext-meta")]
 use homie5::extensions::meta::{self, MetaMessage};
 use homie5::{
     DeviceRef, Homie5ControllerProtocol, Homie5Message, HomieDomain, HomieID, HomieValue,
     PropertyRef, ToTopic,
 };
-use rumqttc::ClientError;
+use rumqttc::{AsyncClient, ClientError};
 use thiserror::Error;
 
+use super::{
+    early_payloads::{EarlyPayload, EarlyPayloadBuffer, PayloadKind},
+    DomainSet, DEFAULT_EARLY_PAYLOAD_LIMIT,
+};
 use crate::{
-    client::HomieMQTTClient,
-    model::{DescriptionUpdate, DeviceRemove, DeviceUpdate, DiscoveryAction, ValueUpdate},
+    client::{HomieMQTTClient, MqttTransport},
+    model::{
+        DescriptionUpdate, Device, DeviceRemove, DeviceUpdate, DiscoveryAction,
+        InvalidPropertyValueError, ValueUpdate,
+    },
     store::{AlertUpdate, DeviceStore},
 };
 
+/// Errors from discovery operations. `E` is the error type of the
+/// underlying [`MqttTransport`].
 #[derive(Debug, Error)]
-pub enum DiscoveryError {
+pub enum DiscoveryError<E = ClientError> {
     #[error("Received a device description message for a non existing device: {0:?}")]
     DescriptionForNonExistingDevice(DeviceRef),
     #[error("Mqtt Client error: {0}")]
-    MqttClient(#[from] ClientError),
+    MqttClient(#[from] E),
 }
+
+/// Discovers the devices of one or more Homie domains.
+///
+/// The domains passed to [`discover`](Self::discover) are recorded (shared
+/// by all clones); once at least one is, [`handle_event`](Self::handle_event)
+/// ignores messages of other domains. Discovering [`HomieDomain::All`]
+/// covers every domain.
+///
+/// Retained property values and targets of a device that arrive before its
+/// description are buffered (at most
+/// [`DEFAULT_EARLY_PAYLOAD_LIMIT`] per device, see
+/// [`early_payload_limit`](Self::early_payload_limit)) and applied once the
+/// description is stored. Property topics are only subscribed once the
+/// description is stored, so payloads can only arrive early when the caller
+/// holds a wider subscription on the same client (e.g. `homie/5/#`).
 #[derive(Clone)]
-pub struct HomieDiscovery {
+pub struct HomieDiscovery<T = AsyncClient> {
     client: Homie5ControllerProtocol,
     #[cfg(feature = "ext-meta")]
     meta_client: meta::MetaControllerProtocol,
-    mqtt_client: HomieMQTTClient,
+    mqtt_client: HomieMQTTClient<T>,
+    domains: Arc<Mutex<DomainSet>>,
+    early_payloads: Arc<Mutex<EarlyPayloadBuffer>>,
 }
 
-impl HomieDiscovery {
-    pub fn new(mqtt_client: HomieMQTTClient) -> Self {
+impl<T: MqttTransport> HomieDiscovery<T> {
+    pub fn new(mqtt_client: HomieMQTTClient<T>) -> Self {
         Self {
             mqtt_client,
             client: Homie5ControllerProtocol::new(),
             #[cfg(feature = "ext-meta")]
             meta_client: meta::MetaControllerProtocol::new(),
+            domains: Arc::default(),
+            early_payloads: Arc::new(Mutex::new(EarlyPayloadBuffer::new(
+                DEFAULT_EARLY_PAYLOAD_LIMIT,
+            ))),
         }
     }
 
-    pub async fn discover(&self, homie_domain: &HomieDomain) -> Result<(), DiscoveryError> {
+    /// Sets how many property payloads are buffered per device while its
+    /// description is missing. Further payloads are rejected with
+    /// [`InvalidPropertyValueError::MissingDescription`].
+    pub fn early_payload_limit(self, limit: usize) -> Self {
+        self.early_payload_buffer().set_limit(limit);
+        self
+    }
+
+    fn domain_set(&self) -> MutexGuard<'_, DomainSet> {
+        self.domains.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn early_payload_buffer(&self) -> MutexGuard<'_, EarlyPayloadBuffer> {
+        self.early_payloads
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The domains currently discovered.
+    pub fn domains(&self) -> DomainSet {
+        self.domain_set().clone()
+    }
+
+    /// Subscribes the discovery topics of `homie_domain` and records the
+    /// domain.
+    pub async fn discover(
+        &self,
+        homie_domain: &HomieDomain,
+    ) -> Result<(), DiscoveryError<T::Error>> {
         self.mqtt_client
             .homie_subscribe(self.client.subscribe_device_discovery(homie_domain))
             .await?;
@@ -54,10 +114,17 @@ impl HomieDiscovery {
                 .homie_subscribe(self.meta_client.subscribe_all_overlays(homie_domain))
                 .await?;
         }
+        self.domain_set().insert(homie_domain.clone());
         Ok(())
     }
 
-    pub async fn stop_discover(&self, homie_domain: &HomieDomain) -> Result<(), DiscoveryError> {
+    /// Unsubscribes the discovery topics of `homie_domain` and forgets the
+    /// domain. Devices already discovered stay subscribed, see
+    /// [`remove_domain`](Self::remove_domain).
+    pub async fn stop_discover(
+        &self,
+        homie_domain: &HomieDomain,
+    ) -> Result<(), DiscoveryError<T::Error>> {
         self.mqtt_client
             .homie_unsubscribe(self.client.unsubscribe_device_discovery(homie_domain))
             .await?;
@@ -76,14 +143,80 @@ impl HomieDiscovery {
                 .homie_unsubscribe(self.meta_client.unsubscribe_all_overlays(homie_domain))
                 .await?;
         }
+        self.domain_set().remove(homie_domain);
         Ok(())
     }
 
+    /// Stops discovering `homie_domain` and removes the devices no longer
+    /// covered by the remaining domains from `devices`, unsubscribing their
+    /// topics. Returns a [`DiscoveryAction::DeviceRemoved`] per removed
+    /// device. Does nothing if the domain is not discovered.
+    pub async fn remove_domain(
+        &self,
+        homie_domain: &HomieDomain,
+        devices: &mut DeviceStore,
+    ) -> Result<Vec<DiscoveryAction>, DiscoveryError<T::Error>> {
+        if !self.domain_set().contains(homie_domain) {
+            return Ok(Vec::new());
+        }
+        self.stop_discover(homie_domain).await?;
+        let stale: Vec<DeviceRef> = {
+            let domains = self.domain_set();
+            devices
+                .iter()
+                .filter(|(domain, _, _)| !domains.matches(domain))
+                .map(|(domain, device_id, _)| DeviceRef::new(domain.clone(), device_id.clone()))
+                .collect()
+        };
+        let mut actions = Vec::new();
+        for device in stale {
+            if let Some(dev) = self.remove_device(&device, devices).await? {
+                actions.push(DiscoveryAction::DeviceRemoved(dev));
+            }
+        }
+        Ok(actions)
+    }
+
+    /// Applies `event` to `devices` and returns the resulting actions.
+    /// `retain` is the retain flag the message arrived with; only retained
+    /// property payloads are buffered until the device description arrives.
+    ///
+    /// A description that applies buffered property payloads is followed by
+    /// the actions of those payloads; every other message results in at most
+    /// one action.
     pub async fn handle_event(
         &self,
         event: Homie5Message,
+        retain: bool,
+        devices: &mut DeviceStore,
+    ) -> Result<Vec<DiscoveryAction>, DiscoveryError<T::Error>> {
+        let described = match &event {
+            Homie5Message::DeviceDescription { device, .. } => Some(device.clone()),
+            _ => None,
+        };
+        let mut actions: Vec<_> = self
+            .handle_message(event, retain, devices)
+            .await?
+            .into_iter()
+            .collect();
+        if let Some(device) = described {
+            actions.extend(self.apply_early_payloads(&device, devices));
+        }
+        Ok(actions)
+    }
+
+    async fn handle_message(
+        &self,
+        event: Homie5Message,
+        retain: bool,
         devices: &mut DeviceStore,
-    ) -> Result<Option<DiscoveryAction>, DiscoveryError> {
+    ) -> Result<Option<DiscoveryAction>, DiscoveryError<T::Error>> {
+        {
+            let domains = self.domain_set();
+            if !domains.is_empty() && !domains.matches(message_domain(&event)) {
+                return Ok(None);
+            }
+        }
         let action = match event {
             Homie5Message::DeviceState { device, state } => match devices.add(&device, state) {
                 DeviceUpdate::Added(device_ref) => {
@@ -133,10 +266,10 @@ impl HomieDiscovery {
                 }
             },
             Homie5Message::PropertyValue { property, value } => {
-                self.update_prop_value(property, value, devices)
+                self.update_prop_value(property, value, retain, devices)
             }
             Homie5Message::PropertyTarget { property, target } => {
-                self.update_prop_target(property, target, devices)
+                self.update_prop_target(property, target, retain, devices)
             }
             Homie5Message::DeviceAlert {
                 device,
@@ -144,24 +277,12 @@ impl HomieDiscovery {
                 alert_msg,
             } => self.store_alert(device, alert_id, alert_msg, devices),
             Homie5Message::DeviceRemoval { device } => {
-                self.mqtt_client
-                    .homie_unsubscribe(self.client.unsubscribe_device(&device))
-                    .await?;
-
-                let DeviceRemove::Removed(dev) = devices.remove_device(&device) else {
-                    return Ok(None);
-                };
-
-                let Some(description) = &dev.description else {
-                    return Ok(None);
-                };
-
-                self.mqtt_client
-                    .homie_unsubscribe(self.client.unsubscribe_props(&device, description))
-                    .await?;
-
-                log::info!("============> Removed device {}", dev.device_id());
-                Some(DiscoveryAction::DeviceRemoved(dev))
+                match self.remove_device(&device, devices).await? {
+                    Some(dev) if dev.description.is_some() => {
+                        Some(DiscoveryAction::DeviceRemoved(dev))
+                    }
+                    _ => None,
+                }
             }
             _ => Some(DiscoveryAction::Unhandled(event)),
         };
@@ -169,6 +290,30 @@ impl HomieDiscovery {
         Ok(action)
     }
 
+    /// Unsubscribes the topics of `device` and removes it from the store.
+    async fn remove_device(
+        &self,
+        device: &DeviceRef,
+        devices: &mut DeviceStore,
+    ) -> Result<Option<Device>, DiscoveryError<T::Error>> {
+        self.mqtt_client
+            .homie_unsubscribe(self.client.unsubscribe_device(device))
+            .await?;
+
+        self.early_payload_buffer().take(device);
+        let DeviceRemove::Removed(dev) = devices.remove_device(device) else {
+            return Ok(None);
+        };
+
+        if let Some(description) = &dev.description {
+            self.mqtt_client
+                .homie_unsubscribe(self.client.unsubscribe_props(device, description))
+                .await?;
+            log::info!("============> Removed device {}", dev.device_id());
+        }
+        Ok(Some(dev))
+    }
+
     /// Handle a parsed `MetaMessage` from the `$meta` overlay namespace.
     ///
     /// Returns the corresponding `DiscoveryAction` for the caller to process.
@@ -216,21 +361,20 @@ impl HomieDiscovery {
         &self,
         property: PropertyRef,
         value: String,
+        retain: bool,
         devices: &mut DeviceStore,
     ) -> Option<DiscoveryAction> {
         let device = devices.get_device_mut(property.device_ref())?;
-        let Some((Ok(value), retained)) = device.description.as_ref().and_then(|desc| {
-            desc.with_property(&property, |prop_desc| {
-                //log::debug!("PropertyValue: {} - {:?}", property.to_topic(), prop_desc,);
-                if !prop_desc.retained {
-                    log::debug!("PropertyValue: {} - {}", property.to_topic(), value,);
-                }
-
-                (HomieValue::parse(&value, prop_desc), prop_desc.retained)
-            })
-        }) else {
-            return None;
+        let (value, retained) = match parse_payload(device, &property, &value) {
+            Ok(parsed) => parsed,
+            Err(InvalidPropertyValueError::MissingDescription) => {
+                return self.buffer_payload(device, property, PayloadKind::Value, value, retain);
+            }
+            Err(error) => return Some(reject_payload(device, property, value, error)),
         };
+        if !retained {
+            log::debug!("PropertyValue: {} - {}", property.to_topic(), value);
+        }
         if retained {
             match device
                 .prop_values
@@ -262,14 +406,17 @@ impl HomieDiscovery {
         &self,
         property: PropertyRef,
         target: String,
+        retain: bool,
         devices: &mut DeviceStore,
     ) -> Option<DiscoveryAction> {
         // log::debug!("PropertyTarget: {} - {}", property.to_topic(), target);
         let device = devices.get_device_mut(property.device_ref())?;
-        let Some(Ok(value)) = device.description.as_ref().and_then(|desc| {
-            desc.with_property(&property, |prop_desc| HomieValue::parse(&target, prop_desc))
-        }) else {
-            return None;
+        let value = match parse_payload(device, &property, &target) {
+            Ok((value, _)) => value,
+            Err(InvalidPropertyValueError::MissingDescription) => {
+                return self.buffer_payload(device, property, PayloadKind::Target, target, retain);
+            }
+            Err(error) => return Some(reject_payload(device, property, target, error)),
         };
         match device
             .prop_values
@@ -290,6 +437,82 @@ impl HomieDiscovery {
             }),
         }
     }
+    /// Buffers a retained payload of a device without description. Rejects
+    /// it if it was not retained or the device buffer is full.
+    fn buffer_payload(
+        &self,
+        device: &mut Device,
+        property: PropertyRef,
+        kind: PayloadKind,
+        raw: String,
+        retain: bool,
+    ) -> Option<DiscoveryAction> {
+        if !retain {
+            return Some(reject_payload(
+                device,
+                property,
+                raw,
+                InvalidPropertyValueError::MissingDescription,
+            ));
+        }
+        log::trace!(
+            "Buffering payload for {} until description",
+            property.to_topic()
+        );
+        let rejected = self.early_payload_buffer().push(EarlyPayload {
+            property,
+            kind,
+            raw,
+        })?;
+        Some(reject_payload(
+            device,
+            rejected.property,
+            rejected.raw,
+            InvalidPropertyValueError::MissingDescription,
+        ))
+    }
+
+    /// Applies the payloads buffered for `device` once it has a description.
+    /// Payloads of properties the description declares non-retained are
+    /// dropped.
+    fn apply_early_payloads(
+        &self,
+        device: &DeviceRef,
+        devices: &mut DeviceStore,
+    ) -> Vec<DiscoveryAction> {
+        if devices
+            .get_device(device)
+            .is_none_or(|dev| dev.description.is_none())
+        {
+            return Vec::new();
+        }
+        let buffered = self.early_payload_buffer().take(device);
+        buffered
+            .into_iter()
+            .filter_map(|payload| {
+                let retained = devices
+                    .get_device(device)
+                    .and_then(|dev| dev.description.as_ref())
+                    .and_then(|desc| desc.with_property(&payload.property, |prop| prop.retained));
+                if retained == Some(false) {
+                    log::trace!(
+                        "Dropping buffered payload for non-retained {}",
+                        payload.property.to_topic()
+                    );
+                    return None;
+                }
+                match payload.kind {
+                    PayloadKind::Value => {
+                        self.update_prop_value(payload.property, payload.raw, true, devices)
+                    }
+                    PayloadKind::Target => {
+                        self.update_prop_target(payload.property, payload.raw, true, devices)
+                    }
+                }
+            })
+            .collect()
+    }
+
     #[allow(dead_code)]
     fn store_alert(
         &self,
@@ -323,3 +546,52 @@ impl HomieDiscovery {
         }
     }
 }
+
+fn message_domain(message: &Homie5Message) -> &HomieDomain {
+    match message {
+        Homie5Message::DeviceState { device, .. }
+        | Homie5Message::DeviceDescription { device, .. }
+        | Homie5Message::DeviceLog { device, .. }
+        | Homie5Message::DeviceAlert { device, .. }
+        | Homie5Message::DeviceRemoval { device } => device.homie_domain(),
+        Homie5Message::PropertyValue { property, .. }
+        | Homie5Message::PropertyTarget { property, .. }
+        | Homie5Message::PropertySet { property, .. } => property.homie_domain(),
+        Homie5Message::Broadcast { homie_domain, .. } => homie_domain,
+    }
+}
+
+/// Parses a value or target payload of `property` against the description
+/// of `device`. Returns the value and whether the property is retained.
+fn parse_payload(
+    device: &Device,
+    property: &PropertyRef,
+    raw: &str,
+) -> Result<(HomieValue, bool), InvalidPropertyValueError> {
+    let description = device
+        .description
+        .as_ref()
+        .ok_or(InvalidPropertyValueError::MissingDescription)?;
+    let (value, retained) = description
+        .with_property(property, |prop_desc| {
+            (HomieValue::parse(raw, prop_desc), prop_desc.retained)
+        })
+        .ok_or(InvalidPropertyValueError::UnknownProperty)?;
+    let value = value.map_err(|err| InvalidPropertyValueError::Parse(Arc::new(err)))?;
+    Ok((value, retained))
+}
+
+/// Counts a rejected payload on `device` and reports it.
+fn reject_payload(
+    device: &mut Device,
+    prop: PropertyRef,
+    raw: String,
+    error: InvalidPropertyValueError,
+) -> DiscoveryAction {
+    device.rejected_payloads += 1;
+    log::debug!(
+        "Rejected payload for {}: {raw:?} ({error})",
+        prop.to_topic()
+    );
+    DiscoveryAction::InvalidPropertyValue { prop, raw, error }
+}
@@ -0,0 +1,82 @@
+use std::collections::BTreeSet;
+
+use homie5::HomieDomain;
+
+/// A set of Homie domains to discover. Containing [`HomieDomain::All`] it
+/// matches every domain.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DomainSet(BTreeSet<HomieDomain>);
+
+impl DomainSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The set matching every domain.
+    pub fn all() -> Self {
+        Self::from(HomieDomain::All)
+    }
+
+    /// Adds `domain`. Returns `false` if it was already in the set.
+    pub fn insert(&mut self, domain: HomieDomain) -> bool {
+        self.0.insert(domain)
+    }
+
+    /// Removes `domain`. Returns `false` if it was not in the set.
+    pub fn remove(&mut self, domain: &HomieDomain) -> bool {
+        self.0.remove(domain)
+    }
+
+    /// Whether `domain` itself is in the set.
+    pub fn contains(&self, domain: &HomieDomain) -> bool {
+        self.0.contains(domain)
+    }
+
+    /// Whether messages of `domain` belong to the set, either because the
+    /// set contains it or because it contains [`HomieDomain::All`].
+    pub fn matches(&self, domain: &HomieDomain) -> bool {
+        self.0.contains(&HomieDomain::All) || self.0.contains(domain)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &HomieDomain> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<HomieDomain> for DomainSet {
+    fn from(domain: HomieDomain) -> Self {
+        Self(BTreeSet::from([domain]))
+    }
+}
+
+impl FromIterator<HomieDomain> for DomainSet {
+    fn from_iter<I: IntoIterator<Item = HomieDomain>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for DomainSet {
+    type Item = HomieDomain;
+    type IntoIter = std::collections::btree_set::IntoIter<HomieDomain>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a DomainSet {
+    type Item = &'a HomieDomain;
+    type IntoIter = std::collections::btree_set::Iter<'a, HomieDomain>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
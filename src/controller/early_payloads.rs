@@ -0,0 +1,62 @@
+use std::collections::{HashMap, VecDeque};
+
+use homie5::{DeviceRef, PropertyRef};
+
+/// Number of payloads [`HomieDiscovery`](super::HomieDiscovery) buffers per
+/// device while its description is missing, unless configured otherwise.
+pub const DEFAULT_EARLY_PAYLOAD_LIMIT: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PayloadKind {
+    Value,
+    Target,
+}
+
+/// A raw property payload received before the device description.
+#[derive(Debug)]
+pub(crate) struct EarlyPayload {
+    pub property: PropertyRef,
+    pub kind: PayloadKind,
+    pub raw: String,
+}
+
+/// Per-device buffer of payloads waiting for the device description. Only
+/// the latest payload per property and kind is kept.
+#[derive(Debug)]
+pub(crate) struct EarlyPayloadBuffer {
+    limit: usize,
+    devices: HashMap<DeviceRef, VecDeque<EarlyPayload>>,
+}
+
+impl EarlyPayloadBuffer {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            devices: HashMap::new(),
+        }
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Buffers `payload`, replacing an older one of the same property and
+    /// kind. Hands it back if the device buffer is full.
+    pub fn push(&mut self, payload: EarlyPayload) -> Option<EarlyPayload> {
+        let buffered = self
+            .devices
+            .entry(payload.property.device_ref().clone())
+            .or_default();
+        buffered.retain(|p| p.kind != payload.kind || p.property != payload.property);
+        if buffered.len() >= self.limit {
+            return Some(payload);
+        }
+        buffered.push_back(payload);
+        None
+    }
+
+    /// Removes and returns the payloads of `device` in arrival order.
+    pub fn take(&mut self, device: &DeviceRef) -> VecDeque<EarlyPayload> {
+        self.devices.remove(device).unwrap_or_default()
+    }
+}
@@ -3,26 +3,48 @@ use std::collections::HashMap;
 use homie5::extensions::meta::{MetaDeviceOverlay, MetaMessage};
 use homie5::{DeviceRef, HomieDomain, HomieID};
 
+use super::DomainSet;
 use crate::store::DeviceStore;
 
 /// Handles meta overlay messages for controller applications.
 ///
 /// Manages pending overlays for undiscovered devices and applies overlays
-/// to the `DeviceStore` when devices become available.
+/// to the `DeviceStore` when devices become available. Messages of domains
+/// outside the handled [`DomainSet`] are ignored.
 pub struct MetaOverlayHandler {
-    homie_domain: HomieDomain,
-    /// provider_id → (device_id → overlay)
-    pending: HashMap<HomieID, HashMap<HomieID, MetaDeviceOverlay>>,
+    domains: DomainSet,
+    /// provider_id → (device → overlay)
+    pending: HashMap<HomieID, HashMap<DeviceRef, MetaDeviceOverlay>>,
 }
 
 impl MetaOverlayHandler {
-    pub fn new(domain: HomieDomain) -> Self {
+    pub fn new(domains: impl Into<DomainSet>) -> Self {
         Self {
-            homie_domain: domain,
+            domains: domains.into(),
             pending: HashMap::new(),
         }
     }
 
+    /// The handled domains.
+    pub fn domains(&self) -> &DomainSet {
+        &self.domains
+    }
+
+    /// Starts handling messages of `domain`.
+    pub fn add_domain(&mut self, domain: HomieDomain) {
+        self.domains.insert(domain);
+    }
+
+    /// Stops handling messages of `domain` and drops its pending overlays.
+    pub fn remove_domain(&mut self, domain: &HomieDomain) {
+        self.domains.remove(domain);
+        let domains = &self.domains;
+        for per_provider in self.pending.values_mut() {
+            per_provider.retain(|device_ref, _| domains.matches(device_ref.homie_domain()));
+        }
+        self.pending.retain(|_, v| !v.is_empty());
+    }
+
     /// Process a `MetaMessage` event. Applies overlay to device if present
     /// in the store, otherwise buffers it as pending.
     ///
@@ -34,7 +56,7 @@ impl MetaOverlayHandler {
                 provider_id,
                 info,
             } => {
-                if homie_domain != self.homie_domain {
+                if !self.domains.matches(&homie_domain) {
                     return false;
                 }
                 log::debug!(
@@ -48,10 +70,10 @@ impl MetaOverlayHandler {
                 homie_domain,
                 provider_id,
             } => {
-                if homie_domain != self.homie_domain {
+                if !self.domains.matches(&homie_domain) {
                     return false;
                 }
-                self.remove_provider(&provider_id, devices);
+                self.remove_provider_in(Some(&homie_domain), &provider_id, devices);
                 log::debug!("Meta provider removed: {}", provider_id);
                 true
             }
@@ -61,10 +83,11 @@ impl MetaOverlayHandler {
                 device_id,
                 overlay,
             } => {
-                if homie_domain != self.homie_domain {
+                if !self.domains.matches(&homie_domain) {
                     return false;
                 }
-                self.upsert_overlay(device_id, provider_id, overlay, devices);
+                let device_ref = DeviceRef::new(homie_domain, device_id);
+                self.upsert_overlay(device_ref, provider_id, overlay, devices);
                 true
             }
             MetaMessage::DeviceOverlayRemoval {
@@ -72,10 +95,11 @@ impl MetaOverlayHandler {
                 provider_id,
                 device_id,
             } => {
-                if homie_domain != self.homie_domain {
+                if !self.domains.matches(&homie_domain) {
                     return false;
                 }
-                self.remove_overlay(&device_id, &provider_id, devices);
+                let device_ref = DeviceRef::new(homie_domain, device_id);
+                self.remove_overlay(&device_ref, &provider_id, devices);
                 true
             }
         }
@@ -83,10 +107,10 @@ impl MetaOverlayHandler {
 
     /// Apply any pending overlays for a specific device (call after device discovery).
     pub fn apply_pending_for_device(&mut self, device_ref: &DeviceRef, devices: &mut DeviceStore) {
-        // Collect all pending overlays for this device_id across all providers
+        // Collect all pending overlays for this device across all providers
         let mut overlays_to_apply: Vec<(HomieID, MetaDeviceOverlay)> = Vec::new();
         for (provider_id, per_provider) in &mut self.pending {
-            if let Some(overlay) = per_provider.remove(device_ref.device_id()) {
+            if let Some(overlay) = per_provider.remove(device_ref) {
                 overlays_to_apply.push((provider_id.clone(), overlay));
             }
         }
@@ -110,14 +134,40 @@ impl MetaOverlayHandler {
         }
     }
 
-    /// Remove all overlays from a specific provider (call when provider disconnects).
+    /// Remove all overlays from a specific provider, in all domains (call
+    /// when provider disconnects).
     pub fn remove_provider(&mut self, provider_id: &HomieID, devices: &mut DeviceStore) {
+        self.remove_provider_in(None, provider_id, devices);
+    }
+
+    /// Clear all pending overlays (call on full reconnect).
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    // ── Internal helpers ──────────────────────────────
+
+    /// Removes the overlays of `provider_id` in `domain`, or in all domains.
+    fn remove_provider_in(
+        &mut self,
+        domain: Option<&HomieDomain>,
+        provider_id: &HomieID,
+        devices: &mut DeviceStore,
+    ) {
+        let in_scope = |device_domain: &HomieDomain| domain.is_none_or(|d| d == device_domain);
+
         // Remove from pending
-        self.pending.remove(provider_id);
+        if let Some(per_provider) = self.pending.get_mut(provider_id) {
+            per_provider.retain(|device_ref, _| !in_scope(device_ref.homie_domain()));
+            if per_provider.is_empty() {
+                self.pending.remove(provider_id);
+            }
+        }
 
         // Collect device refs first, then mutate
         let device_refs: Vec<DeviceRef> = devices
             .iter()
+            .filter(|(device_domain, _, _)| in_scope(device_domain))
             .map(|(domain, device_id, _)| DeviceRef::new(domain.clone(), device_id.clone()))
             .collect();
 
@@ -128,22 +178,13 @@ impl MetaOverlayHandler {
         }
     }
 
-    /// Clear all pending overlays (call on full reconnect).
-    pub fn clear(&mut self) {
-        self.pending.clear();
-    }
-
-    // ── Internal helpers ──────────────────────────────
-
     fn upsert_overlay(
         &mut self,
-        device_id: HomieID,
+        device_ref: DeviceRef,
         provider_id: HomieID,
         overlay: MetaDeviceOverlay,
         devices: &mut DeviceStore,
     ) {
-        let device_ref = DeviceRef::new(self.homie_domain.clone(), device_id.clone());
-
         if let Some(device) = devices.get_device_mut(&device_ref) {
             device.meta_overlays.insert(provider_id, overlay);
             return;
@@ -153,24 +194,22 @@ impl MetaOverlayHandler {
         self.pending
             .entry(provider_id)
             .or_default()
-            .insert(device_id, overlay);
+            .insert(device_ref, overlay);
     }
 
     fn remove_overlay(
         &mut self,
-        device_id: &HomieID,
+        device_ref: &DeviceRef,
         provider_id: &HomieID,
         devices: &mut DeviceStore,
     ) {
-        let device_ref = DeviceRef::new(self.homie_domain.clone(), device_id.clone());
-
-        if let Some(device) = devices.get_device_mut(&device_ref) {
+        if let Some(device) = devices.get_device_mut(device_ref) {
             device.meta_overlays.remove(provider_id);
         }
 
         // Also remove from pending
         if let Some(per_provider) = self.pending.get_mut(provider_id) {
-            per_provider.remove(device_id);
+            per_provider.remove(device_ref);
             if per_provider.is_empty() {
                 self.pending.remove(provider_id);
             }
@@ -1,11 +1,19 @@
 mod client;
 mod device_manager;
 mod discovery;
+mod domains;
+mod early_payloads;
 #[cfg(feature = "ext-meta")]
 mod meta_handler;
+mod set_confirm;
+mod sync;
 
 pub use client::*;
 pub use device_manager::*;
 pub use discovery::*;
+pub use domains::*;
+pub use early_payloads::*;
 #[cfg(feature = "ext-meta")]
 pub use meta_handler::*;
+pub use set_confirm::*;
+pub use sync::*;
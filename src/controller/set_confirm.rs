@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use homie5::{HomieDeviceStatus, HomieValue, PropertyRef};
+
+use crate::store::DeviceStore;
+
+/// Outcome of [`DeviceManager::set_command_confirmed`](super::DeviceManager::set_command_confirmed).
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetCommandOutcome {
+    /// The property's `$target` or value reported the requested value.
+    Confirmed,
+    /// The device reported a different `$target` or value instead.
+    Rejected { value: HomieValue },
+    /// The device is unknown or its (root) device is not `ready`.
+    DeviceNotReady,
+    /// Nothing was reported within the timeout.
+    TimedOut,
+}
+
+/// Decides a pending set command from the property entry in `devices`,
+/// considering only `$target` and value updates received since `sent_at`.
+/// Returns `None` while the device has not answered yet.
+///
+/// A matching `$target` or value confirms the command. A different
+/// `$target` rejects it, a different value only if no `$target` was
+/// received.
+pub(crate) fn set_command_outcome(
+    devices: &DeviceStore,
+    prop: &PropertyRef,
+    requested: &HomieValue,
+    sent_at: DateTime<Utc>,
+) -> Option<SetCommandOutcome> {
+    if devices.device_state_resolved(prop.device_ref()) != Some(HomieDeviceStatus::Ready) {
+        return Some(SetCommandOutcome::DeviceNotReady);
+    }
+    let entry = devices.get_value_entry(prop)?;
+    let since_sent = |value: &Option<HomieValue>, received: Option<DateTime<Utc>>| {
+        value
+            .clone()
+            .filter(|_| received.is_some_and(|received| received >= sent_at))
+    };
+    let target = since_sent(&entry.target, entry.target_last_received);
+    let value = since_sent(&entry.value, entry.value_last_received);
+
+    if target.as_ref() == Some(requested) || value.as_ref() == Some(requested) {
+        return Some(SetCommandOutcome::Confirmed);
+    }
+    target
+        .or(value)
+        .map(|value| SetCommandOutcome::Rejected { value })
+}
@@ -0,0 +1,203 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use homie5::{DeviceRef, HomieDeviceStatus, PropertyPointer};
+use tokio::sync::{watch, RwLock};
+use tokio::time::Instant;
+
+use crate::{client::ConnectionStatus, store::DeviceStore};
+
+/// Settle time used by [`DeviceManager`](super::DeviceManager) unless
+/// configured otherwise.
+pub const DEFAULT_SYNC_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Longest wait for the retained snapshot used by
+/// [`DeviceManager`](super::DeviceManager) unless configured otherwise.
+pub const DEFAULT_SYNC_MAX_WAIT: Duration = Duration::from_secs(30);
+
+/// Phase of the initial discovery sync of a
+/// [`DeviceManager`](super::DeviceManager).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPhase {
+    /// Discovery is not running.
+    #[default]
+    Idle,
+    /// Waiting for the retained snapshot of the discovered domains.
+    Syncing,
+    /// Every ready device received its description and retained property
+    /// values and traffic was quiet for the settle time, or the max wait
+    /// elapsed.
+    Complete,
+}
+
+/// Drives the [`SyncPhase`] of a device manager. Clones share the state.
+#[derive(Clone)]
+pub(crate) struct InitialSyncTracker {
+    phase: watch::Sender<SyncPhase>,
+    /// Time of the last discovery message or (re-)arm.
+    activity: watch::Sender<Instant>,
+    settle: Arc<Mutex<Duration>>,
+    max_wait: Arc<Mutex<Duration>>,
+    /// Time of the last (re-)arm.
+    armed_at: Arc<Mutex<Instant>>,
+    /// Devices still incomplete when the max wait elapsed.
+    incomplete: Arc<Mutex<Vec<DeviceRef>>>,
+}
+
+impl InitialSyncTracker {
+    pub fn new() -> Self {
+        Self {
+            phase: watch::Sender::new(SyncPhase::Idle),
+            activity: watch::Sender::new(Instant::now()),
+            settle: Arc::new(Mutex::new(DEFAULT_SYNC_SETTLE_TIME)),
+            max_wait: Arc::new(Mutex::new(DEFAULT_SYNC_MAX_WAIT)),
+            armed_at: Arc::new(Mutex::new(Instant::now())),
+            incomplete: Arc::default(),
+        }
+    }
+
+    pub fn set_settle_time(&self, settle: Duration) {
+        *self.settle.lock().unwrap_or_else(|e| e.into_inner()) = settle;
+    }
+
+    fn settle_time(&self) -> Duration {
+        *self.settle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_max_wait(&self, max_wait: Duration) {
+        *self.max_wait.lock().unwrap_or_else(|e| e.into_inner()) = max_wait;
+    }
+
+    fn deadline(&self) -> Instant {
+        *self.armed_at.lock().unwrap_or_else(|e| e.into_inner())
+            + *self.max_wait.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_incomplete(&self, devices: Vec<DeviceRef>) {
+        *self.incomplete.lock().unwrap_or_else(|e| e.into_inner()) = devices;
+    }
+
+    pub fn phase(&self) -> watch::Receiver<SyncPhase> {
+        self.phase.subscribe()
+    }
+
+    /// Starts (or restarts) waiting for the retained snapshot.
+    pub fn arm(&self) {
+        *self.armed_at.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        self.set_incomplete(Vec::new());
+        self.phase.send_replace(SyncPhase::Syncing);
+        self.touch();
+    }
+
+    pub fn disarm(&self) {
+        self.phase.send_replace(SyncPhase::Idle);
+    }
+
+    /// Records discovery traffic, restarting the settle time.
+    pub fn touch(&self) {
+        self.activity.send_replace(Instant::now());
+    }
+
+    /// Resolves once the phase is [`SyncPhase::Complete`], with the devices
+    /// that were still incomplete when the max wait elapsed.
+    pub async fn complete(&self) -> Vec<DeviceRef> {
+        let mut phase = self.phase.subscribe();
+        // The sender lives in `self`, so the channel cannot close here.
+        let _ = phase.wait_for(|phase| *phase == SyncPhase::Complete).await;
+        self.incomplete
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Moves a running sync to [`SyncPhase::Complete`].
+    fn finish(&self) {
+        self.phase.send_if_modified(|phase| {
+            let syncing = *phase == SyncPhase::Syncing;
+            if syncing {
+                *phase = SyncPhase::Complete;
+            }
+            syncing
+        });
+    }
+
+    /// Completes the sync once the connection is up, traffic settled and
+    /// `devices` is complete, or once the max wait elapsed, and re-arms it
+    /// after every reconnect. Runs until the connection status channel
+    /// closes.
+    pub async fn run(
+        self,
+        devices: Arc<RwLock<DeviceStore>>,
+        mut status: watch::Receiver<ConnectionStatus>,
+    ) {
+        let mut activity = self.activity.subscribe();
+        let mut connected = status.borrow_and_update().is_connected();
+        // Activity time the store was last found incomplete at; checked
+        // again only after new traffic.
+        let mut checked = None;
+        loop {
+            let last_activity = *activity.borrow_and_update();
+            let syncing = connected && *self.phase.borrow() == SyncPhase::Syncing;
+            let waiting = syncing && checked != Some(last_activity);
+            tokio::select! {
+                changed = status.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    let now_connected = status.borrow_and_update().is_connected();
+                    if now_connected && !connected && *self.phase.borrow() != SyncPhase::Idle {
+                        self.arm();
+                    }
+                    connected = now_connected;
+                }
+                Ok(()) = activity.changed() => {}
+                _ = tokio::time::sleep_until(last_activity + self.settle_time()), if waiting => {
+                    if incomplete_devices(&*devices.read().await).is_empty() {
+                        self.finish();
+                    } else {
+                        checked = Some(last_activity);
+                    }
+                }
+                _ = tokio::time::sleep_until(self.deadline()), if syncing => {
+                    let incomplete = incomplete_devices(&*devices.read().await);
+                    if !incomplete.is_empty() {
+                        log::warn!(
+                            "Initial sync gave up waiting for {} device(s): {:?}",
+                            incomplete.len(),
+                            incomplete
+                        );
+                    }
+                    self.set_incomplete(incomplete);
+                    self.finish();
+                }
+            }
+        }
+    }
+}
+
+/// Ready devices in `devices` that lack their description or a value for one
+/// of their retained properties. Devices in any other state are not expected
+/// to publish values and never hold the sync back.
+fn incomplete_devices(devices: &DeviceStore) -> Vec<DeviceRef> {
+    devices
+        .iter()
+        .filter(|(_, _, device)| {
+            devices.device_state_resolved(&device.ident) == Some(HomieDeviceStatus::Ready)
+        })
+        .filter(|(_, _, device)| {
+            let Some(description) = &device.description else {
+                return true;
+            };
+            !description
+                .iter()
+                .filter(|(_, _, _, prop)| prop.retained)
+                .all(|(node_id, _, prop_id, _)| {
+                    device
+                        .prop_values
+                        .get_value_entry(&PropertyPointer::new(node_id.clone(), prop_id.clone()))
+                        .is_some_and(|entry| entry.value.is_some())
+                })
+        })
+        .map(|(_, _, device)| device.ident.clone())
+        .collect()
+}
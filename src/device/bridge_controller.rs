@@ -8,18 +8,20 @@ use homie5::{
     DeviceRef, Homie5DeviceProtocol, HomieDeviceStatus, HomieDomain, HomieID, NodeRef, PropertyRef,
 };
 
-use crate::client::HomieMQTTClient;
+use rumqttc::AsyncClient;
+
+use crate::client::{HomieMQTTClient, MqttTransport};
 
 /// Manages the root bridge/controller device in a bridge application.
 ///
 /// Handles the device lifecycle (publish, disconnect) and child device
 /// registration with proper state transitions (init → description → ready).
-pub struct BridgeController {
+pub struct BridgeController<T = AsyncClient> {
     device_ref: DeviceRef,
     device_desc: HomieDeviceDescription,
     status: HomieDeviceStatus,
     homie_proto: Homie5DeviceProtocol,
-    mqtt_client: HomieMQTTClient,
+    mqtt_client: HomieMQTTClient<T>,
     action_prop: PropertyRef,
     #[cfg(feature = "ext-meta")]
     meta_provider: Option<homie5::extensions::meta::MetaProviderProtocol>,
@@ -30,14 +32,14 @@ pub struct BridgeController {
     last_child_change: Option<Instant>,
 }
 
-impl BridgeController {
+impl<T: MqttTransport> BridgeController<T> {
     /// Create a new bridge controller with a standard `control` node and
     /// `action` enum property with the given variants (e.g., `["refresh", "update"]`).
     pub fn new(
         controller_id: HomieID,
         controller_name: &str,
         domain: HomieDomain,
-        mqtt_client: HomieMQTTClient,
+        mqtt_client: HomieMQTTClient<T>,
         action_variants: &[&str],
     ) -> Self {
         let device_ref = DeviceRef::new(domain.clone(), controller_id.clone());
@@ -68,7 +70,7 @@ impl BridgeController {
         device_ref: DeviceRef,
         device_desc: HomieDeviceDescription,
         action_prop: PropertyRef,
-        mqtt_client: HomieMQTTClient,
+        mqtt_client: HomieMQTTClient<T>,
     ) -> Self {
         let homie_proto = Homie5DeviceProtocol::new(
             device_ref.device_id().clone(),
@@ -100,7 +102,7 @@ impl BridgeController {
         &self.homie_proto
     }
 
-    pub fn mqtt_client(&self) -> &HomieMQTTClient {
+    pub fn mqtt_client(&self) -> &HomieMQTTClient<T> {
         &self.mqtt_client
     }
 
@@ -133,7 +135,7 @@ impl BridgeController {
     // ── Lifecycle ─────────────────────────────────────
 
     /// Publish the controller device (init → description → subscribe → ready).
-    pub async fn publish(&mut self) -> Result<(), BridgeControllerError> {
+    pub async fn publish(&mut self) -> Result<(), BridgeControllerError<T::Error>> {
         self.status = HomieDeviceStatus::Init;
         self.publish_state().await?;
         self.publish_description_inner().await?;
@@ -144,7 +146,7 @@ impl BridgeController {
     }
 
     /// Disconnect the controller device (lost → unsubscribe).
-    pub async fn disconnect(&mut self) -> Result<(), BridgeControllerError> {
+    pub async fn disconnect(&mut self) -> Result<(), BridgeControllerError<T::Error>> {
         self.status = HomieDeviceStatus::Disconnected;
         self.publish_state().await?;
         self.unsubscribe_props().await?;
@@ -186,7 +188,7 @@ impl BridgeController {
     /// If children are dirty and the debounce period has elapsed, republish
     /// the device description (init → description → ready). Returns `true`
     /// if a republish occurred.
-    pub async fn flush_children(&mut self) -> Result<bool, BridgeControllerError> {
+    pub async fn flush_children(&mut self) -> Result<bool, BridgeControllerError<T::Error>> {
         if !self.children_dirty {
             return Ok(false);
         }
@@ -207,7 +209,10 @@ impl BridgeController {
     /// Add a child device ID. When debounce is configured, the change is
     /// staged and published by the next [`flush_children`] call. Otherwise
     /// transitions immediately: init → publish description → ready.
-    pub async fn add_child(&mut self, child_id: HomieID) -> Result<(), BridgeControllerError> {
+    pub async fn add_child(
+        &mut self,
+        child_id: HomieID,
+    ) -> Result<(), BridgeControllerError<T::Error>> {
         self.device_desc.add_child(child_id);
         self.stage_or_publish_children().await
     }
@@ -215,7 +220,10 @@ impl BridgeController {
     /// Remove a child device ID. When debounce is configured, the change is
     /// staged and published by the next [`flush_children`] call. Otherwise
     /// transitions immediately: init → publish description → ready.
-    pub async fn remove_child(&mut self, child_id: &HomieID) -> Result<(), BridgeControllerError> {
+    pub async fn remove_child(
+        &mut self,
+        child_id: &HomieID,
+    ) -> Result<(), BridgeControllerError<T::Error>> {
         self.device_desc.remove_child(child_id);
         self.stage_or_publish_children().await
     }
@@ -223,7 +231,7 @@ impl BridgeController {
     /// Clear all child device IDs. When debounce is configured, the change is
     /// staged and published by the next [`flush_children`] call. Otherwise
     /// transitions immediately: init → publish description → ready.
-    pub async fn clear_children(&mut self) -> Result<(), BridgeControllerError> {
+    pub async fn clear_children(&mut self) -> Result<(), BridgeControllerError<T::Error>> {
         self.device_desc.children = vec![];
         self.stage_or_publish_children().await
     }
@@ -234,13 +242,16 @@ impl BridgeController {
     pub async fn publish_meta_provider_info(
         &self,
         info: &homie5::extensions::meta::MetaProviderInfo,
-    ) -> Result<(), BridgeControllerError> {
+    ) -> Result<(), BridgeControllerError<T::Error>> {
         let provider = self
             .meta_provider
             .as_ref()
             .ok_or_else(|| BridgeControllerError::MetaProviderNotSet)?;
         let publish = provider.publish_provider_info(info)?;
-        self.mqtt_client.homie_publish(publish).await?;
+        self.mqtt_client
+            .homie_publish(publish)
+            .await
+            .map_err(BridgeControllerError::MqttClient)?;
         Ok(())
     }
 
@@ -248,7 +259,7 @@ impl BridgeController {
 
     /// Stage a children change for deferred publish, or publish immediately
     /// when debounce is not configured.
-    async fn stage_or_publish_children(&mut self) -> Result<(), BridgeControllerError> {
+    async fn stage_or_publish_children(&mut self) -> Result<(), BridgeControllerError<T::Error>> {
         self.device_desc.update_version();
         if self.children_debounce.is_some() {
             self.children_dirty = true;
@@ -259,32 +270,44 @@ impl BridgeController {
         }
     }
 
-    async fn publish_state(&self) -> Result<(), BridgeControllerError> {
+    async fn publish_state(&self) -> Result<(), BridgeControllerError<T::Error>> {
         let p = self.homie_proto.publish_state(self.status);
-        self.mqtt_client.homie_publish(p).await?;
+        self.mqtt_client
+            .homie_publish(p)
+            .await
+            .map_err(BridgeControllerError::MqttClient)?;
         Ok(())
     }
 
-    async fn publish_description_inner(&self) -> Result<(), BridgeControllerError> {
+    async fn publish_description_inner(&self) -> Result<(), BridgeControllerError<T::Error>> {
         let p = self.homie_proto.publish_description(&self.device_desc)?;
-        self.mqtt_client.homie_publish(p).await?;
+        self.mqtt_client
+            .homie_publish(p)
+            .await
+            .map_err(BridgeControllerError::MqttClient)?;
         Ok(())
     }
 
-    async fn subscribe_props(&self) -> Result<(), BridgeControllerError> {
+    async fn subscribe_props(&self) -> Result<(), BridgeControllerError<T::Error>> {
         let p = self.homie_proto.subscribe_props(&self.device_desc)?;
-        self.mqtt_client.homie_subscribe(p).await?;
+        self.mqtt_client
+            .homie_subscribe(p)
+            .await
+            .map_err(BridgeControllerError::MqttClient)?;
         Ok(())
     }
 
-    async fn unsubscribe_props(&self) -> Result<(), BridgeControllerError> {
+    async fn unsubscribe_props(&self) -> Result<(), BridgeControllerError<T::Error>> {
         let p = self.homie_proto.unsubscribe_props(&self.device_desc)?;
-        self.mqtt_client.homie_unsubscribe(p).await?;
+        self.mqtt_client
+            .homie_unsubscribe(p)
+            .await
+            .map_err(BridgeControllerError::MqttClient)?;
         Ok(())
     }
 
     /// Republish description with init→ready state transition.
-    async fn republish_description(&mut self) -> Result<(), BridgeControllerError> {
+    async fn republish_description(&mut self) -> Result<(), BridgeControllerError<T::Error>> {
         self.status = HomieDeviceStatus::Init;
         self.publish_state().await?;
         self.publish_description_inner().await?;
@@ -329,11 +352,12 @@ pub fn build_bridge_controller_description(
     (prop, desc)
 }
 
-/// Errors from BridgeController operations.
+/// Errors from BridgeController operations. `E` is the error type of the
+/// underlying [`MqttTransport`].
 #[derive(Debug, thiserror::Error)]
-pub enum BridgeControllerError {
+pub enum BridgeControllerError<E = rumqttc::ClientError> {
     #[error("MQTT client error: {0}")]
-    MqttClient(#[from] rumqttc::ClientError),
+    MqttClient(#[source] E),
     #[error("Homie protocol error: {0}")]
     HomieProtocol(#[from] homie5::Homie5ProtocolError),
     #[cfg(feature = "ext-meta")]
@@ -343,3 +367,11 @@ pub enum BridgeControllerError {
     #[error("Meta provider not set on BridgeController")]
     MetaProviderNotSet,
 }
+
+// Not derived via `#[from]`: a generic `From<E>` would overlap with the
+// protocol error conversions above.
+impl From<rumqttc::ClientError> for BridgeControllerError<rumqttc::ClientError> {
+    fn from(err: rumqttc::ClientError) -> Self {
+        Self::MqttClient(err)
+    }
+}
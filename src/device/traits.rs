@@ -4,23 +4,27 @@ use homie5::{
     Homie5DeviceProtocol, HomieDeviceStatus, HomieDomain, HomieID, PropertyRef,
 };
 
-use crate::client::HomieMQTTClient;
+use rumqttc::AsyncClient;
 
-pub trait HomieDeviceCore {
+use crate::client::{HomieMQTTClient, MqttTransport};
+
+/// Accessors every Homie device provides. `T` is the [`MqttTransport`] of
+/// the device's client (`rumqttc` by default).
+pub trait HomieDeviceCore<T: MqttTransport = AsyncClient> {
     fn homie_domain(&self) -> &HomieDomain;
     fn homie_id(&self) -> &HomieID;
     fn device_ref(&self) -> &DeviceRef;
     fn description(&self) -> &HomieDeviceDescription;
-    fn client(&self) -> &HomieMQTTClient;
+    fn client(&self) -> &HomieMQTTClient<T>;
     fn homie_proto(&self) -> &Homie5DeviceProtocol;
     fn state(&self) -> HomieDeviceStatus;
     fn set_state(&mut self, state: HomieDeviceStatus);
 }
 
-pub trait HomieDevice: HomieDeviceCore
+pub trait HomieDevice<T: MqttTransport = AsyncClient>: HomieDeviceCore<T>
 where
     Self: Send + Sync,
-    Self::ResultError: From<homie5::Homie5ProtocolError> + From<rumqttc::ClientError> + Send + Sync,
+    Self::ResultError: From<homie5::Homie5ProtocolError> + From<T::Error> + Send + Sync,
 {
     type ResultError;
 
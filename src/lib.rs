@@ -19,6 +19,10 @@ pub mod device;
 #[cfg(feature = "framework")]
 pub mod settings;
 
+// ── test support ─────────────────────────────────
+#[cfg(feature = "test-support")]
+pub mod test_support;
+
 // ── tokio modules ────────────────────────────────────
 #[cfg(feature = "tokio")]
 mod event_multiplexer;
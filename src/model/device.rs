@@ -41,6 +41,10 @@ pub struct Device {
     pub description: Option<HomieDeviceDescription>,
     pub prop_values: PropertyValueStore,
     pub alerts: AlertStore,
+    /// Number of property value and target payloads rejected because they
+    /// did not match the description (see
+    /// [`DiscoveryAction::InvalidPropertyValue`](super::DiscoveryAction::InvalidPropertyValue)).
+    pub rejected_payloads: u64,
     /// Per-provider meta overlay documents for this device.
     #[cfg(feature = "ext-meta")]
     pub meta_overlays: HashMap<HomieID, homie5::extensions::meta::MetaDeviceOverlay>,
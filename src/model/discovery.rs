@@ -1,7 +1,13 @@
+use std::sync::Arc;
+
 use chrono::{DateTime, Utc};
 #[cfg(feature = "ext-meta")]
 use homie5::HomieDomain;
-use homie5::{DeviceRef, Homie5Message, HomieDeviceStatus, HomieID, HomieValue, PropertyRef};
+use homie5::{
+    DeviceRef, Homie5Message, Homie5ProtocolError, HomieDeviceStatus, HomieID, HomieValue,
+    PropertyRef,
+};
+use thiserror::Error;
 
 use super::Device;
 
@@ -36,6 +42,13 @@ pub enum DiscoveryAction {
         prop: PropertyRef,
         value: HomieValue,
     },
+    /// A property value or target payload was rejected. Counted in
+    /// [`Device::rejected_payloads`].
+    InvalidPropertyValue {
+        prop: PropertyRef,
+        raw: String,
+        error: InvalidPropertyValueError,
+    },
     DeviceAlert {
         device: DeviceRef,
         alert_id: HomieID,
@@ -75,3 +88,14 @@ pub enum DiscoveryAction {
     },
     Unhandled(Homie5Message),
 }
+
+/// Why a property payload was rejected.
+#[derive(Debug, Clone, Error)]
+pub enum InvalidPropertyValueError {
+    #[error("Device has no description")]
+    MissingDescription,
+    #[error("Property is not part of the device description")]
+    UnknownProperty,
+    #[error("Payload does not match the property description: {0}")]
+    Parse(Arc<Homie5ProtocolError>),
+}
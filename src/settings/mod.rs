@@ -4,7 +4,7 @@ use std::str::FromStr;
 use homie5::{HomieDomain, HomieID};
 use rand::{distr::Alphanumeric, rng, RngExt};
 
-use crate::client::MqttClientConfig;
+use crate::client::{BrokerEndpoint, MqttClientConfig, MqttTransportKind};
 use crate::util::UnwrapOrExit;
 
 // ── Prefixed env-var helpers ────────────────────────────────────────────
@@ -74,7 +74,10 @@ pub fn optional_path_setting(prefix: &str, name: &str) -> Option<PathBuf> {
 
 #[cfg(test)]
 mod tests {
-    use super::parse_bool_setting;
+    use homie5::HomieDomain;
+
+    use super::{parse_bool_setting, HomieSettings};
+    use crate::client::BrokerEndpoint;
 
     #[test]
     fn parse_bool_setting_accepts_true_and_false_only() {
@@ -88,6 +91,29 @@ mod tests {
         assert_eq!(parse_bool_setting("enabled"), None);
         assert_eq!(parse_bool_setting(""), None);
     }
+
+    #[test]
+    fn single_host_config_keeps_hostname_overrides() {
+        let mut settings =
+            HomieSettings::from_env("HC_SETTINGS_TEST_UNSET", "test-", HomieDomain::Default);
+        let config = settings
+            .to_mqtt_client_config()
+            .hostname("broker")
+            .port(1884);
+        assert_eq!(
+            config.broker_endpoints(),
+            vec![BrokerEndpoint::new("broker", 1884)]
+        );
+
+        settings.endpoints = vec![
+            BrokerEndpoint::new("primary", 1883),
+            BrokerEndpoint::new("backup", 1883),
+        ];
+        assert_eq!(
+            settings.to_mqtt_client_config().broker_endpoints(),
+            settings.endpoints
+        );
+    }
 }
 
 // ── Direct env-var helpers (for crates with non-standard env var names) ─
@@ -124,6 +150,9 @@ where
 pub struct HomieSettings {
     pub hostname: String,
     pub port: u16,
+    /// All brokers from `HOMIE_HOST`, in failover order. `hostname` and
+    /// `port` hold the first one.
+    pub endpoints: Vec<BrokerEndpoint>,
     pub username: String,
     pub password: String,
     pub client_id: String,
@@ -134,18 +163,28 @@ pub struct HomieSettings {
     pub ca_path: Option<PathBuf>,
     pub client_cert_path: Option<PathBuf>,
     pub client_key_path: Option<PathBuf>,
+    pub transport: MqttTransportKind,
+    pub ws_path: String,
 }
 
 impl HomieSettings {
     /// Read HomieSettings from environment variables with the given prefix.
     ///
     /// Reads: `{prefix}_HOMIE_HOST`, `{prefix}_HOMIE_PORT`, etc.
+    /// `HOMIE_HOST` may list several brokers separated by commas
+    /// (`host[:port]`, `HOMIE_PORT` being the default port).
     ///
     /// `client_id_prefix` is used for auto-generated client IDs (e.g., "hcactl-").
     /// `default_domain` sets the default HomieDomain when the env var is absent.
     pub fn from_env(prefix: &str, client_id_prefix: &str, default_domain: HomieDomain) -> Self {
-        let hostname = string_setting(prefix, "HOMIE_HOST", "localhost");
-        let port = number_setting(prefix, "HOMIE_PORT", 1883u16);
+        let default_port = number_setting(prefix, "HOMIE_PORT", 1883u16);
+        let endpoints = BrokerEndpoint::parse_list(
+            &string_setting(prefix, "HOMIE_HOST", "localhost"),
+            default_port,
+        )
+        .unwrap_or_exit("Invalid HOMIE_HOST setting");
+        let hostname = endpoints[0].host.clone();
+        let port = endpoints[0].port;
         let username = string_setting(prefix, "HOMIE_USERNAME", String::default());
         let password = string_setting(prefix, "HOMIE_PASSWORD", String::default());
         let client_id = string_setting(
@@ -172,10 +211,13 @@ impl HomieSettings {
         let ca_path = optional_path_setting(prefix, "HOMIE_CA_PATH");
         let client_cert_path = optional_path_setting(prefix, "HOMIE_CLIENT_CERT");
         let client_key_path = optional_path_setting(prefix, "HOMIE_CLIENT_KEY");
+        let transport = generic_setting(prefix, "HOMIE_TRANSPORT", MqttTransportKind::Tcp);
+        let ws_path = string_setting(prefix, "HOMIE_WS_PATH", "/mqtt");
 
         Self {
             hostname,
             port,
+            endpoints,
             username,
             password,
             client_id,
@@ -186,11 +228,17 @@ impl HomieSettings {
             ca_path,
             client_cert_path,
             client_key_path,
+            transport,
+            ws_path,
         }
     }
 
+    /// Client config for these settings. The endpoint list is only set when
+    /// `HOMIE_HOST` names several brokers, so a single broker can still be
+    /// overridden with [`MqttClientConfig::hostname`] and
+    /// [`MqttClientConfig::port`].
     pub fn to_mqtt_client_config(&self) -> MqttClientConfig {
-        MqttClientConfig::new(&self.hostname)
+        let config = MqttClientConfig::new(&self.hostname)
             .port(self.port)
             .username(&self.username)
             .password(&self.password)
@@ -199,5 +247,12 @@ impl HomieSettings {
             .ca_path(self.ca_path.as_ref())
             .client_cert_path(self.client_cert_path.as_ref())
             .client_key_path(self.client_key_path.as_ref())
+            .transport(self.transport)
+            .ws_path(&self.ws_path);
+        if self.endpoints.len() > 1 {
+            config.endpoints(self.endpoints.clone())
+        } else {
+            config
+        }
     }
 }
@@ -48,6 +48,7 @@ impl DeviceStore {
                 description: None,
                 prop_values: PropertyValueStore::new(),
                 alerts: AlertStore::new(),
+                rejected_payloads: 0,
                 #[cfg(feature = "ext-meta")]
                 meta_overlays: std::collections::HashMap::new(),
             };
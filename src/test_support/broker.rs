@@ -0,0 +1,525 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use homie5::{HomieDomain, HOMIE_VERSION};
+use rumqttc::mqttbytes::{self, matches};
+use rumqttc::{
+    ConnAck, ConnectReturnCode, Packet, PubAck, PubComp, PubRec, Publish, QoS, SubAck, Subscribe,
+    SubscribeReasonCode, UnsubAck, Unsubscribe,
+};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+use crate::client::{
+    run_homie_client_with_client_options, HomieClientError, HomieClientEvent, HomieClientHandle,
+    HomieMQTTClient, MqttClientConfig, ReconnectPolicy,
+};
+
+/// Upper bound for packets accepted from test clients.
+const MAX_PACKET_SIZE: usize = 16 * 1024 * 1024;
+
+/// Error returned by [`TestBroker::wait_for_retained`] when the expected
+/// retained payload did not show up within the allowed wait time.
+#[derive(Debug, Error)]
+#[error("timed out waiting for retained message on topic [{topic}]")]
+pub struct RetainedTimeout {
+    pub topic: String,
+}
+
+/// Minimal MQTT 3.1.1 broker for integration tests.
+///
+/// Supports what the Homie client layer needs: CONNECT with last will,
+/// PUBLISH with retained storage (an empty retained payload clears the
+/// topic), SUBSCRIBE/UNSUBSCRIBE with `+`/`#` wildcards and retained
+/// delivery, PING and DISCONNECT. Deliveries to subscribers are downgraded
+/// to at most QoS 1. Sessions are never persisted: every connection starts
+/// clean regardless of the `clean_session` flag.
+///
+/// The broker shuts down when dropped.
+pub struct TestBroker {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    stop_sender: watch::Sender<bool>,
+    accept_task: JoinHandle<()>,
+}
+
+impl TestBroker {
+    /// Binds the broker to an ephemeral loopback port and starts accepting
+    /// connections.
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let addr = listener.local_addr()?;
+        let (retained_changed, _) = watch::channel(0u64);
+        let shared = Arc::new(Shared {
+            state: Mutex::new(BrokerState::default()),
+            retained_changed,
+        });
+        let (stop_sender, mut stop_receiver) = watch::channel(false);
+
+        let accept_shared = Arc::clone(&shared);
+        let accept_task = tokio::spawn(async move {
+            loop {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    _ = stop_receiver.changed() => break,
+                };
+                match accepted {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve_connection(Arc::clone(&accept_shared), stream));
+                    }
+                    Err(err) => {
+                        log::error!("TestBroker: error accepting connection: {err}");
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            addr,
+            shared,
+            stop_sender,
+            accept_task,
+        })
+    }
+
+    /// Address the broker listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Client configuration pointing at this broker. Reconnects are retried
+    /// every 50 ms so tests around [`disconnect_all`](Self::disconnect_all)
+    /// stay fast.
+    pub fn client_config(&self, client_id: &str) -> MqttClientConfig {
+        MqttClientConfig::new(self.addr.ip().to_string())
+            .port(self.addr.port())
+            .client_id(client_id)
+            .mqtt_channel_size(1024)
+            .reconnect_policy(ReconnectPolicy::fixed(Duration::from_millis(50)))
+    }
+
+    /// Starts a Homie client event loop connected to this broker.
+    pub fn client(
+        &self,
+        client_id: &str,
+    ) -> Result<
+        (
+            HomieClientHandle,
+            HomieMQTTClient,
+            mpsc::Receiver<HomieClientEvent>,
+        ),
+        HomieClientError,
+    > {
+        let config = self.client_config(client_id);
+        run_homie_client_with_client_options(config.to_mqtt_options()?, config.to_client_options())
+    }
+
+    /// Returns the retained payload stored for `topic`.
+    pub fn retained(&self, topic: &str) -> Option<Bytes> {
+        self.shared.state().retained.get(topic).cloned()
+    }
+
+    /// Returns all retained topics matching the MQTT topic `filter`.
+    pub fn retained_matching(&self, filter: &str) -> BTreeMap<String, Bytes> {
+        self.shared
+            .state()
+            .retained
+            .iter()
+            .filter(|(topic, _)| matches(topic, filter))
+            .map(|(topic, payload)| (topic.clone(), payload.clone()))
+            .collect()
+    }
+
+    /// Returns the retained state of a Homie domain (`{domain}/5/#`).
+    pub fn retained_in_domain(&self, homie_domain: &HomieDomain) -> BTreeMap<String, Bytes> {
+        self.retained_matching(&format!("{}/{}/#", homie_domain, HOMIE_VERSION))
+    }
+
+    /// Asserts that `topic` currently holds the retained `expected` payload.
+    #[track_caller]
+    pub fn assert_retained(&self, topic: &str, expected: impl AsRef<[u8]>) {
+        let expected = expected.as_ref();
+        match self.retained(topic) {
+            Some(payload) if payload.as_ref() == expected => {}
+            Some(payload) => panic!(
+                "retained payload mismatch on [{}]: expected {:?}, got {:?}",
+                topic,
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(&payload)
+            ),
+            None => panic!(
+                "no retained message on [{}] (expected {:?})",
+                topic,
+                String::from_utf8_lossy(expected)
+            ),
+        }
+    }
+
+    /// Asserts that no retained message is stored for `topic`.
+    #[track_caller]
+    pub fn assert_not_retained(&self, topic: &str) {
+        if let Some(payload) = self.retained(topic) {
+            panic!(
+                "unexpected retained message on [{}]: {:?}",
+                topic,
+                String::from_utf8_lossy(&payload)
+            );
+        }
+    }
+
+    /// Waits until `topic` holds the retained `expected` payload.
+    ///
+    /// Publishes travel through the client event loop and the broker
+    /// asynchronously; use this instead of [`assert_retained`](Self::assert_retained)
+    /// right after publishing.
+    pub async fn wait_for_retained(
+        &self,
+        topic: &str,
+        expected: impl AsRef<[u8]>,
+        max_wait: Duration,
+    ) -> Result<(), RetainedTimeout> {
+        let expected = expected.as_ref();
+        let mut rx = self.shared.retained_changed.subscribe();
+        tokio::time::timeout(max_wait, async move {
+            loop {
+                rx.mark_unchanged();
+                if self.retained(topic).is_some_and(|p| p.as_ref() == expected) {
+                    return;
+                }
+                if rx.changed().await.is_err() {
+                    return;
+                }
+            }
+        })
+        .await
+        .map_err(|_| RetainedTimeout {
+            topic: topic.to_owned(),
+        })
+    }
+
+    /// Removes all retained messages.
+    pub fn clear_retained(&self) {
+        self.shared.state().retained.clear();
+        self.shared.notify_retained();
+    }
+
+    /// Client ids of all currently connected clients.
+    pub fn connected_clients(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .shared
+            .state()
+            .sessions
+            .values()
+            .map(|s| s.client_id.clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Drops every client connection without a DISCONNECT, as a broker
+    /// restart would. Last wills are published; clients reconnect through
+    /// their regular retry logic.
+    pub fn disconnect_all(&self) {
+        for session in self.shared.state().sessions.values() {
+            let _ = session.kick.send(true);
+        }
+    }
+
+    /// Stops accepting connections and drops all connected clients.
+    pub async fn shutdown(mut self) {
+        let _ = self.stop_sender.send(true);
+        self.disconnect_all();
+        let _ = (&mut self.accept_task).await;
+    }
+}
+
+impl Drop for TestBroker {
+    fn drop(&mut self) {
+        let _ = self.stop_sender.send(true);
+        self.disconnect_all();
+        self.accept_task.abort();
+    }
+}
+
+// ── Broker state ──────────────────────────────────
+
+struct Shared {
+    state: Mutex<BrokerState>,
+    /// Bumped on every retained change so waiters can re-check.
+    retained_changed: watch::Sender<u64>,
+}
+
+#[derive(Default)]
+struct BrokerState {
+    retained: BTreeMap<String, Bytes>,
+    sessions: HashMap<u64, Session>,
+    next_session_id: u64,
+}
+
+struct Session {
+    client_id: String,
+    /// Topic filter → granted QoS.
+    subscriptions: Vec<(String, QoS)>,
+    outgoing: mpsc::UnboundedSender<Packet>,
+    kick: watch::Sender<bool>,
+    next_pkid: u16,
+}
+
+impl Session {
+    fn deliver(&mut self, topic: &str, payload: Bytes, qos: QoS, retain: bool) {
+        let mut publish = Publish::from_bytes(topic, qos, payload);
+        publish.retain = retain;
+        if qos != QoS::AtMostOnce {
+            self.next_pkid = self.next_pkid.checked_add(1).unwrap_or(1);
+            publish.pkid = self.next_pkid;
+        }
+        let _ = self.outgoing.send(Packet::Publish(publish));
+    }
+
+    fn granted_qos(&self, topic: &str) -> Option<QoS> {
+        self.subscriptions
+            .iter()
+            .filter(|(filter, _)| matches(topic, filter))
+            .map(|(_, qos)| *qos)
+            .max_by_key(|qos| *qos as u8)
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, BrokerState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn notify_retained(&self) {
+        self.retained_changed
+            .send_modify(|v| *v = v.wrapping_add(1));
+    }
+
+    fn register(
+        &self,
+        client_id: String,
+        outgoing: mpsc::UnboundedSender<Packet>,
+        kick: watch::Sender<bool>,
+    ) -> u64 {
+        let mut state = self.state();
+        // A new connection with the same client id takes over the session.
+        for session in state.sessions.values() {
+            if session.client_id == client_id {
+                let _ = session.kick.send(true);
+            }
+        }
+        state.next_session_id += 1;
+        let id = state.next_session_id;
+        state.sessions.insert(
+            id,
+            Session {
+                client_id,
+                subscriptions: Vec::new(),
+                outgoing,
+                kick,
+                next_pkid: 0,
+            },
+        );
+        id
+    }
+
+    fn unregister(&self, session_id: u64) {
+        self.state().sessions.remove(&session_id);
+    }
+
+    fn publish(&self, topic: &str, payload: Bytes, qos: QoS, retain: bool) {
+        let mut state = self.state();
+        if retain {
+            if payload.is_empty() {
+                state.retained.remove(topic);
+            } else {
+                state.retained.insert(topic.to_owned(), payload.clone());
+            }
+        }
+        for session in state.sessions.values_mut() {
+            if let Some(granted) = session.granted_qos(topic) {
+                let qos = min_qos(qos, granted);
+                session.deliver(topic, payload.clone(), qos, false);
+            }
+        }
+        drop(state);
+        if retain {
+            self.notify_retained();
+        }
+    }
+
+    fn subscribe(&self, session_id: u64, subscribe: Subscribe) {
+        let mut state = self.state();
+        let BrokerState {
+            retained, sessions, ..
+        } = &mut *state;
+        let Some(session) = sessions.get_mut(&session_id) else {
+            return;
+        };
+
+        let mut return_codes = Vec::with_capacity(subscribe.filters.len());
+        for filter in &subscribe.filters {
+            let granted = min_qos(filter.qos, QoS::AtLeastOnce);
+            session.subscriptions.retain(|(f, _)| f != &filter.path);
+            session.subscriptions.push((filter.path.clone(), granted));
+            return_codes.push(SubscribeReasonCode::Success(granted));
+        }
+        let _ = session
+            .outgoing
+            .send(Packet::SubAck(SubAck::new(subscribe.pkid, return_codes)));
+
+        for (topic, payload) in retained.iter() {
+            let granted = subscribe
+                .filters
+                .iter()
+                .filter(|f| matches(topic, &f.path))
+                .map(|f| min_qos(f.qos, QoS::AtLeastOnce))
+                .max_by_key(|qos| *qos as u8);
+            if let Some(qos) = granted {
+                session.deliver(topic, payload.clone(), qos, true);
+            }
+        }
+    }
+
+    fn unsubscribe(&self, session_id: u64, unsubscribe: Unsubscribe) {
+        let mut state = self.state();
+        let Some(session) = state.sessions.get_mut(&session_id) else {
+            return;
+        };
+        session
+            .subscriptions
+            .retain(|(filter, _)| !unsubscribe.topics.contains(filter));
+        let _ = session
+            .outgoing
+            .send(Packet::UnsubAck(UnsubAck::new(unsubscribe.pkid)));
+    }
+}
+
+fn min_qos(a: QoS, b: QoS) -> QoS {
+    if (a as u8) <= (b as u8) {
+        a
+    } else {
+        b
+    }
+}
+
+// ── Connection handling ───────────────────────────
+
+async fn serve_connection(shared: Arc<Shared>, stream: TcpStream) {
+    let _ = stream.set_nodelay(true);
+    let (mut reader, writer) = stream.into_split();
+    let mut buf = BytesMut::with_capacity(4096);
+
+    let connect = match read_packet(&mut reader, &mut buf).await {
+        Ok(Some(Packet::Connect(connect))) => connect,
+        Ok(_) => return,
+        Err(err) => {
+            log::debug!("TestBroker: error reading CONNECT: {err}");
+            return;
+        }
+    };
+
+    let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+    let (kick, mut kick_rx) = watch::channel(false);
+    let session_id = shared.register(connect.client_id.clone(), outgoing.clone(), kick);
+    let writer_task = tokio::spawn(write_packets(writer, outgoing_rx));
+    let _ = outgoing.send(Packet::ConnAck(ConnAck::new(
+        ConnectReturnCode::Success,
+        false,
+    )));
+
+    let mut clean_disconnect = false;
+    loop {
+        let packet = tokio::select! {
+            packet = read_packet(&mut reader, &mut buf) => packet,
+            _ = kick_rx.changed() => break,
+        };
+        let packet = match packet {
+            Ok(Some(packet)) => packet,
+            Ok(None) => break,
+            Err(err) => {
+                log::debug!("TestBroker: connection error: {err}");
+                break;
+            }
+        };
+        match packet {
+            Packet::Publish(publish) => {
+                // Route before acknowledging: once the client sees the ack,
+                // the broker state already reflects the publish.
+                shared.publish(&publish.topic, publish.payload, publish.qos, publish.retain);
+                match publish.qos {
+                    QoS::AtMostOnce => {}
+                    QoS::AtLeastOnce => {
+                        let _ = outgoing.send(Packet::PubAck(PubAck::new(publish.pkid)));
+                    }
+                    QoS::ExactlyOnce => {
+                        let _ = outgoing.send(Packet::PubRec(PubRec::new(publish.pkid)));
+                    }
+                }
+            }
+            Packet::PubRel(rel) => {
+                let _ = outgoing.send(Packet::PubComp(PubComp::new(rel.pkid)));
+            }
+            Packet::Subscribe(subscribe) => shared.subscribe(session_id, subscribe),
+            Packet::Unsubscribe(unsubscribe) => shared.unsubscribe(session_id, unsubscribe),
+            Packet::PingReq => {
+                let _ = outgoing.send(Packet::PingResp);
+            }
+            Packet::Disconnect => {
+                clean_disconnect = true;
+                break;
+            }
+            // Acknowledgements for our deliveries need no bookkeeping.
+            _ => {}
+        }
+    }
+
+    shared.unregister(session_id);
+    drop(outgoing);
+    if !clean_disconnect {
+        if let Some(will) = connect.last_will {
+            shared.publish(&will.topic, will.message, will.qos, will.retain);
+        }
+    }
+    // The writer ends once all senders are gone; dropping it closes the socket.
+    let _ = writer_task.await;
+}
+
+/// Reads the next complete packet. `Ok(None)` signals a closed connection.
+async fn read_packet(
+    reader: &mut OwnedReadHalf,
+    buf: &mut BytesMut,
+) -> Result<Option<Packet>, mqttbytes::Error> {
+    loop {
+        match Packet::read(buf, MAX_PACKET_SIZE) {
+            Ok(packet) => return Ok(Some(packet)),
+            Err(mqttbytes::Error::InsufficientBytes(_)) => {}
+            Err(err) => return Err(err),
+        }
+        if reader.read_buf(buf).await? == 0 {
+            return Ok(None);
+        }
+    }
+}
+
+async fn write_packets(mut writer: OwnedWriteHalf, mut rx: mpsc::UnboundedReceiver<Packet>) {
+    let mut buf = BytesMut::with_capacity(4096);
+    while let Some(packet) = rx.recv().await {
+        buf.clear();
+        if let Err(err) = packet.write(&mut buf, MAX_PACKET_SIZE) {
+            log::error!("TestBroker: error encoding packet: {err}");
+            continue;
+        }
+        if writer.write_all(&buf).await.is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
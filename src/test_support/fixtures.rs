@@ -0,0 +1,196 @@
+//! Message builders and transport stubs shared by the integration tests.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use homie5::client::{Publish, QoS};
+use tokio::sync::mpsc;
+
+use crate::client::{MqttEventLoop, MqttTransport, TransportEvent};
+
+/// A QoS 1 publish with a UTF-8 payload.
+pub fn publish(topic: &str, payload: &str, retain: bool) -> Publish {
+    Publish {
+        topic: topic.to_string(),
+        payload: payload.as_bytes().to_vec(),
+        qos: QoS::AtLeastOnce,
+        retain,
+    }
+}
+
+/// Request sent through a [`RecordingTransport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportRequest {
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        qos: QoS,
+        retain: bool,
+    },
+    /// One SUBSCRIBE packet with its filters.
+    Subscribe(Vec<String>),
+    /// One UNSUBSCRIBE packet with its filters.
+    Unsubscribe(Vec<String>),
+    Disconnect,
+}
+
+/// [`MqttTransport`] that records every request instead of sending it.
+///
+/// Multi-topic (un)subscriptions are recorded as one request per packet.
+/// With [`fail_requests`](Self::fail_requests) set, requests fail with an
+/// I/O error and are not recorded.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingTransport {
+    requests: Arc<Mutex<Vec<TransportRequest>>>,
+    failing: Arc<AtomicBool>,
+}
+
+impl RecordingTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn recorded(&self) -> MutexGuard<'_, Vec<TransportRequest>> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record(&self, request: TransportRequest) -> Result<(), std::io::Error> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(std::io::ErrorKind::BrokenPipe.into());
+        }
+        self.recorded().push(request);
+        Ok(())
+    }
+
+    /// Makes all following requests fail (or succeed again).
+    pub fn fail_requests(&self, failing: bool) {
+        self.failing.store(failing, Ordering::SeqCst);
+    }
+
+    /// All recorded requests, in order.
+    pub fn requests(&self) -> Vec<TransportRequest> {
+        self.recorded().clone()
+    }
+
+    /// Recorded publishes as `topic=payload`.
+    pub fn publishes(&self) -> Vec<String> {
+        self.recorded()
+            .iter()
+            .filter_map(|request| match request {
+                TransportRequest::Publish { topic, payload, .. } => {
+                    Some(format!("{topic}={}", String::from_utf8_lossy(payload)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Filters of every recorded SUBSCRIBE packet.
+    pub fn subscribe_packets(&self) -> Vec<Vec<String>> {
+        self.recorded()
+            .iter()
+            .filter_map(|request| match request {
+                TransportRequest::Subscribe(topics) => Some(topics.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Filters of every recorded UNSUBSCRIBE packet.
+    pub fn unsubscribe_packets(&self) -> Vec<Vec<String>> {
+        self.recorded()
+            .iter()
+            .filter_map(|request| match request {
+                TransportRequest::Unsubscribe(topics) => Some(topics.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Waits up to `max_wait` until at least `count` publishes were
+    /// recorded and returns them. Panics on timeout.
+    pub async fn wait_for_publishes(&self, count: usize, max_wait: Duration) -> Vec<String> {
+        tokio::time::timeout(max_wait, async {
+            loop {
+                let publishes = self.publishes();
+                if publishes.len() >= count {
+                    return publishes;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("publishes were not sent")
+    }
+}
+
+impl MqttTransport for RecordingTransport {
+    type Error = std::io::Error;
+
+    async fn publish(
+        &self,
+        topic: String,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+    ) -> Result<(), Self::Error> {
+        self.record(TransportRequest::Publish {
+            topic,
+            payload,
+            qos,
+            retain,
+        })
+    }
+
+    async fn subscribe(&self, topic: String, qos: QoS) -> Result<(), Self::Error> {
+        self.subscribe_many(vec![(topic, qos)]).await
+    }
+
+    async fn subscribe_many(&self, topics: Vec<(String, QoS)>) -> Result<(), Self::Error> {
+        let topics = topics.into_iter().map(|(topic, _)| topic).collect();
+        self.record(TransportRequest::Subscribe(topics))
+    }
+
+    async fn unsubscribe(&self, topic: String) -> Result<(), Self::Error> {
+        self.unsubscribe_many(vec![topic]).await
+    }
+
+    async fn unsubscribe_many(&self, topics: Vec<String>) -> Result<(), Self::Error> {
+        self.record(TransportRequest::Unsubscribe(topics))
+    }
+
+    async fn disconnect(&self) -> Result<(), Self::Error> {
+        self.record(TransportRequest::Disconnect)
+    }
+}
+
+/// Sends scripted events into a [`ScriptedEventLoop`].
+pub type EventScript = mpsc::UnboundedSender<Result<TransportEvent, std::io::Error>>;
+
+/// [`MqttEventLoop`] that yields the events and connection errors sent
+/// through its [`EventScript`], and idles once the script is dropped.
+pub struct ScriptedEventLoop(mpsc::UnboundedReceiver<Result<TransportEvent, std::io::Error>>);
+
+impl ScriptedEventLoop {
+    pub fn new() -> (EventScript, Self) {
+        let (script, events) = mpsc::unbounded_channel();
+        (script, Self(events))
+    }
+
+    /// An event loop that never yields anything.
+    pub fn idle() -> Self {
+        Self::new().1
+    }
+}
+
+impl MqttEventLoop for ScriptedEventLoop {
+    type Error = std::io::Error;
+
+    async fn poll(&mut self) -> Result<TransportEvent, Self::Error> {
+        match self.0.recv().await {
+            Some(event) => event,
+            None => std::future::pending().await,
+        }
+    }
+}
@@ -0,0 +1,18 @@
+//! In-process MQTT infrastructure for integration tests.
+//!
+//! [`TestBroker`] is a small MQTT 3.1.1 broker bound to a loopback port. It
+//! hands out ready-to-use [`HomieMQTTClient`](crate::client::HomieMQTTClient)s
+//! driven by the regular client event loop, keeps retained topics, and lets
+//! tests inspect and assert on the retained state of a Homie domain — so a
+//! device publish and a controller discovery can run against each other in a
+//! single `#[tokio::test]` without an external broker.
+//!
+//! Tests that drive the client loop without any broker use
+//! [`RecordingTransport`] and [`ScriptedEventLoop`] instead; [`publish`]
+//! builds the messages for either.
+
+mod broker;
+mod fixtures;
+
+pub use broker::*;
+pub use fixtures::*;
@@ -0,0 +1,133 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{
+        run_homie_client_with_client_options, BackpressurePolicy, HomieClientEvent,
+        HomieClientHandle, MqttClientConfig,
+    };
+    use hc_homie5::test_support::{publish, TestBroker};
+    use homie5::client::{QoS, Subscription};
+    use homie5::Homie5Message;
+    use tokio::sync::mpsc;
+
+    const WAIT: Duration = Duration::from_secs(5);
+    const CHANNEL_SIZE: usize = 4;
+
+    async fn slow_consumer(
+        broker: &TestBroker,
+        policy: BackpressurePolicy,
+    ) -> (HomieClientHandle, mpsc::Receiver<HomieClientEvent>) {
+        let config: MqttClientConfig = broker
+            .client_config("slow-consumer")
+            .mqtt_channel_size(CHANNEL_SIZE)
+            .backpressure(policy);
+        let (handle, client, events) = run_homie_client_with_client_options(
+            config.to_mqtt_options().unwrap(),
+            config.to_client_options(),
+        )
+        .unwrap();
+        client
+            .homie_subscribe(std::iter::once(Subscription {
+                topic: "homie/5/#".to_string(),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+        (handle, events)
+    }
+
+    /// Waits until the event loop received `count` messages, without
+    /// consuming any events.
+    async fn wait_for_messages_in(handle: &HomieClientHandle, count: u64) {
+        tokio::time::timeout(WAIT, async {
+            while handle.stats().messages_in < count {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("event loop stalled");
+    }
+
+    /// Property values received until the channel runs dry.
+    async fn drain_values(events: &mut mpsc::Receiver<HomieClientEvent>) -> Vec<(String, String)> {
+        let mut values = Vec::new();
+        while let Ok(Some(event)) =
+            tokio::time::timeout(Duration::from_millis(100), events.recv()).await
+        {
+            if let HomieClientEvent::HomieMessage {
+                message: Homie5Message::PropertyValue { property, value },
+                ..
+            } = event
+            {
+                values.push((property.prop_id().to_string(), value));
+            }
+        }
+        values
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_keeps_event_loop_running() {
+        let broker = TestBroker::start().await.unwrap();
+        let (handle, mut events) = slow_consumer(&broker, BackpressurePolicy::DropOldest).await;
+
+        let (_publisher_handle, publisher, _publisher_events) = broker.client("publisher").unwrap();
+        for i in 0..50 {
+            publisher
+                .homie_publish(publish("homie/5/dev-1/node/counter", &i.to_string(), false))
+                .await
+                .unwrap();
+        }
+        wait_for_messages_in(&handle, 50).await;
+
+        let values = drain_values(&mut events).await;
+        let stats = handle.stats();
+        assert!(stats.dropped_events > 0);
+        assert_eq!(values.len() as u64 + stats.dropped_events, 50);
+        // The newest values survive.
+        assert_eq!(values.last().unwrap().1, "49");
+        handle.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_retained_keeps_latest_value() {
+        let broker = TestBroker::start().await.unwrap();
+        let (_publisher_handle, publisher, _publisher_events) = broker.client("publisher").unwrap();
+        for prop in ["a", "b", "c"] {
+            publisher
+                .homie_publish(publish(&format!("homie/5/dev-1/node/{prop}"), "0", true))
+                .await
+                .unwrap();
+        }
+        broker
+            .wait_for_retained("homie/5/dev-1/node/c", "0", WAIT)
+            .await
+            .unwrap();
+
+        let (handle, mut events) =
+            slow_consumer(&broker, BackpressurePolicy::CoalesceRetained).await;
+        wait_for_messages_in(&handle, 3).await;
+        for i in 1..=20 {
+            publisher
+                .homie_publish(publish("homie/5/dev-1/node/a", &i.to_string(), true))
+                .await
+                .unwrap();
+        }
+        wait_for_messages_in(&handle, 23).await;
+
+        let values = drain_values(&mut events).await;
+        let stats = handle.stats();
+        // Live updates only coalesce with a retained value still queued, the
+        // rest is dropped once the queue is full.
+        assert_eq!(
+            values.len() as u64 + stats.coalesced_events + stats.dropped_events,
+            23
+        );
+        for prop in ["b", "c"] {
+            assert!(values.contains(&(prop.to_string(), "0".to_string())));
+        }
+        let last_a = values.iter().rev().find(|(prop, _)| prop == "a").unwrap();
+        assert_eq!(last_a.1, "20");
+        handle.stop().await.unwrap();
+    }
+}
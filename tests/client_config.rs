@@ -2,7 +2,7 @@
 mod tests {
     use std::time::Duration;
 
-    use hc_homie5::client::MqttClientConfig;
+    use hc_homie5::client::{HomieClientError, MqttClientConfig, MqttTransportKind};
 
     #[test]
     fn test_max_disconnect_default_none() {
@@ -39,4 +39,54 @@ mod tests {
         // Check that max_disconnect was preserved
         assert_eq!(setup.max_disconnect, Some(Duration::from_secs(120)));
     }
+
+    #[test]
+    fn test_transport_kind_parsing_and_tls_upgrade() {
+        assert_eq!(
+            " WSS ".parse::<MqttTransportKind>().unwrap(),
+            MqttTransportKind::Wss
+        );
+        assert!(matches!(
+            "quic".parse::<MqttTransportKind>(),
+            Err(HomieClientError::TransportConfig(_))
+        ));
+
+        let config = MqttClientConfig::new("localhost").use_tls(true);
+        assert_eq!(config.effective_transport(), MqttTransportKind::Tls);
+        let config = config.transport(MqttTransportKind::Ws);
+        assert_eq!(config.effective_transport(), MqttTransportKind::Wss);
+    }
+
+    #[cfg(not(feature = "websocket"))]
+    #[test]
+    fn test_websocket_requires_feature() {
+        let config = MqttClientConfig::new("localhost").transport(MqttTransportKind::Ws);
+        assert!(matches!(
+            config.to_mqtt_options(),
+            Err(HomieClientError::TransportConfig(_))
+        ));
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn test_websocket_options() {
+        let config = MqttClientConfig::new("broker.local")
+            .port(8080)
+            .transport(MqttTransportKind::Ws)
+            .ws_path("mqtt-proxy/ws")
+            .ws_header("X-Api-Key", "secret");
+        let options = config.to_mqtt_options().unwrap();
+        assert_eq!(
+            options.broker_address(),
+            ("ws://broker.local:8080/mqtt-proxy/ws".to_string(), 8080)
+        );
+        assert!(matches!(options.transport(), rumqttc::Transport::Ws));
+        assert!(options.request_modifier().is_some());
+
+        let invalid = config.ws_header("bad header", "x");
+        assert!(matches!(
+            invalid.to_mqtt_options(),
+            Err(HomieClientError::TransportConfig(_))
+        ));
+    }
 }
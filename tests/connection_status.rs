@@ -0,0 +1,50 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::ConnectionStatus;
+    use hc_homie5::connection::ConnectionState;
+    use hc_homie5::test_support::TestBroker;
+    use tokio::sync::watch;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    async fn wait_for_state(
+        status: &mut watch::Receiver<ConnectionStatus>,
+        state: ConnectionState,
+    ) -> ConnectionStatus {
+        tokio::time::timeout(WAIT, status.wait_for(|s| s.state == state))
+            .await
+            .unwrap()
+            .unwrap()
+            .clone()
+    }
+
+    #[tokio::test]
+    async fn test_status_follows_connection() {
+        let broker = TestBroker::start().await.unwrap();
+        // The event receiver is never read: the status does not depend on it.
+        let (handle, _client, _events) = broker.client("status").unwrap();
+        let mut status = handle.connection_status();
+
+        let connected = wait_for_state(&mut status, ConnectionState::Connected).await;
+        assert!(connected.is_connected());
+        assert_eq!(connected.last_error, None);
+
+        broker.disconnect_all();
+        let disconnected = wait_for_state(&mut status, ConnectionState::Disconnected).await;
+        assert!(disconnected.last_error.is_some());
+        assert!(disconnected.since >= connected.since);
+
+        let reconnected = wait_for_state(&mut status, ConnectionState::Connected).await;
+        // The error of the connection loss is kept.
+        assert_eq!(reconnected.last_error, disconnected.last_error);
+
+        handle.stop().await.unwrap();
+        assert_eq!(
+            status.borrow_and_update().state,
+            ConnectionState::Disconnected
+        );
+        assert!(status.changed().await.is_err());
+    }
+}
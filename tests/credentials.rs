@@ -0,0 +1,97 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::BytesMut;
+    use hc_homie5::client::{
+        run_homie_client_with_client_options, CredentialError, Credentials, HomieClientEvent,
+        MqttClientConfig, ReconnectPolicy,
+    };
+    use rumqttc::{ConnAck, ConnectReturnCode, Packet};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// Accepts connections whose password is `accepted` and refuses all
+    /// others. Reports the password of every CONNECT.
+    async fn start_auth_broker(accepted: &'static str) -> (u16, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (passwords, passwords_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let passwords = passwords.clone();
+                tokio::spawn(async move {
+                    let mut buf = BytesMut::new();
+                    let connect = loop {
+                        match Packet::read(&mut buf, 1024 * 1024) {
+                            Ok(Packet::Connect(connect)) => break connect,
+                            Ok(other) => panic!("expected CONNECT, got {other:?}"),
+                            Err(_) => {
+                                if stream.read_buf(&mut buf).await.unwrap_or(0) == 0 {
+                                    return;
+                                }
+                            }
+                        }
+                    };
+                    let password = connect.login.map(|l| l.password).unwrap_or_default();
+                    let code = if password == accepted {
+                        ConnectReturnCode::Success
+                    } else {
+                        ConnectReturnCode::BadUserNamePassword
+                    };
+                    let _ = passwords.send(password);
+                    let mut out = BytesMut::new();
+                    ConnAck::new(code, false).write(&mut out).unwrap();
+                    stream.write_all(&out).await.unwrap();
+                    // Keep the connection open until the client drops it.
+                    while stream.read_buf(&mut buf).await.unwrap_or(0) > 0 {}
+                });
+            }
+        });
+        (port, passwords_rx)
+    }
+
+    #[tokio::test]
+    async fn test_credentials_are_refreshed_before_reconnect() {
+        let (port, mut passwords) = start_auth_broker("token-2").await;
+        let issued = Arc::new(AtomicUsize::new(0));
+        let provider_issued = Arc::clone(&issued);
+        let provider = move || {
+            let n = provider_issued.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Ok::<_, CredentialError>(Credentials::new("bridge", format!("token-{n}"))) }
+        };
+        let config = MqttClientConfig::new("127.0.0.1")
+            .port(port)
+            .client_id("jwt-client")
+            .username("static")
+            .password("static")
+            .reconnect_policy(ReconnectPolicy::fixed(Duration::from_millis(10)))
+            .credential_provider(provider);
+        let (handle, _client, mut events) = run_homie_client_with_client_options(
+            config.to_mqtt_options().unwrap(),
+            config.to_client_options(),
+        )
+        .unwrap();
+
+        let mut errors = 0;
+        loop {
+            match tokio::time::timeout(WAIT, events.recv()).await.unwrap() {
+                Some(HomieClientEvent::Connect { .. }) => break,
+                Some(HomieClientEvent::Error(_)) => errors += 1,
+                Some(_) => {}
+                None => panic!("client loop ended"),
+            }
+        }
+        assert_eq!(errors, 1);
+        assert_eq!(passwords.recv().await.unwrap(), "token-1");
+        assert_eq!(passwords.recv().await.unwrap(), "token-2");
+        assert_eq!(issued.load(Ordering::SeqCst), 2);
+
+        handle.stop().await.unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::HomieClientEvent;
+    use hc_homie5::controller::{DeviceManager, HomieDiscovery};
+    use hc_homie5::model::{DiscoveryAction, InvalidPropertyValueError};
+    use hc_homie5::store::DeviceStore;
+    use hc_homie5::test_support::{publish, TestBroker};
+    use homie5::client::{QoS, Subscription};
+    use homie5::device_description::{
+        DeviceDescriptionBuilder, NodeDescriptionBuilder, PropertyDescriptionBuilder,
+    };
+    use homie5::{
+        DeviceRef, Homie5Message, HomieDeviceStatus, HomieDomain, HomieID, HomieValue, PropertyRef,
+    };
+
+    fn device() -> DeviceRef {
+        DeviceRef::new(HomieDomain::Default, HomieID::new_const("lamp-1"))
+    }
+
+    fn property(prop_id: &'static str) -> PropertyRef {
+        PropertyRef::new(
+            HomieDomain::Default,
+            HomieID::new_const("lamp-1"),
+            HomieID::new_const("light"),
+            HomieID::new_const(prop_id),
+        )
+    }
+
+    fn value(prop_id: &'static str, raw: &str) -> Homie5Message {
+        Homie5Message::PropertyValue {
+            property: property(prop_id),
+            value: raw.to_string(),
+        }
+    }
+
+    fn description_message() -> Homie5Message {
+        let description = DeviceDescriptionBuilder::new()
+            .add_node(
+                HomieID::new_const("light"),
+                NodeDescriptionBuilder::new()
+                    .add_property(
+                        HomieID::new_const("brightness"),
+                        PropertyDescriptionBuilder::integer().settable(true).build(),
+                    )
+                    .add_property(
+                        HomieID::new_const("power"),
+                        PropertyDescriptionBuilder::boolean().build(),
+                    )
+                    .add_property(
+                        HomieID::new_const("alarm"),
+                        PropertyDescriptionBuilder::boolean()
+                            .retained(false)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        Homie5Message::DeviceDescription {
+            device: device(),
+            description,
+        }
+    }
+
+    async fn start_manager(broker: &TestBroker, limit: usize) -> DeviceManager {
+        let (manager, _handle, _events) =
+            DeviceManager::new(HomieDomain::Default, &broker.client_config("dashboard")).unwrap();
+        let manager = manager.early_payload_limit(limit);
+        manager
+            .discovery_handle_event(
+                Homie5Message::DeviceState {
+                    device: device(),
+                    state: HomieDeviceStatus::Ready,
+                },
+                true,
+            )
+            .await
+            .unwrap();
+        manager
+    }
+
+    #[tokio::test]
+    async fn test_payloads_before_description_are_applied() {
+        let broker = TestBroker::start().await.unwrap();
+        let manager = start_manager(&broker, 8).await;
+
+        for message in [
+            value("brightness", "10"),
+            value("brightness", "40"),
+            Homie5Message::PropertyTarget {
+                property: property("brightness"),
+                target: "80".to_string(),
+            },
+        ] {
+            assert!(manager
+                .discovery_handle_event(message, true)
+                .await
+                .unwrap()
+                .is_empty());
+        }
+
+        let actions = manager
+            .discovery_handle_event(description_message(), true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            &actions[..],
+            [
+                DiscoveryAction::DeviceDescriptionChanged(_),
+                DiscoveryAction::DevicePropertyValueChanged {
+                    to: HomieValue::Integer(40),
+                    ..
+                },
+                DiscoveryAction::DevicePropertyTargetChanged {
+                    to: HomieValue::Integer(80),
+                    ..
+                },
+            ]
+        ));
+        let devices = manager.read().await;
+        let entry = devices.get_value_entry(&property("brightness")).unwrap();
+        assert_eq!(entry.value, Some(HomieValue::Integer(40)));
+        assert_eq!(entry.target, Some(HomieValue::Integer(80)));
+        assert_eq!(devices.get_device(&device()).unwrap().rejected_payloads, 0);
+    }
+
+    #[tokio::test]
+    async fn test_full_buffer_rejects_payloads() {
+        let broker = TestBroker::start().await.unwrap();
+        let manager = start_manager(&broker, 1).await;
+
+        assert!(manager
+            .discovery_handle_event(value("brightness", "10"), true)
+            .await
+            .unwrap()
+            .is_empty());
+        let action = manager
+            .discovery_handle_event(value("power", "true"), true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            &action[..],
+            [DiscoveryAction::InvalidPropertyValue {
+                error: InvalidPropertyValueError::MissingDescription,
+                ..
+            }]
+        ));
+
+        let actions = manager
+            .discovery_handle_event(description_message(), true)
+            .await
+            .unwrap();
+        assert_eq!(actions.len(), 2);
+        let devices = manager.read().await;
+        assert_eq!(
+            devices.get_property_value(&property("brightness")),
+            Some(&HomieValue::Integer(10))
+        );
+        assert_eq!(devices.get_property_value(&property("power")), None);
+        assert_eq!(devices.get_device(&device()).unwrap().rejected_payloads, 1);
+    }
+
+    #[tokio::test]
+    async fn test_only_retained_payloads_are_buffered() {
+        let broker = TestBroker::start().await.unwrap();
+        let manager = start_manager(&broker, 8).await;
+
+        // A live payload is not buffered.
+        let actions = manager
+            .discovery_handle_event(value("power", "true"), false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            &actions[..],
+            [DiscoveryAction::InvalidPropertyValue {
+                error: InvalidPropertyValueError::MissingDescription,
+                ..
+            }]
+        ));
+        // A buffered payload of a non-retained property is dropped.
+        assert!(manager
+            .discovery_handle_event(value("alarm", "true"), true)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let actions = manager
+            .discovery_handle_event(description_message(), true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            &actions[..],
+            [DiscoveryAction::DeviceDescriptionChanged(_)]
+        ));
+        let devices = manager.read().await;
+        assert_eq!(devices.get_property_value(&property("power")), None);
+        assert_eq!(devices.get_device(&device()).unwrap().rejected_payloads, 1);
+    }
+
+    #[tokio::test]
+    async fn test_retained_values_before_description_through_wide_subscription() {
+        const WAIT: Duration = Duration::from_secs(5);
+        const DESCRIPTION: &str = r#"{"homie":"5.0","version":1,"nodes":{"light":{"properties":{"brightness":{"datatype":"integer","settable":true}}}}}"#;
+
+        let broker = TestBroker::start().await.unwrap();
+        let (device_handle, device_client, _device_events) = broker.client("lamp").unwrap();
+        for (topic, payload) in [
+            ("homie/5/lamp-1/$state", "ready"),
+            ("homie/5/lamp-1/$description", DESCRIPTION),
+            ("homie/5/lamp-1/light/brightness", "40"),
+        ] {
+            device_client
+                .homie_publish(publish(topic, payload, true))
+                .await
+                .unwrap();
+        }
+        device_handle.flush(WAIT).await.unwrap();
+
+        let (_handle, client, mut events) = broker.client("dashboard").unwrap();
+        // The wide subscription delivers the retained value together with
+        // $state, before discovery subscribes the description.
+        client
+            .homie_subscribe(std::iter::once(Subscription {
+                topic: "homie/5/#".to_string(),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+        let discovery = HomieDiscovery::new(client);
+        discovery.discover(&HomieDomain::Default).await.unwrap();
+
+        let mut devices = DeviceStore::new();
+        let actions = tokio::time::timeout(WAIT, async {
+            loop {
+                let Some(HomieClientEvent::HomieMessage { message, retain }) = events.recv().await
+                else {
+                    continue;
+                };
+                // The first $description is delivered before the device is
+                // known and fails.
+                let Ok(actions) = discovery.handle_event(message, retain, &mut devices).await
+                else {
+                    continue;
+                };
+                if matches!(
+                    actions.first(),
+                    Some(DiscoveryAction::DeviceDescriptionChanged(_))
+                ) {
+                    return actions;
+                }
+            }
+        })
+        .await
+        .expect("description was not applied");
+
+        assert!(matches!(
+            &actions[..],
+            [
+                DiscoveryAction::DeviceDescriptionChanged(_),
+                DiscoveryAction::DevicePropertyValueChanged {
+                    to: HomieValue::Integer(40),
+                    ..
+                },
+            ]
+        ));
+        assert_eq!(
+            devices.get_property_value(&property("brightness")),
+            Some(&HomieValue::Integer(40))
+        );
+    }
+}
@@ -0,0 +1,180 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{
+        run_homie_client_with_client_options, EventFilter, EventKind, HomieClientEvent, HubEvent,
+    };
+    use hc_homie5::test_support::{publish, TestBroker};
+    use homie5::client::{QoS, Subscription};
+    use homie5::{DeviceRef, Homie5Message, HomieDomain, HomieID, PropertyRef};
+    use tokio::sync::mpsc;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn id(id: &str) -> HomieID {
+        HomieID::try_from(id.to_string()).unwrap()
+    }
+
+    /// Topics of the Homie messages received until `Stop`, after which the
+    /// channel must end.
+    async fn collect_until_stop(events: &mut mpsc::Receiver<HubEvent>) -> Vec<String> {
+        let mut topics = Vec::new();
+        loop {
+            match tokio::time::timeout(WAIT, events.recv()).await.unwrap() {
+                Some(HomieClientEvent::HomieMessage {
+                    message: Homie5Message::DeviceState { device, .. },
+                    ..
+                }) => topics.push(format!("{}/$state", device.device_id())),
+                Some(HomieClientEvent::HomieMessage {
+                    message: Homie5Message::PropertyValue { property, .. },
+                    ..
+                }) => topics.push(format!(
+                    "{}/{}/{}",
+                    property.device_id(),
+                    property.node_id(),
+                    property.prop_id()
+                )),
+                Some(HomieClientEvent::HomieMessage { message: other, .. }) => {
+                    panic!("unexpected {other:?}")
+                }
+                Some(HomieClientEvent::Stop) => break,
+                Some(_) => {}
+                None => panic!("channel ended before Stop"),
+            }
+        }
+        assert!(events.recv().await.is_none());
+        topics
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_receive_matching_events() {
+        let broker = TestBroker::start().await.unwrap();
+        let (handle, client, mut events) = broker.client("hub").unwrap();
+
+        let mut device = handle.subscribe(
+            EventFilter::all().device(DeviceRef::new(HomieDomain::Default, id("dev-1"))),
+        );
+        let mut property = handle.subscribe(
+            EventFilter::all()
+                .property(PropertyRef::new(
+                    HomieDomain::Default,
+                    id("dev-2"),
+                    id("node"),
+                    id("temp"),
+                ))
+                .kind(EventKind::PropertyValue),
+        );
+        let mut states = handle
+            .event_hub()
+            .subscribe(EventFilter::all().kind(EventKind::DeviceState));
+        assert_eq!(handle.event_hub().subscriber_count(), 3);
+
+        client
+            .homie_subscribe(std::iter::once(Subscription {
+                topic: "homie/5/#".to_string(),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+        let (_publisher_handle, publisher, _publisher_events) = broker.client("publisher").unwrap();
+        let messages = [
+            ("homie/5/dev-1/$state", "ready"),
+            ("homie/5/dev-1/node/temp", "1"),
+            ("homie/5/dev-2/node/temp", "2"),
+            ("homie/5/dev-2/node/hum", "3"),
+            ("homie/5/dev-2/$state", "ready"),
+        ];
+        for (topic, payload) in messages {
+            publisher
+                .homie_publish(publish(topic, payload, false))
+                .await
+                .unwrap();
+        }
+
+        // The main receiver still gets everything.
+        let mut received = 0;
+        while received < messages.len() {
+            if let Some(HomieClientEvent::HomieMessage { .. }) =
+                tokio::time::timeout(WAIT, events.recv()).await.unwrap()
+            {
+                received += 1;
+            }
+        }
+        handle.stop().await.unwrap();
+
+        assert_eq!(
+            collect_until_stop(&mut device).await,
+            vec!["dev-1/$state", "dev-1/node/temp"]
+        );
+        assert_eq!(
+            collect_until_stop(&mut property).await,
+            vec!["dev-2/node/temp"]
+        );
+        assert_eq!(
+            collect_until_stop(&mut states).await,
+            vec!["dev-1/$state", "dev-2/$state"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_full_subscriber_does_not_stall_delivery() {
+        let broker = TestBroker::start().await.unwrap();
+        let config = broker.client_config("hub").mqtt_channel_size(4);
+        let (handle, client, mut events) = run_homie_client_with_client_options(
+            config.to_mqtt_options().unwrap(),
+            config.to_client_options(),
+        )
+        .unwrap();
+        // Never read.
+        let _stalled = handle.subscribe(EventFilter::all());
+
+        client
+            .homie_subscribe(std::iter::once(Subscription {
+                topic: "homie/5/#".to_string(),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+        let (_publisher_handle, publisher, _publisher_events) = broker.client("publisher").unwrap();
+        for i in 0..20 {
+            publisher
+                .homie_publish(publish("homie/5/dev-1/node/temp", &i.to_string(), false))
+                .await
+                .unwrap();
+        }
+
+        let mut received = 0;
+        while received < 20 {
+            if let Some(HomieClientEvent::HomieMessage { .. }) =
+                tokio::time::timeout(WAIT, events.recv()).await.unwrap()
+            {
+                received += 1;
+            }
+        }
+        assert!(handle.stats().hub_dropped_events >= 16);
+        handle.stop().await.unwrap();
+    }
+
+    #[test]
+    fn test_filter_constraints() {
+        let raw: HubEvent = HomieClientEvent::Raw {
+            topic: "zigbee/lamp".to_string(),
+            payload: Default::default(),
+            retain: false,
+            qos: QoS::AtMostOnce,
+        };
+        let disconnect: HubEvent = HomieClientEvent::Disconnect;
+        let device = EventFilter::all().device(DeviceRef::new(HomieDomain::Default, id("dev-1")));
+
+        assert!(EventFilter::all().matches(&raw));
+        assert!(!device.matches(&raw));
+        assert!(!EventFilter::all()
+            .kind(EventKind::DeviceState)
+            .matches(&raw));
+        assert!(device.matches(&disconnect));
+        assert!(EventFilter::all()
+            .kind(EventKind::DeviceState)
+            .matches(&disconnect));
+    }
+}
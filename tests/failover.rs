@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{
+        run_homie_client_with_failover, BrokerEndpoint, HomieClientEvent, MqttClientConfig,
+    };
+    use hc_homie5::test_support::TestBroker;
+    use tokio::sync::mpsc;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// A loopback port nothing listens on.
+    async fn dead_endpoint() -> BrokerEndpoint {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        BrokerEndpoint::new("127.0.0.1", listener.local_addr().unwrap().port())
+    }
+
+    fn live_endpoint(broker: &TestBroker) -> BrokerEndpoint {
+        BrokerEndpoint::new("127.0.0.1", broker.addr().port())
+    }
+
+    /// Returns the endpoint of the next `Connect` and the number of
+    /// connection errors reported before it.
+    async fn next_connect(
+        events: &mut mpsc::Receiver<HomieClientEvent>,
+    ) -> (Option<BrokerEndpoint>, usize) {
+        let mut errors = 0;
+        loop {
+            match tokio::time::timeout(WAIT, events.recv()).await.unwrap() {
+                Some(HomieClientEvent::Connect { endpoint }) => return (endpoint, errors),
+                Some(HomieClientEvent::Error(_)) => errors += 1,
+                Some(_) => {}
+                None => panic!("client loop ended"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_to_next_broker() {
+        let broker = TestBroker::start().await.unwrap();
+        let config: MqttClientConfig = broker
+            .client_config("failover")
+            .endpoints(vec![dead_endpoint().await, live_endpoint(&broker)]);
+        let (handle, _client, mut events) = run_homie_client_with_failover(
+            config.to_failover_mqtt_options().unwrap(),
+            config.to_client_options(),
+        )
+        .unwrap();
+
+        let (endpoint, errors) = next_connect(&mut events).await;
+        assert_eq!(errors, 1);
+        assert_eq!(endpoint, Some(live_endpoint(&broker)));
+
+        // A dropped connection is retried on the same broker first: the
+        // only error is the connection loss itself.
+        broker.disconnect_all();
+        let (endpoint, errors) = next_connect(&mut events).await;
+        assert_eq!(errors, 1);
+        assert_eq!(endpoint, Some(live_endpoint(&broker)));
+
+        handle.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failover_options_share_client_id() {
+        let config = MqttClientConfig::new("unused")
+            .endpoints(BrokerEndpoint::parse_list("mqtt-a,mqtt-b:8883", 1884).unwrap());
+        let options = config.to_failover_mqtt_options().unwrap();
+        assert_eq!(options.len(), 2);
+        assert_eq!(options[0].client_id(), options[1].client_id());
+        assert_eq!(options[0].broker_address(), ("mqtt-a".to_string(), 1884));
+        assert_eq!(options[1].broker_address(), ("mqtt-b".to_string(), 8883));
+        assert_eq!(
+            config.to_mqtt_options().unwrap().broker_address(),
+            ("mqtt-a".to_string(), 1884)
+        );
+    }
+}
@@ -0,0 +1,102 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::BytesMut;
+    use hc_homie5::client::{
+        run_homie_client_with_client_options, ConnectionFailureKind, ErrorClass, HomieClientError,
+        HomieClientEvent, MqttClientConfig, ReasonCode, ReconnectPolicy,
+    };
+    use rumqttc::{ConnAck, ConnectReturnCode, Packet};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// Answers every CONNECT with `code`. Returns the port and the number
+    /// of connection attempts.
+    async fn start_refusing_broker(code: ConnectReturnCode) -> (u16, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&attempts);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = BytesMut::new();
+                while !matches!(Packet::read(&mut buf, 1024 * 1024), Ok(Packet::Connect(_))) {
+                    if stream.read_buf(&mut buf).await.unwrap_or(0) == 0 {
+                        break;
+                    }
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut out = BytesMut::new();
+                ConnAck::new(code, false).write(&mut out).unwrap();
+                let _ = stream.write_all(&out).await;
+            }
+        });
+        (port, attempts)
+    }
+
+    fn config(port: u16) -> MqttClientConfig {
+        MqttClientConfig::new("127.0.0.1")
+            .port(port)
+            .client_id("fatal-test")
+            .reconnect_policy(ReconnectPolicy::fixed(Duration::from_millis(10)))
+    }
+
+    #[tokio::test]
+    async fn test_bad_credentials_stop_the_client() {
+        let (port, attempts) = start_refusing_broker(ConnectReturnCode::BadUserNamePassword).await;
+        let config = config(port);
+        let (handle, _client, mut events) = run_homie_client_with_client_options(
+            config.to_mqtt_options().unwrap(),
+            config.to_client_options(),
+        )
+        .unwrap();
+
+        let failure = loop {
+            match tokio::time::timeout(WAIT, events.recv()).await.unwrap() {
+                Some(HomieClientEvent::Fatal(failure)) => break failure,
+                Some(HomieClientEvent::Error(err)) => panic!("retried after {err:?}"),
+                Some(_) => {}
+                None => panic!("client loop ended without a fatal event"),
+            }
+        };
+        assert_eq!(
+            failure.kind,
+            ConnectionFailureKind::Refused(ReasonCode(0x86))
+        );
+        assert!(matches!(events.recv().await, Some(HomieClientEvent::Stop)));
+        assert!(events.recv().await.is_none());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        let err = handle.stop().await.unwrap_err();
+        assert!(err.is_fatal());
+        assert!(matches!(err, HomieClientError::FatalConnection(f) if f == failure));
+    }
+
+    #[tokio::test]
+    async fn test_classifier_overrides_default() {
+        let (port, attempts) = start_refusing_broker(ConnectReturnCode::NotAuthorized).await;
+        let config = config(port).error_classifier(|_| ErrorClass::Recoverable);
+        let (handle, _client, mut events) = run_homie_client_with_client_options(
+            config.to_mqtt_options().unwrap(),
+            config.to_client_options(),
+        )
+        .unwrap();
+
+        let mut scheduled = 0;
+        while scheduled < 2 {
+            match tokio::time::timeout(WAIT, events.recv()).await.unwrap() {
+                Some(HomieClientEvent::ReconnectScheduled { .. }) => scheduled += 1,
+                Some(HomieClientEvent::Fatal(failure)) => panic!("stopped after {failure}"),
+                Some(_) => {}
+                None => panic!("client loop ended"),
+            }
+        }
+        assert!(attempts.load(Ordering::SeqCst) >= 2);
+        handle.stop().await.unwrap();
+    }
+}
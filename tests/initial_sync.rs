@@ -0,0 +1,154 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{HomieClientEvent, HomieClientHandle};
+    use hc_homie5::controller::{DeviceManager, SyncPhase};
+    use hc_homie5::test_support::{publish, TestBroker};
+    use homie5::{HomieDomain, HomieValue, PropertyRef};
+
+    const WAIT: Duration = Duration::from_secs(5);
+    const SETTLE: Duration = Duration::from_millis(100);
+
+    const DESCRIPTION: &str = r#"{"homie":"5.0","version":1,"nodes":{"sensor":{"properties":{"temp":{"datatype":"integer"}}}}}"#;
+
+    async fn publish_retained(broker: &TestBroker, topics: &[(&str, &str)]) {
+        let (handle, client, _events) = broker.client("device").unwrap();
+        for (topic, payload) in topics {
+            client
+                .homie_publish(publish(topic, payload, true))
+                .await
+                .unwrap();
+        }
+        handle.flush(WAIT).await.unwrap();
+        handle.stop().await.unwrap();
+    }
+
+    fn start_manager(broker: &TestBroker) -> (DeviceManager, HomieClientHandle) {
+        let (manager, handle, mut events) =
+            DeviceManager::new(HomieDomain::Default, &broker.client_config("dashboard")).unwrap();
+        let manager = manager.sync_settle_time(SETTLE);
+        let feeder = manager.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                if let HomieClientEvent::HomieMessage { message, retain } = event {
+                    let _ = feeder.discovery_handle_event(message, retain).await;
+                }
+            }
+        });
+        (manager, handle)
+    }
+
+    fn temp() -> PropertyRef {
+        PropertyRef::new(
+            HomieDomain::Default,
+            "sensor-1".try_into().unwrap(),
+            "sensor".try_into().unwrap(),
+            "temp".try_into().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_sync_completes_and_rearms_after_reconnect() {
+        let broker = TestBroker::start().await.unwrap();
+        publish_retained(
+            &broker,
+            &[
+                ("homie/5/sensor-1/$description", DESCRIPTION),
+                ("homie/5/sensor-1/$state", "ready"),
+                ("homie/5/sensor-1/sensor/temp", "21"),
+            ],
+        )
+        .await;
+
+        let (manager, _handle) = start_manager(&broker);
+        assert_eq!(*manager.sync_phase().borrow(), SyncPhase::Idle);
+        manager.discover().await.unwrap();
+        tokio::time::timeout(WAIT, manager.initial_sync_complete())
+            .await
+            .expect("initial sync did not complete");
+        assert_eq!(
+            manager.read().await.get_property_value(&temp()),
+            Some(&HomieValue::Integer(21))
+        );
+
+        let mut phase = manager.sync_phase();
+        broker.disconnect_all();
+        tokio::time::timeout(WAIT, phase.wait_for(|p| *p == SyncPhase::Syncing))
+            .await
+            .expect("sync was not re-armed")
+            .unwrap();
+        tokio::time::timeout(WAIT, manager.initial_sync_complete())
+            .await
+            .expect("sync did not complete after reconnect");
+    }
+
+    #[tokio::test]
+    async fn test_missing_retained_value_holds_sync_back() {
+        let broker = TestBroker::start().await.unwrap();
+        publish_retained(
+            &broker,
+            &[
+                ("homie/5/sensor-1/$description", DESCRIPTION),
+                ("homie/5/sensor-1/$state", "ready"),
+            ],
+        )
+        .await;
+
+        let (manager, _handle) = start_manager(&broker);
+        manager.discover().await.unwrap();
+        assert!(
+            tokio::time::timeout(SETTLE * 5, manager.initial_sync_complete())
+                .await
+                .is_err()
+        );
+        assert_eq!(*manager.sync_phase().borrow(), SyncPhase::Syncing);
+
+        publish_retained(&broker, &[("homie/5/sensor-1/sensor/temp", "21")]).await;
+        tokio::time::timeout(WAIT, manager.initial_sync_complete())
+            .await
+            .expect("sync did not complete once the value arrived");
+    }
+
+    #[tokio::test]
+    async fn test_devices_that_are_not_ready_do_not_hold_sync_back() {
+        let broker = TestBroker::start().await.unwrap();
+        publish_retained(
+            &broker,
+            &[
+                ("homie/5/sensor-1/$description", DESCRIPTION),
+                ("homie/5/sensor-1/$state", "init"),
+            ],
+        )
+        .await;
+
+        let (manager, _handle) = start_manager(&broker);
+        manager.discover().await.unwrap();
+        let incomplete = tokio::time::timeout(WAIT, manager.initial_sync_complete())
+            .await
+            .expect("sync waited for a device that is not ready");
+        assert!(incomplete.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_max_wait_completes_sync_and_reports_incomplete_devices() {
+        let broker = TestBroker::start().await.unwrap();
+        publish_retained(
+            &broker,
+            &[
+                ("homie/5/sensor-1/$description", DESCRIPTION),
+                ("homie/5/sensor-1/$state", "ready"),
+            ],
+        )
+        .await;
+
+        let (manager, _handle) = start_manager(&broker);
+        let manager = manager.sync_max_wait(SETTLE * 5);
+        manager.discover().await.unwrap();
+        let incomplete = tokio::time::timeout(WAIT, manager.initial_sync_complete())
+            .await
+            .expect("sync did not give up after the max wait");
+        assert_eq!(incomplete, vec![temp().device_ref().clone()]);
+        assert_eq!(*manager.sync_phase().borrow(), SyncPhase::Complete);
+    }
+}
@@ -0,0 +1,116 @@
+#[cfg(test)]
+mod tests {
+    use hc_homie5::controller::DeviceManager;
+    use hc_homie5::model::{DiscoveryAction, InvalidPropertyValueError};
+    use hc_homie5::test_support::TestBroker;
+    use homie5::device_description::{
+        DeviceDescriptionBuilder, NodeDescriptionBuilder, PropertyDescriptionBuilder,
+    };
+    use homie5::{DeviceRef, Homie5Message, HomieDeviceStatus, HomieDomain, HomieID, PropertyRef};
+
+    fn property(device: &DeviceRef, prop_id: &'static str) -> PropertyRef {
+        PropertyRef::new(
+            device.homie_domain().clone(),
+            device.device_id().clone(),
+            HomieID::new_const("sensor"),
+            HomieID::new_const(prop_id),
+        )
+    }
+
+    fn value(property: &PropertyRef, raw: &str) -> Homie5Message {
+        Homie5Message::PropertyValue {
+            property: property.clone(),
+            value: raw.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_payloads_are_reported_and_counted() {
+        let broker = TestBroker::start().await.unwrap();
+        let (manager, _handle, _events) =
+            DeviceManager::new(HomieDomain::Default, &broker.client_config("dashboard")).unwrap();
+        let device = DeviceRef::new(HomieDomain::Default, HomieID::new_const("sensor-1"));
+        let temp = property(&device, "temp");
+
+        manager
+            .discovery_handle_event(
+                Homie5Message::DeviceState {
+                    device: device.clone(),
+                    state: HomieDeviceStatus::Ready,
+                },
+                true,
+            )
+            .await
+            .unwrap();
+
+        let description = DeviceDescriptionBuilder::new()
+            .add_node(
+                HomieID::new_const("sensor"),
+                NodeDescriptionBuilder::new()
+                    .add_property(
+                        HomieID::new_const("temp"),
+                        PropertyDescriptionBuilder::integer().build(),
+                    )
+                    .build(),
+            )
+            .build();
+        manager
+            .discovery_handle_event(
+                Homie5Message::DeviceDescription {
+                    device: device.clone(),
+                    description,
+                },
+                true,
+            )
+            .await
+            .unwrap();
+
+        let action = manager
+            .discovery_handle_event(value(&temp, "warm"), true)
+            .await
+            .unwrap();
+        let [DiscoveryAction::InvalidPropertyValue { prop, raw, error }] = &action[..] else {
+            panic!("expected an invalid value action, got {action:?}");
+        };
+        assert_eq!(prop, &temp);
+        assert_eq!(raw, "warm");
+        assert!(matches!(error, InvalidPropertyValueError::Parse(_)));
+
+        let action = manager
+            .discovery_handle_event(
+                Homie5Message::PropertyTarget {
+                    property: property(&device, "humidity"),
+                    target: "40".to_string(),
+                },
+                true,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            &action[..],
+            [DiscoveryAction::InvalidPropertyValue {
+                error: InvalidPropertyValueError::UnknownProperty,
+                ..
+            }]
+        ));
+
+        // Valid payloads are stored and not counted
+        let action = manager
+            .discovery_handle_event(value(&temp, "21"), true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            &action[..],
+            [DiscoveryAction::DevicePropertyValueChanged { .. }]
+        ));
+        assert_eq!(
+            manager
+                .read()
+                .await
+                .get_device(&device)
+                .unwrap()
+                .rejected_payloads,
+            2
+        );
+    }
+}
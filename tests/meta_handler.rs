@@ -263,4 +263,40 @@ mod tests {
             .meta_overlays
             .contains_key(&provider_id("provider-2")));
     }
+
+    #[test]
+    fn test_multiple_domains() {
+        let staging: HomieDomain = "staging".try_into().unwrap();
+        let mut handler = MetaOverlayHandler::new(test_domain());
+        let mut store = DeviceStore::new();
+
+        let overlay_msg = |homie_domain: &HomieDomain| MetaMessage::DeviceOverlay {
+            homie_domain: homie_domain.clone(),
+            provider_id: provider_id("provider-1"),
+            device_id: device_id("dev-1"),
+            overlay: test_overlay(),
+        };
+
+        // Domains outside the set are ignored until added
+        assert!(!handler.handle_meta_message(overlay_msg(&staging), &mut store));
+        handler.add_domain(staging.clone());
+        assert!(handler.handle_meta_message(overlay_msg(&staging), &mut store));
+        assert!(handler.handle_meta_message(overlay_msg(&test_domain()), &mut store));
+
+        // Removing a domain drops its pending overlays only
+        handler.remove_domain(&staging);
+        let staging_ref = DeviceRef::new(staging.clone(), device_id("dev-1"));
+        store.add(&staging_ref, HomieDeviceStatus::Ready);
+        handler.apply_pending_for_device(&staging_ref, &mut store);
+        assert!(store
+            .get_device(&staging_ref)
+            .unwrap()
+            .meta_overlays
+            .is_empty());
+
+        let dref = device_ref("dev-1");
+        store.add(&dref, HomieDeviceStatus::Ready);
+        handler.apply_pending_for_device(&dref, &mut store);
+        assert_eq!(store.get_device(&dref).unwrap().meta_overlays.len(), 1);
+    }
 }
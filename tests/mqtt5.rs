@@ -0,0 +1,265 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::BytesMut;
+    use hc_homie5::client::{
+        run_homie_client_v5, BrokerReason, HomieClientError, HomieClientEvent, MqttClientConfig,
+        MqttProtocolVersion, ReasonCode, ReconnectPolicy,
+    };
+    use hc_homie5::controller::DeviceManager;
+    use homie5::client::{Publish, QoS, Subscription};
+    use homie5::HomieDomain;
+    use rumqttc::v5::mqttbytes::{
+        v5::{ConnAck, ConnectReturnCode, Packet, SubAck, SubscribeReasonCode},
+        Error as PacketError,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+
+    async fn read_packet(stream: &mut TcpStream, buf: &mut BytesMut) -> Option<Packet> {
+        loop {
+            match Packet::read(buf, None) {
+                Ok(packet) => return Some(packet),
+                Err(PacketError::InsufficientBytes(_)) => {}
+                Err(err) => panic!("invalid packet: {err:?}"),
+            }
+            if stream.read_buf(buf).await.ok()? == 0 {
+                return None;
+            }
+        }
+    }
+
+    async fn write_packet(stream: &mut TcpStream, packet: Packet) {
+        let mut out = BytesMut::new();
+        packet.write(&mut out, None).unwrap();
+        stream.write_all(&out).await.unwrap();
+    }
+
+    fn connack(code: ConnectReturnCode) -> Packet {
+        Packet::ConnAck(ConnAck {
+            session_present: false,
+            code,
+            properties: None,
+        })
+    }
+
+    /// Accepts a single MQTT 5 connection, answers CONNECT with `code`,
+    /// denies every subscription and forwards all other packets.
+    async fn start_v5_broker(code: ConnectReturnCode) -> (u16, mpsc::UnboundedReceiver<Packet>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (packets, packets_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = BytesMut::new();
+            while let Some(packet) = read_packet(&mut stream, &mut buf).await {
+                match &packet {
+                    Packet::Connect(..) => write_packet(&mut stream, connack(code)).await,
+                    Packet::Subscribe(sub) => {
+                        let suback = SubAck {
+                            pkid: sub.pkid,
+                            return_codes: vec![SubscribeReasonCode::NotAuthorized],
+                            properties: None,
+                        };
+                        write_packet(&mut stream, Packet::SubAck(suback)).await;
+                    }
+                    _ => {}
+                }
+                let _ = packets.send(packet);
+            }
+        });
+        (port, packets_rx)
+    }
+
+    fn v5_config(port: u16) -> MqttClientConfig {
+        MqttClientConfig::new("127.0.0.1")
+            .port(port)
+            .client_id("v5-test")
+            .protocol_version(MqttProtocolVersion::V5)
+            .session_expiry_interval(Some(300))
+            .message_expiry_interval(Some(30))
+            .reconnect_policy(
+                ReconnectPolicy::fixed(Duration::from_millis(10)).max_attempts(Some(1)),
+            )
+    }
+
+    async fn next_reason(
+        events: &mut mpsc::Receiver<HomieClientEvent<rumqttc::v5::ConnectionError>>,
+    ) -> BrokerReason {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), events.recv()).await {
+                Ok(Some(HomieClientEvent::Reason(reason))) => return reason,
+                Ok(Some(_)) => {}
+                other => panic!("expected a reason event, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_protocol_version_selects_options() {
+        let config = v5_config(1883);
+        assert!(matches!(
+            config.to_mqtt_options(),
+            Err(HomieClientError::ProtocolVersion(_))
+        ));
+        let options = config.to_mqtt_v5_options().unwrap();
+        assert_eq!(options.session_expiry_interval(), Some(300));
+        assert_eq!(
+            MqttClientConfig::new("localhost").protocol_version,
+            MqttProtocolVersion::V311
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connack_reason_code_is_reported() {
+        let (port, _packets) = start_v5_broker(ConnectReturnCode::NotAuthorized).await;
+        let config = v5_config(port);
+        let (handle, _client, mut events) = run_homie_client_v5(
+            config.to_mqtt_v5_options().unwrap(),
+            config.message_expiry_interval,
+            config.to_client_options(),
+        )
+        .unwrap();
+
+        match next_reason(&mut events).await {
+            BrokerReason::ConnAck { code, .. } => {
+                assert_eq!(code, ReasonCode(0x87));
+                assert!(!code.is_success());
+            }
+            other => panic!("unexpected reason {other:?}"),
+        }
+        // Not authorized is fatal: the client stops instead of retrying.
+        loop {
+            match events.recv().await {
+                Some(HomieClientEvent::Fatal(_)) => break,
+                Some(HomieClientEvent::Error(err)) => panic!("retried after {err:?}"),
+                Some(_) => {}
+                None => panic!("client loop ended without a fatal event"),
+            }
+        }
+        assert!(handle.stop().await.unwrap_err().is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_publish_properties_and_suback_reason() {
+        let (port, mut packets) = start_v5_broker(ConnectReturnCode::Success).await;
+        let config = v5_config(port);
+        let (handle, client, mut events) = run_homie_client_v5(
+            config.to_mqtt_v5_options().unwrap(),
+            config.message_expiry_interval,
+            config.to_client_options(),
+        )
+        .unwrap();
+
+        match packets.recv().await {
+            Some(Packet::Connect(connect, ..)) => assert_eq!(
+                connect.properties.unwrap().session_expiry_interval,
+                Some(300)
+            ),
+            other => panic!("expected CONNECT, got {other:?}"),
+        }
+        assert_eq!(
+            next_reason(&mut events).await,
+            BrokerReason::ConnAck {
+                code: ReasonCode::SUCCESS,
+                session_present: false,
+                reason_string: None,
+            }
+        );
+        assert!(matches!(
+            events.recv().await,
+            Some(HomieClientEvent::Connect { .. })
+        ));
+
+        client
+            .homie_subscribe(std::iter::once(Subscription {
+                topic: "homie/5/+/$state".to_string(),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+        match next_reason(&mut events).await {
+            BrokerReason::SubAck { codes, .. } => assert_eq!(codes, vec![ReasonCode(0x87)]),
+            other => panic!("unexpected reason {other:?}"),
+        }
+
+        client
+            .homie_publish_with_user_properties(
+                Publish {
+                    topic: "homie/5/dev/node/prop/$target".to_string(),
+                    payload: b"on".to_vec(),
+                    qos: QoS::AtMostOnce,
+                    retain: false,
+                },
+                vec![("origin".to_string(), "test".to_string())],
+            )
+            .await
+            .unwrap();
+        client
+            .homie_publish(Publish {
+                topic: "homie/5/dev/$state".to_string(),
+                payload: b"ready".to_vec(),
+                qos: QoS::AtMostOnce,
+                retain: true,
+            })
+            .await
+            .unwrap();
+
+        let mut publishes = Vec::new();
+        while publishes.len() < 2 {
+            if let Some(Packet::Publish(p)) = packets.recv().await {
+                publishes.push(p);
+            }
+        }
+        let target = publishes[0].properties.clone().unwrap();
+        assert_eq!(target.message_expiry_interval, Some(30));
+        assert_eq!(
+            target.user_properties,
+            vec![("origin".to_string(), "test".to_string())]
+        );
+        assert!(publishes[1].retain);
+        assert!(publishes[1]
+            .properties
+            .as_ref()
+            .is_none_or(|p| p.message_expiry_interval.is_none()));
+
+        handle.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_device_manager_connects_with_v5_config() {
+        let (port, mut packets) = start_v5_broker(ConnectReturnCode::Success).await;
+        let (manager, handle, mut events) =
+            DeviceManager::new(HomieDomain::Default, &v5_config(port)).unwrap();
+
+        match packets.recv().await {
+            Some(Packet::Connect(connect, ..)) => assert_eq!(
+                connect.properties.unwrap().session_expiry_interval,
+                Some(300)
+            ),
+            other => panic!("expected an MQTT 5 CONNECT, got {other:?}"),
+        }
+        loop {
+            match events.recv().await {
+                Some(HomieClientEvent::Connect { .. }) => break,
+                Some(_) => {}
+                None => panic!("client loop ended before connecting"),
+            }
+        }
+
+        manager.discover().await.unwrap();
+        loop {
+            match packets.recv().await {
+                Some(Packet::Subscribe(sub)) => {
+                    assert!(sub.filters.iter().any(|f| f.path == "homie/5/+/$state"));
+                    break;
+                }
+                Some(_) => {}
+                None => panic!("no SUBSCRIBE received"),
+            }
+        }
+        handle.stop().await.unwrap();
+    }
+}
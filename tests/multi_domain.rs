@@ -0,0 +1,126 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{HomieClientEvent, MqttConnectionError};
+    use hc_homie5::controller::{DeviceManager, DomainSet};
+    use hc_homie5::model::DiscoveryAction;
+    use hc_homie5::store::DeviceStore;
+    use hc_homie5::test_support::TestBroker;
+    use homie5::client::{Publish, QoS};
+    use homie5::{DeviceRef, HomieDomain};
+    use tokio::sync::mpsc;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn domain(name: &str) -> HomieDomain {
+        name.to_string().try_into().unwrap()
+    }
+
+    fn device(domain_name: &str, id: &str) -> DeviceRef {
+        DeviceRef::new(domain(domain_name), id.to_string().try_into().unwrap())
+    }
+
+    async fn publish_ready(broker: &TestBroker, devices: &[DeviceRef]) {
+        let (handle, client, _events) = broker.client("devices").unwrap();
+        for device in devices {
+            client
+                .homie_publish(Publish {
+                    topic: format!("{}/5/{}/$state", device.homie_domain(), device.device_id()),
+                    payload: b"ready".to_vec(),
+                    qos: QoS::AtLeastOnce,
+                    retain: true,
+                })
+                .await
+                .unwrap();
+        }
+        handle.flush(WAIT).await.unwrap();
+        handle.stop().await.unwrap();
+    }
+
+    /// Feeds events to discovery until `done` holds for the store.
+    async fn discover_until(
+        manager: &DeviceManager,
+        events: &mut mpsc::Receiver<HomieClientEvent<MqttConnectionError>>,
+        done: impl Fn(&DeviceStore) -> bool,
+    ) {
+        tokio::time::timeout(WAIT, async {
+            while !done(&*manager.read().await) {
+                if let Some(HomieClientEvent::HomieMessage { message, retain }) =
+                    events.recv().await
+                {
+                    manager
+                        .discovery_handle_event(message, retain)
+                        .await
+                        .unwrap();
+                }
+            }
+        })
+        .await
+        .expect("devices were not discovered");
+    }
+
+    #[tokio::test]
+    async fn test_domains_are_discovered_added_and_removed() {
+        let broker = TestBroker::start().await.unwrap();
+        let prod = device("homie", "lamp");
+        let staging = device("staging", "lamp");
+        let lab = device("lab", "sensor");
+        publish_ready(&broker, &[prod.clone(), staging.clone(), lab.clone()]).await;
+
+        let domains: DomainSet = [domain("homie"), domain("staging")].into_iter().collect();
+        let (manager, handle, mut events) =
+            DeviceManager::new(domains, &broker.client_config("dashboard")).unwrap();
+        manager.discover().await.unwrap();
+        discover_until(&manager, &mut events, |store| {
+            store.get_device(&prod).is_some() && store.get_device(&staging).is_some()
+        })
+        .await;
+        assert!(manager.read().await.get_device(&lab).is_none());
+
+        manager.add_domain(domain("lab")).await.unwrap();
+        discover_until(&manager, &mut events, |store| {
+            store.get_device(&lab).is_some()
+        })
+        .await;
+
+        let removed = manager.remove_domain(&domain("staging")).await.unwrap();
+        assert!(matches!(
+            &removed[..],
+            [DiscoveryAction::DeviceRemoved(dev)] if dev.ident == staging
+        ));
+        let store = manager.read().await;
+        assert!(store.get_device(&staging).is_none());
+        assert!(store.get_device(&prod).is_some());
+        assert!(!manager.domains().contains(&domain("staging")));
+        drop(store);
+
+        handle.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_all_domains() {
+        let broker = TestBroker::start().await.unwrap();
+        let prod = device("homie", "lamp");
+        let lab = device("lab", "sensor");
+        publish_ready(&broker, &[prod.clone(), lab.clone()]).await;
+
+        let (manager, handle, mut events) =
+            DeviceManager::new(DomainSet::all(), &broker.client_config("dashboard")).unwrap();
+        manager.discover().await.unwrap();
+        discover_until(&manager, &mut events, |store| {
+            store.get_device(&prod).is_some() && store.get_device(&lab).is_some()
+        })
+        .await;
+
+        // Removing a domain also covered by `All` keeps its devices.
+        assert!(manager
+            .remove_domain(&domain("lab"))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(manager.read().await.get_device(&lab).is_some());
+
+        handle.stop().await.unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{
+        run_homie_client_with_transport, HomieClientEvent, HomieClientOptions, OfflineBufferPolicy,
+        ReconnectPolicy, TransportEvent,
+    };
+    use hc_homie5::test_support::{publish, RecordingTransport, ScriptedEventLoop};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn test_offline_publishes_are_coalesced_and_flushed_in_order() {
+        let transport = RecordingTransport::default();
+        let (script, eventloop) = ScriptedEventLoop::new();
+        let options = HomieClientOptions::new(16)
+            .reconnect_policy(ReconnectPolicy::fixed(Duration::ZERO))
+            .offline_buffer(Some(OfflineBufferPolicy::new(10)));
+        let (handle, client, mut events) =
+            run_homie_client_with_transport(transport.clone(), eventloop, options).unwrap();
+        let pending = handle.pending_publishes();
+
+        // Not connected yet: everything is buffered.
+        client
+            .homie_publish(publish("dev/$state", "init", true))
+            .await
+            .unwrap();
+        client
+            .homie_publish(publish("dev/cmd", "ping", false))
+            .await
+            .unwrap();
+        client
+            .homie_publish(publish("dev/$state", "ready", true))
+            .await
+            .unwrap();
+        assert!(transport.publishes().is_empty());
+        assert_eq!(client.offline_buffer().unwrap().len(), 2);
+        assert_eq!(pending.pending_count(), 2);
+
+        script.send(Ok(TransportEvent::Connected)).unwrap();
+        assert_eq!(
+            transport.wait_for_publishes(2, WAIT).await,
+            vec!["dev/cmd=ping", "dev/$state=ready"]
+        );
+        // Still queued until the event loop reports them as sent.
+        assert_eq!(pending.pending_count(), 2);
+        script.send(Ok(TransportEvent::PublishSent(0))).unwrap();
+        script.send(Ok(TransportEvent::PublishSent(0))).unwrap();
+        handle.flush(WAIT).await.unwrap();
+
+        // Connection lost: buffering resumes until the next connect.
+        script
+            .send(Err(std::io::ErrorKind::ConnectionReset.into()))
+            .unwrap();
+        loop {
+            match tokio::time::timeout(WAIT, events.recv()).await.unwrap() {
+                Some(HomieClientEvent::Error(_)) => break,
+                Some(_) => {}
+                None => panic!("client loop ended"),
+            }
+        }
+        client
+            .homie_publish(publish("dev/$state", "lost", true))
+            .await
+            .unwrap();
+        assert_eq!(transport.publishes().len(), 2);
+        assert_eq!(pending.pending_count(), 1);
+
+        script.send(Ok(TransportEvent::Connected)).unwrap();
+        assert_eq!(
+            transport.wait_for_publishes(3, WAIT).await[2],
+            "dev/$state=lost".to_string()
+        );
+        assert!(client.offline_buffer().unwrap().is_empty());
+        handle.stop().await.unwrap();
+    }
+}
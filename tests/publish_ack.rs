@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{run_homie_client_with_client_options, MqttClientConfig};
+    use hc_homie5::test_support::TestBroker;
+    use homie5::client::{Publish, QoS};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn state_publish(qos: QoS) -> Publish {
+        Publish {
+            topic: "homie/5/dev-1/$state".to_string(),
+            payload: b"ready".to_vec(),
+            qos,
+            retain: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tracked_publish_resolves_after_broker_ack() {
+        let broker = TestBroker::start().await.unwrap();
+        let (handle, client, _events) = broker.client("tracked").unwrap();
+
+        for qos in [QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce] {
+            let ack = client
+                .homie_publish_tracked(state_publish(qos))
+                .await
+                .unwrap();
+            // Untracked traffic in between must not confuse the pairing.
+            client
+                .homie_publish(Publish {
+                    topic: "test/other".to_string(),
+                    payload: b"1".to_vec(),
+                    qos: QoS::AtLeastOnce,
+                    retain: false,
+                })
+                .await
+                .unwrap();
+            tokio::time::timeout(WAIT, ack).await.unwrap().unwrap();
+        }
+        assert_eq!(
+            broker.retained("homie/5/dev-1/$state").as_deref(),
+            Some(&b"ready"[..])
+        );
+        handle.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tracked_publish_fails_when_client_stops() {
+        // Nothing listens on the port: the publish stays queued.
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let config = MqttClientConfig::new("127.0.0.1")
+            .port(port)
+            .client_id("tracked-stop");
+        let (handle, client, _events) = run_homie_client_with_client_options(
+            config.to_mqtt_options().unwrap(),
+            config.to_client_options(),
+        )
+        .unwrap();
+        let ack = client
+            .homie_publish_tracked(state_publish(QoS::AtLeastOnce))
+            .await
+            .unwrap();
+        handle.stop().await.unwrap();
+        assert!(tokio::time::timeout(WAIT, ack).await.unwrap().is_err());
+    }
+}
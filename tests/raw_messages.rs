@@ -0,0 +1,75 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{run_homie_client_with_client_options, HomieClientEvent};
+    use hc_homie5::test_support::{publish, TestBroker};
+    use homie5::client::{QoS, Subscription};
+    use homie5::Homie5Message;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn test_only_opted_in_topics_are_passed_through() {
+        let broker = TestBroker::start().await.unwrap();
+        let config = broker
+            .client_config("bridge")
+            .raw_topic_filter("vendor/+/status");
+        let (_handle, client, mut events) = run_homie_client_with_client_options(
+            config.to_mqtt_options().unwrap(),
+            config.to_client_options(),
+        )
+        .unwrap();
+        client
+            .homie_subscribe(["vendor/#", "homie/5/+/$state"].into_iter().map(|topic| {
+                Subscription {
+                    topic: topic.to_string(),
+                    qos: QoS::AtLeastOnce,
+                }
+            }))
+            .await
+            .unwrap();
+
+        let (_publisher_handle, publisher, _publisher_events) = broker.client("publisher").unwrap();
+        // Not opted in: logged and dropped.
+        publisher
+            .homie_publish(publish("vendor/lamp/garbage", "x", false))
+            .await
+            .unwrap();
+        publisher
+            .homie_publish(publish("vendor/lamp/status", "on", false))
+            .await
+            .unwrap();
+        // Homie messages are still parsed as usual.
+        publisher
+            .homie_publish(publish("homie/5/dev-1/$state", "ready", false))
+            .await
+            .unwrap();
+
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            match tokio::time::timeout(WAIT, events.recv()).await.unwrap() {
+                Some(HomieClientEvent::Raw {
+                    topic,
+                    payload,
+                    retain,
+                    qos,
+                }) => {
+                    assert_eq!(payload.as_ref(), b"on");
+                    assert!(!retain);
+                    assert_eq!(qos, QoS::AtLeastOnce);
+                    received.push(topic);
+                }
+                Some(HomieClientEvent::HomieMessage {
+                    message: Homie5Message::DeviceState { .. },
+                    ..
+                }) => {
+                    received.push("$state".to_string());
+                }
+                Some(_) => {}
+                None => panic!("client loop ended"),
+            }
+        }
+        assert_eq!(received, vec!["vendor/lamp/status", "$state"]);
+    }
+}
@@ -0,0 +1,174 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{
+        run_homie_client_with_transport, HomieClientError, HomieClientEvent, HomieClientOptions,
+        MqttClientConfig, MqttEventLoop, ReconnectPolicy, TransportEvent,
+    };
+    use hc_homie5::test_support::RecordingTransport;
+
+    /// Every connection attempt is refused.
+    struct RefusingEventLoop;
+
+    impl MqttEventLoop for RefusingEventLoop {
+        type Error = std::io::Error;
+
+        async fn poll(&mut self) -> Result<TransportEvent, Self::Error> {
+            Err(std::io::ErrorKind::ConnectionRefused.into())
+        }
+    }
+
+    #[test]
+    fn test_default_policy_is_fixed_five_seconds() {
+        let policy = MqttClientConfig::new("localhost").reconnect_policy;
+        assert_eq!(policy, ReconnectPolicy::fixed(Duration::from_secs(5)));
+        for attempt in 1..10 {
+            assert_eq!(policy.next_delay(attempt), Duration::from_secs(5));
+        }
+        assert!(!policy.attempts_exhausted(u32::MAX));
+    }
+
+    #[test]
+    fn test_exponential_backoff_is_capped() {
+        let policy =
+            ReconnectPolicy::exponential(Duration::from_millis(100), Duration::from_secs(1));
+        let delays: Vec<_> = (1..=6).map(|a| policy.base_delay(a)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(400),
+                Duration::from_millis(800),
+                Duration::from_secs(1),
+                Duration::from_secs(1),
+            ]
+        );
+        assert_eq!(policy.base_delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let policy = ReconnectPolicy::fixed(Duration::from_secs(10)).jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.next_delay(1);
+            assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(10));
+        }
+        assert_eq!(ReconnectPolicy::default().jitter(7.0).jitter, 1.0);
+        assert_eq!(ReconnectPolicy::default().jitter(-1.0).jitter, 0.0);
+    }
+
+    #[test]
+    fn test_reconnect_policy_builder() {
+        let config = MqttClientConfig::new("localhost").reconnect_policy(
+            ReconnectPolicy::exponential(Duration::from_secs(1), Duration::from_secs(60))
+                .max_attempts(Some(3)),
+        );
+        let options = config.to_client_options();
+        assert_eq!(options.reconnect_policy.max_attempts, Some(3));
+        assert!(!options.reconnect_policy.attempts_exhausted(2));
+        assert!(options.reconnect_policy.attempts_exhausted(3));
+
+        let setup = config
+            .into_bridge_setup(
+                "test-bridge".try_into().unwrap(),
+                "homie".try_into().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(setup.reconnect_policy.max_attempts, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_retry_delays_are_reported_until_attempts_exhausted() {
+        let options = HomieClientOptions::new(16).reconnect_policy(
+            ReconnectPolicy::exponential(Duration::from_millis(5), Duration::from_millis(10))
+                .max_attempts(Some(4)),
+        );
+        let (handle, _client, mut events) =
+            run_homie_client_with_transport(RecordingTransport::new(), RefusingEventLoop, options)
+                .unwrap();
+
+        let mut scheduled = Vec::new();
+        while let Some(event) = events.recv().await {
+            match event {
+                HomieClientEvent::ReconnectScheduled { attempt, delay } => {
+                    scheduled.push((attempt, delay))
+                }
+                HomieClientEvent::Stop => break,
+                _ => {}
+            }
+        }
+        assert_eq!(
+            scheduled,
+            vec![
+                (1, Duration::from_millis(5)),
+                (2, Duration::from_millis(10)),
+                (3, Duration::from_millis(10)),
+            ]
+        );
+        let err = handle.stop().await.unwrap_err();
+        assert!(matches!(err, HomieClientError::RecoverableConnection(_)));
+        assert!(!err.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_max_disconnect_stops_without_error() {
+        let options = HomieClientOptions::new(16)
+            .max_disconnect(Some(Duration::from_millis(20)))
+            .reconnect_policy(ReconnectPolicy::fixed(Duration::from_millis(5)));
+        let (handle, _client, mut events) =
+            run_homie_client_with_transport(RecordingTransport::new(), RefusingEventLoop, options)
+                .unwrap();
+
+        while let Some(event) = events.recv().await {
+            if matches!(event, HomieClientEvent::Stop) {
+                break;
+            }
+        }
+        handle.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_final_error_is_reported_before_stop() {
+        let options = HomieClientOptions::new(16).reconnect_policy(
+            ReconnectPolicy::fixed(Duration::from_millis(5)).max_attempts(Some(3)),
+        );
+        let (_handle, _client, mut events) =
+            run_homie_client_with_transport(RecordingTransport::new(), RefusingEventLoop, options)
+                .unwrap();
+
+        let mut received = Vec::new();
+        while let Some(event) = events.recv().await {
+            match event {
+                HomieClientEvent::Error(_) => received.push("error"),
+                HomieClientEvent::ReconnectScheduled { .. } => received.push("scheduled"),
+                HomieClientEvent::Stop => break,
+                _ => {}
+            }
+        }
+        assert_eq!(
+            received,
+            vec!["error", "scheduled", "error", "scheduled", "error"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stop_interrupts_backoff_delay() {
+        let options = HomieClientOptions::new(16)
+            .reconnect_policy(ReconnectPolicy::fixed(Duration::from_secs(3600)));
+        let (handle, _client, mut events) =
+            run_homie_client_with_transport(RecordingTransport::new(), RefusingEventLoop, options)
+                .unwrap();
+
+        loop {
+            if let Some(HomieClientEvent::ReconnectScheduled { .. }) = events.recv().await {
+                break;
+            }
+        }
+        tokio::time::timeout(Duration::from_secs(1), handle.stop())
+            .await
+            .expect("stop should not wait for the backoff delay")
+            .unwrap();
+    }
+}
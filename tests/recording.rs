@@ -0,0 +1,170 @@
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use hc_homie5::client::{
+        run_homie_client_with_client_options, HomieClientEvent, HomieClientOptions, MqttMessage,
+        Recording, RecordingError, ReplayPace, TrafficRecorder,
+    };
+    use hc_homie5::test_support::TestBroker;
+    use homie5::client::{Publish, QoS, Subscription};
+    use homie5::{Homie5Message, HomieDeviceStatus};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn test_recorded_traffic_replays_as_events() {
+        let path =
+            std::env::temp_dir().join(format!("hc-homie5-recording-{}.jsonl", std::process::id()));
+        let broker = TestBroker::start().await.unwrap();
+        let (publisher_handle, publisher, _publisher_events) = broker.client("publisher").unwrap();
+        publisher
+            .homie_publish(Publish {
+                topic: "homie/5/dev-1/$state".to_string(),
+                payload: b"ready".to_vec(),
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            })
+            .await
+            .unwrap();
+        publisher_handle.flush(WAIT).await.unwrap();
+
+        let config = broker
+            .client_config("recorder")
+            .recorder(TrafficRecorder::create(&path).unwrap());
+        let (handle, client, mut events) = run_homie_client_with_client_options(
+            config.to_mqtt_options().unwrap(),
+            config.to_client_options(),
+        )
+        .unwrap();
+        client
+            .homie_subscribe(std::iter::once(Subscription {
+                topic: "homie/5/#".to_string(),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+        loop {
+            match tokio::time::timeout(WAIT, events.recv()).await.unwrap() {
+                Some(HomieClientEvent::HomieMessage { .. }) => break,
+                Some(_) => {}
+                None => panic!("client loop ended"),
+            }
+        }
+        handle.stop().await.unwrap();
+
+        let recording = Recording::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recording.messages.len(), 1);
+        let recorded = &recording.messages[0].message;
+        assert_eq!(recorded.topic, "homie/5/dev-1/$state");
+        assert_eq!(&recorded.payload[..], b"ready");
+        assert!(recorded.retain);
+
+        let (replay, mut events) =
+            recording.replay(ReplayPace::AsFastAsPossible, HomieClientOptions::new(16));
+        assert!(matches!(
+            events.recv().await,
+            Some(HomieClientEvent::HomieMessage {
+                message: Homie5Message::DeviceState {
+                    state: HomieDeviceStatus::Ready,
+                    ..
+                },
+                ..
+            })
+        ));
+        assert!(matches!(events.recv().await, Some(HomieClientEvent::Stop)));
+        assert!(events.recv().await.is_none());
+        replay.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_keeps_original_pace() {
+        let lines = concat!(
+            r#"{"timestamp":"2026-01-05T10:00:00Z","topic":"homie/5/dev-1/$state","payload":"init","qos":"AtLeastOnce","retain":true}"#,
+            "\n\n",
+            r#"{"timestamp":"2026-01-05T10:00:00.300Z","topic":"vendor/raw","payload_bytes":[0,159,146,150],"qos":"AtMostOnce","retain":false}"#,
+            "\n",
+        );
+        let recording = Recording::from_reader(lines.as_bytes()).unwrap();
+        assert_eq!(recording.messages.len(), 2);
+
+        let start = tokio::time::Instant::now();
+        let (replay, mut events) = recording.replay(
+            ReplayPace::Original,
+            HomieClientOptions::new(16).raw_topic_filters(vec!["vendor/#".to_string()]),
+        );
+        assert!(matches!(
+            events.recv().await,
+            Some(HomieClientEvent::HomieMessage {
+                message: Homie5Message::DeviceState {
+                    state: HomieDeviceStatus::Init,
+                    ..
+                },
+                ..
+            })
+        ));
+        match events.recv().await {
+            Some(HomieClientEvent::Raw { topic, payload, .. }) => {
+                assert_eq!(topic, "vendor/raw");
+                assert_eq!(&payload[..], &[0, 159, 146, 150]);
+            }
+            other => panic!("expected a raw message, got {other:?}"),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(matches!(events.recv().await, Some(HomieClientEvent::Stop)));
+        replay.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_line_is_reported() {
+        let lines = concat!(
+            r#"{"timestamp":"2026-01-05T10:00:00Z","topic":"a","payload":"1","qos":"AtMostOnce","retain":false}"#,
+            "\nnot json\n",
+        );
+        let err = Recording::from_reader(lines.as_bytes()).unwrap_err();
+        assert!(matches!(err, RecordingError::Parse { line: 2, .. }));
+    }
+
+    /// Takes 100ms per write.
+    #[derive(Clone, Default)]
+    struct SlowWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(100));
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recording_does_not_wait_for_the_writer() {
+        let written = SlowWriter::default();
+        let recorder = TrafficRecorder::from_writer(written.clone());
+        let message = MqttMessage {
+            topic: "homie/5/dev-1/$state".to_string(),
+            payload: "ready".into(),
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        };
+
+        let start = std::time::Instant::now();
+        for _ in 0..5 {
+            recorder.record(&message).unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        recorder.flush().await.unwrap();
+        let data = written.0.lock().unwrap().clone();
+        let recording = Recording::from_reader(&data[..]).unwrap();
+        assert_eq!(recording.messages.len(), 5);
+        assert_eq!(recording.messages[0].message, message);
+    }
+}
@@ -0,0 +1,113 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{HomieClientEvent, RequestError, ResponseMatcher};
+    use hc_homie5::test_support::{publish, TestBroker};
+    use homie5::client::{Publish, QoS, Subscription};
+    use homie5::{Homie5Message, HomieDomain, HomieID, PropertyRef};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn light() -> PropertyRef {
+        PropertyRef::new(
+            HomieDomain::Default,
+            HomieID::new_const("lamp"),
+            HomieID::new_const("light"),
+            HomieID::new_const("on"),
+        )
+    }
+
+    fn subscription(topic: &str) -> Subscription {
+        Subscription {
+            topic: topic.to_string(),
+            qos: QoS::AtLeastOnce,
+        }
+    }
+
+    /// Runs a device that confirms every `/set` of the light with a value
+    /// publish.
+    async fn start_device(broker: &TestBroker) {
+        let (handle, device, mut events) = broker.client("lamp").unwrap();
+        device
+            .homie_subscribe(std::iter::once(subscription("homie/5/lamp/light/on/set")))
+            .await
+            .unwrap();
+        // The broker handles packets of a connection in order: once this
+        // marker is retained, the subscription is active.
+        device
+            .homie_publish(Publish {
+                retain: true,
+                ..publish("test/lamp-ready", "1", false)
+            })
+            .await
+            .unwrap();
+        broker
+            .wait_for_retained("test/lamp-ready", "1", WAIT)
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let _handle = handle;
+            while let Some(event) = events.recv().await {
+                if let HomieClientEvent::HomieMessage {
+                    message: Homie5Message::PropertySet { set_value, .. },
+                    ..
+                } = event
+                {
+                    device
+                        .homie_publish(publish("homie/5/lamp/light/on", &set_value, false))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_request_resolves_with_confirmation() {
+        let broker = TestBroker::start().await.unwrap();
+        start_device(&broker).await;
+        let (handle, controller, _events) = broker.client("controller").unwrap();
+        controller
+            .homie_subscribe(std::iter::once(subscription("homie/5/lamp/light/on")))
+            .await
+            .unwrap();
+
+        let response = controller
+            .homie_request(
+                publish("homie/5/lamp/light/on/set", "true", false),
+                ResponseMatcher::property_value(&light()).payload("true"),
+                WAIT,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.topic, "homie/5/lamp/light/on");
+        assert_eq!(response.payload, "true");
+        assert!(controller.responses().is_empty());
+
+        let err = controller
+            .homie_request(
+                publish("homie/5/lamp/light/on/set", "false", false),
+                ResponseMatcher::property_target(&light()),
+                Duration::from_millis(100),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RequestError::Timeout(_)));
+        assert!(controller.responses().is_empty());
+
+        handle.stop().await.unwrap();
+        let err = controller
+            .homie_request(
+                publish("homie/5/lamp/light/on/set", "true", false),
+                ResponseMatcher::property_value(&light()),
+                WAIT,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RequestError::Closed | RequestError::Publish(_)
+        ));
+    }
+}
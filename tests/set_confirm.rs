@@ -0,0 +1,134 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{HomieClientEvent, HomieClientHandle};
+    use hc_homie5::controller::{DeviceManager, SetCommandOutcome};
+    use hc_homie5::test_support::{publish, TestBroker};
+    use homie5::client::{QoS, Subscription};
+    use homie5::{Homie5Message, HomieDomain, HomieID, HomieValue, PropertyRef, ToTopic};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    const DESCRIPTION: &str = r#"{"homie":"5.0","version":1,"nodes":{"light":{"properties":{"brightness":{"datatype":"integer","settable":true}}}}}"#;
+
+    fn brightness(device_id: &'static str) -> PropertyRef {
+        PropertyRef::new(
+            HomieDomain::Default,
+            HomieID::new_const(device_id),
+            HomieID::new_const("light"),
+            HomieID::new_const("brightness"),
+        )
+    }
+
+    /// Publishes a dimmer that answers `/set` with the `$target` returned by
+    /// `answer`, or not at all.
+    async fn start_dimmer(
+        broker: &TestBroker,
+        answer: fn(&str) -> Option<&'static str>,
+    ) -> HomieClientHandle {
+        let (handle, client, mut events) = broker.client("dimmer").unwrap();
+        let prop = brightness("dimmer");
+        for (topic, payload) in [
+            ("homie/5/dimmer/$description", DESCRIPTION),
+            ("homie/5/dimmer/$state", "ready"),
+            ("homie/5/dimmer/light/brightness", "0"),
+        ] {
+            client
+                .homie_publish(publish(topic, payload, true))
+                .await
+                .unwrap();
+        }
+        client
+            .homie_subscribe(std::iter::once(Subscription {
+                topic: format!("{}/set", prop.to_topic()),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+        handle.flush(WAIT).await.unwrap();
+
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let HomieClientEvent::HomieMessage {
+                    message:
+                        Homie5Message::PropertySet {
+                            property,
+                            set_value,
+                        },
+                    ..
+                } = event
+                else {
+                    continue;
+                };
+                if let Some(target) = answer(&set_value) {
+                    let topic = format!("{}/$target", property.to_topic());
+                    client
+                        .homie_publish(publish(&topic, target, true))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+        handle
+    }
+
+    #[tokio::test]
+    async fn test_set_command_outcomes() {
+        let broker = TestBroker::start().await.unwrap();
+        // Accepts 80, clamps 99 to 50 and ignores everything else
+        let _dimmer = start_dimmer(&broker, |set_value| match set_value {
+            "80" => Some("80"),
+            "99" => Some("50"),
+            _ => None,
+        })
+        .await;
+
+        let (manager, _handle, mut events) =
+            DeviceManager::new(HomieDomain::Default, &broker.client_config("dashboard")).unwrap();
+        let feeder = manager.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                if let HomieClientEvent::HomieMessage { message, retain } = event {
+                    let _ = feeder.discovery_handle_event(message, retain).await;
+                }
+            }
+        });
+        manager.discover().await.unwrap();
+        tokio::time::timeout(WAIT, manager.initial_sync_complete())
+            .await
+            .expect("dimmer was not discovered");
+
+        let prop = brightness("dimmer");
+        let set = |value: i64, timeout: Duration| {
+            let manager = manager.clone();
+            let prop = prop.clone();
+            async move {
+                manager
+                    .set_command_confirmed(&prop, &HomieValue::Integer(value), timeout)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert_eq!(set(80, WAIT).await, SetCommandOutcome::Confirmed);
+        assert_eq!(
+            set(99, WAIT).await,
+            SetCommandOutcome::Rejected {
+                value: HomieValue::Integer(50)
+            }
+        );
+        // The stored value 0 predates the command and does not confirm it
+        assert_eq!(
+            set(0, Duration::from_millis(200)).await,
+            SetCommandOutcome::TimedOut
+        );
+        assert_eq!(
+            manager
+                .set_command_confirmed(&brightness("unknown"), &HomieValue::Integer(1), WAIT)
+                .await
+                .unwrap(),
+            SetCommandOutcome::DeviceNotReady
+        );
+    }
+}
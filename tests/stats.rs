@@ -0,0 +1,78 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{HomieClientEvent, HomieClientHandle};
+    use hc_homie5::test_support::{publish, TestBroker};
+    use homie5::client::{QoS, Subscription};
+    use tokio::sync::mpsc;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    async fn wait_for_connect(events: &mut mpsc::Receiver<HomieClientEvent>) {
+        loop {
+            match tokio::time::timeout(WAIT, events.recv()).await.unwrap() {
+                Some(HomieClientEvent::Connect { .. }) => return,
+                Some(_) => {}
+                None => panic!("client loop ended"),
+            }
+        }
+    }
+
+    async fn wait_for_stats(
+        handle: &HomieClientHandle,
+        done: impl Fn(&hc_homie5::client::ClientStatsSnapshot) -> bool,
+    ) -> hc_homie5::client::ClientStatsSnapshot {
+        tokio::time::timeout(WAIT, async {
+            loop {
+                let stats = handle.stats();
+                if done(&stats) {
+                    return stats;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("stats did not reach the expected state")
+    }
+
+    #[tokio::test]
+    async fn test_traffic_and_reconnects_are_counted() {
+        let broker = TestBroker::start().await.unwrap();
+        let (handle, client, mut events) = broker.client("subscriber").unwrap();
+        wait_for_connect(&mut events).await;
+        client
+            .homie_subscribe(std::iter::once(Subscription {
+                topic: "homie/5/#".to_string(),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+
+        let (publisher_handle, publisher, _publisher_events) = broker.client("publisher").unwrap();
+        publisher
+            .homie_publish(publish("homie/5/dev-1/$state", "ready", false))
+            .await
+            .unwrap();
+        publisher
+            .homie_publish(publish("homie/5/dev-1/$nonsense/x", "garbage", false))
+            .await
+            .unwrap();
+        publisher_handle.flush(WAIT).await.unwrap();
+        assert_eq!(publisher_handle.stats().messages_out, 2);
+        assert_eq!(publisher_handle.stats().bytes_out, 12);
+
+        let stats = wait_for_stats(&handle, |s| s.messages_in == 2).await;
+        assert_eq!(stats.bytes_in, 12);
+        assert_eq!(stats.homie_parse_failures, 1);
+        assert_eq!(stats.reconnects, 0);
+        assert!(stats.connected_for.is_some());
+
+        broker.disconnect_all();
+        wait_for_connect(&mut events).await;
+        let stats = wait_for_stats(&handle, |s| s.reconnects == 1).await;
+        assert!(stats.connected_total >= stats.connected_for.unwrap());
+
+        handle.stop().await.unwrap();
+    }
+}
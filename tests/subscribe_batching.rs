@@ -0,0 +1,104 @@
+#[cfg(test)]
+mod tests {
+    use hc_homie5::client::{run_homie_client_with_transport, HomieClientOptions};
+    use hc_homie5::test_support::{RecordingTransport, ScriptedEventLoop};
+    use homie5::client::{QoS, Subscription, Unsubscribe};
+
+    /// `homie/5/dev-NNN/$state`: 22 bytes, 25 bytes encoded in a SUBSCRIBE.
+    fn state_topic(i: usize) -> String {
+        format!("homie/5/dev-{i:03}/$state")
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_are_batched_by_packet_size() {
+        let transport = RecordingTransport::new();
+        // 8 header bytes + 4 filters of 25 bytes.
+        let options = HomieClientOptions::new(16).max_packet_size_outgoing(108);
+        let (handle, client, _events) =
+            run_homie_client_with_transport(transport.clone(), ScriptedEventLoop::idle(), options)
+                .unwrap();
+
+        client
+            .homie_subscribe((0..10).map(|i| Subscription {
+                topic: state_topic(i),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+        let subscribes = transport.subscribe_packets();
+        assert_eq!(
+            subscribes.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![4, 4, 2]
+        );
+        assert_eq!(
+            subscribes.concat(),
+            (0..10).map(state_topic).collect::<Vec<_>>()
+        );
+        assert_eq!(client.subscriptions().len(), 10);
+
+        // A second reference keeps dev-000 subscribed.
+        client
+            .homie_subscribe(std::iter::once(Subscription {
+                topic: state_topic(0),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+        client
+            .homie_unsubscribe((0..6).map(|i| Unsubscribe {
+                topic: state_topic(i),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(
+            transport.unsubscribe_packets(),
+            vec![
+                (1..5).map(state_topic).collect::<Vec<_>>(),
+                vec![state_topic(5)]
+            ]
+        );
+        assert_eq!(client.subscriptions().len(), 5);
+
+        handle.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_unsubscribe_keeps_the_filter_registered() {
+        let transport = RecordingTransport::new();
+        let (handle, client, _events) = run_homie_client_with_transport(
+            transport.clone(),
+            ScriptedEventLoop::idle(),
+            HomieClientOptions::new(16),
+        )
+        .unwrap();
+
+        client
+            .homie_subscribe((0..2).map(|i| Subscription {
+                topic: state_topic(i),
+                qos: QoS::AtLeastOnce,
+            }))
+            .await
+            .unwrap();
+
+        transport.fail_requests(true);
+        let unsubscribe = || {
+            (0..2).map(|i| Unsubscribe {
+                topic: state_topic(i),
+            })
+        };
+        assert!(client.homie_unsubscribe(unsubscribe()).await.is_err());
+        // Still registered, so a reconnect replays it and a retry sends it.
+        assert_eq!(client.subscriptions().ref_count(&state_topic(0)), 1);
+        assert_eq!(client.subscriptions().ref_count(&state_topic(1)), 1);
+
+        transport.fail_requests(false);
+        client.homie_unsubscribe(unsubscribe()).await.unwrap();
+        assert_eq!(
+            transport.unsubscribe_packets(),
+            vec![vec![state_topic(0), state_topic(1)]]
+        );
+        assert!(client.subscriptions().is_empty());
+
+        handle.stop().await.unwrap();
+    }
+}
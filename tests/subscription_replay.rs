@@ -0,0 +1,99 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::HomieClientEvent;
+    use hc_homie5::test_support::{publish, TestBroker};
+    use homie5::client::{QoS, Subscription, Unsubscribe};
+    use homie5::{Homie5Message, HomieDeviceStatus};
+    use tokio::sync::mpsc;
+
+    const WAIT: Duration = Duration::from_secs(5);
+    const STATE_TOPIC: &str = "homie/5/dev-1/$state";
+
+    async fn next_state(events: &mut mpsc::Receiver<HomieClientEvent>) -> HomieDeviceStatus {
+        loop {
+            match tokio::time::timeout(WAIT, events.recv()).await {
+                Ok(Some(HomieClientEvent::HomieMessage {
+                    message: Homie5Message::DeviceState { state, .. },
+                    ..
+                })) => return state,
+                Ok(Some(_)) => {}
+                other => panic!("expected a $state message, got {other:?}"),
+            }
+        }
+    }
+
+    fn state_subscription() -> Subscription {
+        Subscription {
+            topic: STATE_TOPIC.to_string(),
+            qos: QoS::AtLeastOnce,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_are_replayed_after_reconnect() {
+        let broker = TestBroker::start().await.unwrap();
+        let (publisher_handle, publisher, _publisher_events) = broker.client("publisher").unwrap();
+        publisher
+            .homie_publish(publish(STATE_TOPIC, "ready", true))
+            .await
+            .unwrap();
+        publisher_handle.flush(WAIT).await.unwrap();
+
+        let (_handle, client, mut events) = broker.client("subscriber").unwrap();
+        client
+            .homie_subscribe(std::iter::once(state_subscription()))
+            .await
+            .unwrap();
+        assert_eq!(next_state(&mut events).await, HomieDeviceStatus::Ready);
+
+        broker.disconnect_all();
+
+        // The retained $state is only delivered again if the subscription
+        // was re-issued on the new session.
+        assert_eq!(next_state(&mut events).await, HomieDeviceStatus::Ready);
+        assert_eq!(client.subscriptions().ref_count(STATE_TOPIC), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_keeps_filter_while_referenced() {
+        let broker = TestBroker::start().await.unwrap();
+        let (publisher_handle, publisher, _publisher_events) = broker.client("publisher").unwrap();
+        publisher
+            .homie_publish(publish(STATE_TOPIC, "ready", true))
+            .await
+            .unwrap();
+        publisher_handle.flush(WAIT).await.unwrap();
+
+        let (_handle, client, mut events) = broker.client("subscriber").unwrap();
+        for _ in 0..2 {
+            client
+                .homie_subscribe(std::iter::once(state_subscription()))
+                .await
+                .unwrap();
+            assert_eq!(next_state(&mut events).await, HomieDeviceStatus::Ready);
+        }
+        client
+            .homie_unsubscribe(std::iter::once(Unsubscribe {
+                topic: STATE_TOPIC.to_string(),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(client.subscriptions().ref_count(STATE_TOPIC), 1);
+
+        publisher
+            .homie_publish(publish(STATE_TOPIC, "sleeping", true))
+            .await
+            .unwrap();
+        assert_eq!(next_state(&mut events).await, HomieDeviceStatus::Sleeping);
+
+        client
+            .homie_unsubscribe(std::iter::once(Unsubscribe {
+                topic: STATE_TOPIC.to_string(),
+            }))
+            .await
+            .unwrap();
+        assert!(client.subscriptions().is_empty());
+    }
+}
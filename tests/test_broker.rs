@@ -0,0 +1,102 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::HomieClientEvent;
+    use hc_homie5::controller::DeviceManager;
+    use hc_homie5::device::BridgeController;
+    use hc_homie5::test_support::TestBroker;
+    use homie5::client::{Publish, QoS};
+    use homie5::{DeviceRef, HomieDeviceStatus, HomieDomain, HomieID};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn test_retained_publish_and_clear() {
+        let broker = TestBroker::start().await.unwrap();
+        let (handle, client, _events) = broker.client("retain-client").unwrap();
+
+        client
+            .homie_publish(Publish {
+                topic: "homie/5/dev-1/$state".to_string(),
+                retain: true,
+                payload: b"ready".to_vec(),
+                qos: QoS::AtLeastOnce,
+            })
+            .await
+            .unwrap();
+        broker
+            .wait_for_retained("homie/5/dev-1/$state", "ready", WAIT)
+            .await
+            .unwrap();
+        assert_eq!(broker.retained_in_domain(&HomieDomain::Default).len(), 1);
+
+        // An empty retained payload clears the topic.
+        client
+            .homie_publish(Publish {
+                topic: "homie/5/dev-1/$state".to_string(),
+                retain: true,
+                payload: Vec::new(),
+                qos: QoS::AtLeastOnce,
+            })
+            .await
+            .unwrap();
+        handle.flush(WAIT).await.unwrap();
+        broker.assert_not_retained("homie/5/dev-1/$state");
+    }
+
+    #[tokio::test]
+    async fn test_device_publish_is_discovered_by_controller() {
+        let broker = TestBroker::start().await.unwrap();
+        let domain = HomieDomain::Default;
+        let bridge_id: HomieID = "test-bridge".try_into().unwrap();
+
+        let (_dev_handle, dev_client, _dev_events) = broker.client("bridge").unwrap();
+        let mut bridge = BridgeController::new(
+            bridge_id.clone(),
+            "Test Bridge",
+            domain.clone(),
+            dev_client,
+            &["refresh"],
+        );
+        bridge.publish().await.unwrap();
+        broker
+            .wait_for_retained("homie/5/test-bridge/$state", "ready", WAIT)
+            .await
+            .unwrap();
+        assert!(broker
+            .retained_in_domain(&domain)
+            .contains_key("homie/5/test-bridge/$description"));
+
+        let (manager, _ctrl_handle, mut events) =
+            DeviceManager::new(domain.clone(), &broker.client_config("controller")).unwrap();
+        manager.discover().await.unwrap();
+
+        let device_ref = DeviceRef::new(domain, bridge_id);
+        tokio::time::timeout(WAIT, async {
+            while let Some(event) = events.recv().await {
+                if let HomieClientEvent::HomieMessage { message, retain } = event {
+                    manager
+                        .discovery_handle_event(message, retain)
+                        .await
+                        .unwrap();
+                }
+                let devices = manager.read().await;
+                if let Some(device) = devices.get_device(&device_ref) {
+                    if device.state == HomieDeviceStatus::Ready && device.description.is_some() {
+                        return;
+                    }
+                }
+            }
+        })
+        .await
+        .expect("controller did not discover the bridge device");
+
+        let devices = manager.read().await;
+        let device = devices.get_device(&device_ref).unwrap();
+        assert_eq!(
+            device.description.as_ref().unwrap().name.as_deref(),
+            Some("Test Bridge")
+        );
+    }
+}
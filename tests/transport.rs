@@ -0,0 +1,57 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hc_homie5::client::{
+        run_homie_client_with_transport, HomieClientEvent, HomieClientOptions, MqttMessage,
+        TransportEvent,
+    };
+    use hc_homie5::controller::HomieDiscovery;
+    use hc_homie5::test_support::{RecordingTransport, ScriptedEventLoop};
+    use homie5::client::QoS;
+    use homie5::{Homie5Message, HomieDomain};
+
+    #[tokio::test]
+    async fn test_custom_transport_drives_client_loop() {
+        let transport = RecordingTransport::default();
+        let (script, eventloop) = ScriptedEventLoop::new();
+        let (handle, client, mut events) = run_homie_client_with_transport(
+            transport.clone(),
+            eventloop,
+            HomieClientOptions::new(16),
+        )
+        .unwrap();
+
+        script.send(Ok(TransportEvent::Connected)).unwrap();
+        script
+            .send(Ok(TransportEvent::Message(MqttMessage {
+                topic: "homie/5/dev-1/$state".to_string(),
+                payload: "ready".into(),
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            })))
+            .unwrap();
+
+        assert!(matches!(
+            events.recv().await,
+            Some(HomieClientEvent::Connect { endpoint: None })
+        ));
+        assert!(matches!(
+            events.recv().await,
+            Some(HomieClientEvent::HomieMessage {
+                message: Homie5Message::DeviceState { .. },
+                ..
+            })
+        ));
+
+        let discovery = HomieDiscovery::new(client);
+        discovery.discover(&HomieDomain::Default).await.unwrap();
+        assert!(transport
+            .subscribe_packets()
+            .concat()
+            .contains(&"homie/5/+/$state".to_string()));
+
+        handle.flush(Duration::from_millis(100)).await.unwrap();
+        handle.stop().await.unwrap();
+    }
+}